@@ -0,0 +1,109 @@
+use bevy::prelude::*;
+
+use crate::common::{
+    logic::{neo_gameboard::Gameboard, PlayerTeam},
+    network::protocol::{BoardDelta, BoardSnapshot},
+};
+
+// What the player remembers of the board. The server only sends tiles the
+// player can see, so anything else on the client's Gameboard is whatever it
+// looked like the last time they saw it
+#[derive(Debug, Default, Resource)]
+pub struct FogMemory {
+    height: usize,
+    last_seen: Vec<Option<u32>>,
+}
+
+impl FogMemory {
+    pub fn new(width: u32, height: u32) -> Self {
+        return Self {
+            height: height as usize,
+            last_seen: vec![None; (width * height) as usize],
+        };
+    }
+
+    fn index(&self, pos: Vec2) -> Option<usize> {
+        if pos.x < 0f32 || pos.y < 0f32 || pos.y as usize >= self.height {
+            return None;
+        }
+        let index = pos.x as usize * self.height + pos.y as usize;
+        if index >= self.last_seen.len() {
+            return None;
+        }
+        return Some(index);
+    }
+
+    // The last turn the tile was visible on, None if it's never been seen
+    pub fn last_seen(&self, pos: Vec2) -> Option<u32> {
+        return self.last_seen[self.index(pos)?];
+    }
+
+    pub fn is_explored(&self, pos: Vec2) -> bool {
+        return self.last_seen(pos).is_some();
+    }
+
+    fn see(&mut self, pos: Vec2, turn: u32) {
+        if let Some(index) = self.index(pos) {
+            self.last_seen[index] = Some(turn);
+        }
+    }
+}
+
+// Builds the board a player starts from. Tiles that weren't sent stay
+// unexplored
+pub fn board_from_snapshot(snapshot: &BoardSnapshot, team: &PlayerTeam) -> (Gameboard, FogMemory) {
+    let mut gameboard = Gameboard::unexplored(snapshot.width, snapshot.height);
+    let mut memory = FogMemory::new(snapshot.width, snapshot.height);
+
+    for tile in &snapshot.tiles {
+        gameboard.set_tile(tile.clone());
+    }
+    mark_visible(
+        &mut gameboard,
+        &mut memory,
+        &snapshot.visible,
+        team,
+        snapshot.turn,
+    );
+
+    return (gameboard, memory);
+}
+
+pub fn apply_delta(
+    gameboard: &mut Gameboard,
+    memory: &mut FogMemory,
+    delta: &BoardDelta,
+    team: &PlayerTeam,
+    turn: u32,
+) {
+    // Everything starts out as remembered, and the delta says what's still in
+    // view
+    let (width, height) = (gameboard.x() as usize, gameboard.y() as usize);
+    for x in 0..width {
+        for y in 0..height {
+            if let Some(tile) = gameboard.tile_mut(x, y) {
+                tile.set_visible_for(Vec::new());
+            }
+        }
+    }
+
+    for tile in &delta.tiles {
+        gameboard.set_tile(tile.clone());
+    }
+    mark_visible(gameboard, memory, &delta.visible, team, turn);
+}
+
+fn mark_visible(
+    gameboard: &mut Gameboard,
+    memory: &mut FogMemory,
+    visible: &[Vec2],
+    team: &PlayerTeam,
+    turn: u32,
+) {
+    for pos in visible {
+        if let Some(tile) = gameboard.tile_mut(pos.x as usize, pos.y as usize) {
+            tile.set_visible_for(vec![team.0.clone()]);
+            memory.see(*pos, turn);
+        }
+    }
+}
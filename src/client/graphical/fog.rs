@@ -0,0 +1,151 @@
+use bevy::{
+    prelude::*,
+    render::render_resource::{Extent3d, TextureDimension, TextureFormat},
+};
+use bevy_fast_tilemap::Map;
+
+use crate::{
+    client::{fog::FogMemory, LocalPlayer, Spectating},
+    common::logic::{neo_gameboard::Gameboard, Unit},
+};
+
+// Drawn over the board, but under units and everything the player interacts with
+const FOG_Z: f32 = 8f32;
+
+const UNEXPLORED: [u8; 4] = [0, 0, 0, 255];
+const REMEMBERED: [u8; 4] = [0, 0, 0, 140];
+const VISIBLE: [u8; 4] = [0, 0, 0, 0];
+
+// One pixel per tile, stretched over the whole board
+#[derive(Component)]
+pub struct FogOverlay {
+    width: u32,
+    height: u32,
+}
+
+// Without a local player (hotseat singleplayer) everyone shares the screen, so
+// there's no fog at all. Spectators see everything too
+pub fn update_fog_overlay(
+    mut commands: Commands,
+    mut images: ResMut<Assets<Image>>,
+    local_player: Option<Res<LocalPlayer>>,
+    spectating: Option<Res<Spectating>>,
+    memory: Option<Res<FogMemory>>,
+    gameboard_q: Query<Ref<Gameboard>>,
+    map_q: Query<&Map>,
+    mut overlay_q: Query<(Entity, &FogOverlay, &Handle<Image>, &mut Visibility)>,
+) {
+    let (Ok(gameboard), Ok(map)) = (gameboard_q.get_single(), map_q.get_single()) else {
+        return;
+    };
+    let Some(local_player) = local_player.filter(|_| spectating.is_none()) else {
+        overlay_q
+            .iter_mut()
+            .for_each(|(_, _, _, mut visibility)| *visibility = Visibility::Hidden);
+        return;
+    };
+
+    let (width, height) = (gameboard.x(), gameboard.y());
+    let existing = overlay_q
+        .iter_mut()
+        .find(|(_, overlay, _, _)| overlay.width == width && overlay.height == height);
+    let memory_changed = memory.as_ref().map_or(false, |m| m.is_changed());
+    if existing.is_some() && !gameboard.is_changed() && !memory_changed {
+        return;
+    }
+
+    // The map's projection decides which way is up, so work out which corner
+    // of the image tile (0, 0) ends up in
+    let origin = map.map_to_world(Vec2::ZERO);
+    let far_corner = map.map_to_world(Vec2::new(width as f32, height as f32));
+    let flip_x = far_corner.x < origin.x;
+    let flip_y = far_corner.y > origin.y;
+
+    let mut data = vec![0u8; (width * height * 4) as usize];
+    for tile in gameboard.tiles() {
+        let (x, y) = tile.pos_usize();
+        let colour = if tile.is_visible_to(&local_player.team) {
+            VISIBLE
+        } else if memory.as_ref().map_or(false, |m| m.is_explored(tile.pos())) {
+            REMEMBERED
+        } else {
+            UNEXPLORED
+        };
+
+        let column = if flip_x { width as usize - 1 - x } else { x };
+        let row = if flip_y { height as usize - 1 - y } else { y };
+        let index = (row * width as usize + column) * 4;
+        data[index..index + 4].copy_from_slice(&colour);
+    }
+
+    if let Some((_, _, handle, mut visibility)) = existing {
+        if let Some(image) = images.get_mut(handle) {
+            image.data = data;
+        }
+        *visibility = Visibility::Inherited;
+        return;
+    }
+
+    // Either there's no overlay yet, or the board changed size
+    overlay_q
+        .iter()
+        .for_each(|(e, _, _, _)| commands.entity(e).despawn_recursive());
+
+    let image = Image::new(
+        Extent3d {
+            width,
+            height,
+            depth_or_array_layers: 1,
+        },
+        TextureDimension::D2,
+        data,
+        TextureFormat::Rgba8UnormSrgb,
+    );
+    let centre = (origin + far_corner) / 2f32;
+    commands
+        .spawn(SpriteBundle {
+            sprite: Sprite {
+                custom_size: Some((far_corner - origin).abs()),
+                ..default()
+            },
+            texture: images.add(image),
+            transform: Transform::from_translation(centre.extend(FOG_Z)),
+            ..default()
+        })
+        .insert(FogOverlay { width, height })
+        .insert(Name::new("Fog"));
+}
+
+// Enemy units outside the local player's vision. The server already leaves
+// most of these out, this catches anything the client has that it shouldn't
+// show
+pub fn hide_unseen_units(
+    local_player: Option<Res<LocalPlayer>>,
+    spectating: Option<Res<Spectating>>,
+    gameboard_q: Query<&Gameboard>,
+    mut units: Query<(&Unit, &mut Visibility)>,
+) {
+    let Ok(gameboard) = gameboard_q.get_single() else {
+        return;
+    };
+
+    for (unit, mut visibility) in units.iter_mut() {
+        let visible = match local_player.as_ref() {
+            _ if spectating.is_some() => true,
+            None => true,
+            Some(player) if player.team == unit.owner => true,
+            Some(player) => gameboard
+                .tile(unit.pos.x as usize, unit.pos.y as usize)
+                .map_or(false, |t| t.is_visible_to(&player.team)),
+        };
+
+        let wanted = if visible {
+            Visibility::Inherited
+        } else {
+            Visibility::Hidden
+        };
+        if *visibility != wanted {
+            *visibility = wanted;
+        }
+    }
+}
@@ -4,15 +4,31 @@ use bevy::input::mouse::{MouseMotion, MouseWheel};
 use bevy::prelude::*;
 use bevy_fast_tilemap::Map;
 
-use crate::common::logic::neo_gameboard::Gameboard;
+use crate::client::LocalPlayer;
+use crate::common::logic::{
+    recruitment::RecruitOrder, structures::StructureKind, units::UnitRegistry, Unit, UnitAction,
+    UnitActions,
+};
 
 use super::GameCamera;
 
 pub struct TurnCompletedEvent;
 
-#[derive(Debug, Component, FromReflect, Reflect)]
+#[derive(Debug, FromReflect, Reflect, Resource)]
 pub struct SelectedUnit(pub Vec2);
 
+// The grid position under the cursor, if it's over the window
+#[derive(Debug, Default, Resource)]
+pub struct HoveredTile(pub Option<Vec2>);
+
+// Units to ask for at the nest when the turn ends, and which recruitable type
+// Q will queue next
+#[derive(Debug, Default, Resource)]
+pub struct PlannedRecruits {
+    pub orders: Vec<RecruitOrder>,
+    pub selected: usize,
+}
+
 #[derive(Debug)]
 pub struct ZoomEvent {
     zoom: f32,
@@ -56,11 +72,19 @@ pub fn scroll_events(
     }
 }
 
-pub fn keyboard_input(keys: Res<Input<KeyCode>>, mut turn_evw: EventWriter<TurnCompletedEvent>) {
+pub fn keyboard_input(
+    mut commands: Commands,
+    keys: Res<Input<KeyCode>>,
+    mut turn_evw: EventWriter<TurnCompletedEvent>,
+) {
     if keys.just_pressed(KeyCode::Return) {
         turn_evw.send(TurnCompletedEvent);
         info!("Ending turn")
     }
+
+    if keys.just_pressed(KeyCode::Escape) {
+        commands.remove_resource::<SelectedUnit>();
+    }
 }
 
 pub fn mouse_pan_events(
@@ -97,21 +121,190 @@ pub fn mouse_click_events(
     }
 }
 
+pub fn track_hovered_tile(
+    windows: Query<&Window>,
+    camera_q: Query<(&Camera, &GlobalTransform), With<GameCamera>>,
+    map: Query<&Map>,
+    mut hovered: ResMut<HoveredTile>,
+) {
+    let (Ok(window), Ok((camera, camera_transform)), Ok(map)) = (
+        windows.get_single(),
+        camera_q.get_single(),
+        map.get_single(),
+    ) else {
+        return;
+    };
+
+    let map_pos = window
+        .cursor_position()
+        .and_then(|cursor| camera.viewport_to_world(camera_transform, cursor))
+        .map(|ray| world_to_grid(map, ray.origin.truncate()));
+    if hovered.0 != map_pos {
+        hovered.0 = map_pos;
+    }
+}
+
+fn world_to_grid(map: &Map, world_pos: Vec2) -> Vec2 {
+    // This is a bit messy, because the tilemap consider's the tile's "position" as it's top left corner
+    return map
+        .world_to_map(world_pos.add(Vec2::new(8f32, -8f32)))
+        .round();
+}
+
 pub fn select_unit(
     mut commands: Commands,
+    keys: Res<Input<KeyCode>>,
     mut click_evr: EventReader<GridPosClickEvent>,
+    selected: Option<Res<SelectedUnit>>,
+    local_player: Option<Res<LocalPlayer>>,
     map: Query<&Map>,
-    gameboard: Query<&Gameboard>,
+    units: Query<(Entity, &Unit)>,
 ) {
     for click_event in click_evr.iter() {
-        let map = map.single();
-        // This is a bit messy, because the tilemap consider's the tile's "position" as it's top left corner
-        let click_pos = click_event.pos.add(Vec2::new(8f32, -8f32));
-        let map_pos = map.world_to_map(click_pos).round();
-        let world_pos = map.map_to_world(map_pos).add(Vec2::new(-8f32, 8f32));
+        let map_pos = world_to_grid(map.single(), click_event.pos);
+
+        let selected_unit = selected
+            .as_ref()
+            .and_then(|s| units.iter().find(|(_, u)| u.pos == s.0));
+        let clicked_unit = units.iter().find(|(_, u)| u.pos == map_pos);
+
+        // Without a local player (singleplayer) every unit can be controlled
+        let controllable = |unit: &Unit| {
+            local_player
+                .as_ref()
+                .map_or(true, |player| player.team == unit.owner)
+        };
+
+        // Hold 1, 2 or 3 while clicking a tile to build on it
+        let build_kind = if keys.pressed(KeyCode::Key1) {
+            Some(StructureKind::Wall)
+        } else if keys.pressed(KeyCode::Key2) {
+            Some(StructureKind::Watchtower)
+        } else if keys.pressed(KeyCode::Key3) {
+            Some(StructureKind::Outpost)
+        } else {
+            None
+        };
+
+        let action_type = match (selected_unit, clicked_unit) {
+            (Some(_), _) if build_kind.is_some() => UnitActions::Build(build_kind.unwrap()),
+            (Some((_, unit)), Some((_, target))) if target.owner != unit.owner => {
+                UnitActions::Attack
+            }
+            // Shift-click a friendly unit to heal it rather than select it
+            (Some((_, unit)), Some(_)) if unit.heal.is_some() && keys.pressed(KeyCode::LShift) => {
+                UnitActions::Heal
+            }
+            (_, Some((_, target))) if controllable(target) => {
+                commands.insert_resource(SelectedUnit(target.pos));
+                continue;
+            }
+            (Some(_), _) => UnitActions::Move,
+            (None, _) => continue,
+        };
+
+        // Only reachable through the arms above that have a selected unit
+        let (entity, unit) = selected_unit.unwrap();
+        commands.entity(entity).insert(UnitAction {
+            action_type,
+            turn_stage: unit.turn_execute_stage.clone(),
+            curr_pos: unit.pos,
+            action_pos: map_pos,
+        });
+        commands.remove_resource::<SelectedUnit>();
     }
 }
 
+// Press U with a unit selected to promote it. Pressing it again cycles through
+// everything the unit can become
+pub fn plan_upgrade(
+    mut commands: Commands,
+    keys: Res<Input<KeyCode>>,
+    selected: Option<Res<SelectedUnit>>,
+    registry: Res<UnitRegistry>,
+    units: Query<(Entity, &Unit, Option<&UnitAction>)>,
+) {
+    if !keys.just_pressed(KeyCode::U) {
+        return;
+    }
+    let Some(selected) = selected else {
+        return;
+    };
+    let Some((entity, unit, planned)) = units.iter().find(|(_, u, _)| u.pos == selected.0) else {
+        return;
+    };
+
+    let upgrades = registry.upgrades_for(&unit.id);
+    if upgrades.is_empty() {
+        info!("{} has no upgrades", unit.id);
+        return;
+    }
+
+    let next = match planned.map(|action| &action.action_type) {
+        Some(UnitActions::Upgrade(current)) => upgrades
+            .iter()
+            .position(|(id, _)| *id == current)
+            .map_or(0, |i| (i + 1) % upgrades.len()),
+        _ => 0,
+    };
+    let (id, definition) = upgrades[next];
+
+    info!(
+        "Upgrading {} to {} for {:?}",
+        unit.id, definition.name, definition.upgrade_cost
+    );
+    commands.entity(entity).insert(UnitAction {
+        action_type: UnitActions::Upgrade(id.clone()),
+        turn_stage: unit.turn_execute_stage.clone(),
+        curr_pos: unit.pos,
+        action_pos: unit.pos,
+    });
+}
+
+// Tab picks which type of unit to recruit, Q adds one to the order. Without a
+// local player (hotseat) it's recruited for whoever owns the selected unit
+pub fn plan_recruit(
+    keys: Res<Input<KeyCode>>,
+    selected: Option<Res<SelectedUnit>>,
+    local_player: Option<Res<LocalPlayer>>,
+    registry: Res<UnitRegistry>,
+    mut planned: ResMut<PlannedRecruits>,
+    units: Query<&Unit>,
+) {
+    let recruitable = registry.recruitable();
+    if recruitable.is_empty() {
+        return;
+    }
+
+    if keys.just_pressed(KeyCode::Tab) {
+        planned.selected = (planned.selected + 1) % recruitable.len();
+        let (id, definition) = recruitable[planned.selected];
+        info!(
+            "Recruiting {} ({}) for {:?}",
+            definition.name, id, definition.recruit_cost
+        );
+    }
+
+    if !keys.just_pressed(KeyCode::Q) {
+        return;
+    }
+    let owner = match local_player {
+        Some(player) => Some(player.team.clone()),
+        None => selected.and_then(|s| units.iter().find(|u| u.pos == s.0).map(|u| u.owner.clone())),
+    };
+    let Some(owner) = owner else {
+        info!("Select a unit to recruit for its team");
+        return;
+    };
+
+    let (id, _) = recruitable[planned.selected % recruitable.len()];
+    info!("Queued {} for {:?}", id, owner.0);
+    planned.orders.push(RecruitOrder {
+        owner,
+        unit: id.clone(),
+    });
+}
+
 pub fn zoom_camera(
     mut zoom_evr: EventReader<ZoomEvent>,
     mut cam: Query<(&mut Transform, With<Camera2d>, With<GameCamera>)>,
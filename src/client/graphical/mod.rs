@@ -1,21 +1,29 @@
+pub mod fog;
 pub mod inputs;
 
 use std::ops::Add;
 
 use bevy::{math::Vec3Swizzles, prelude::*, sprite::MaterialMesh2dBundle};
-use bevy_fast_tilemap::{Map, MapBundle, MeshManagedByMap};
+use bevy_fast_tilemap::{Map, MapBundle, MapReadyEvent, MeshManagedByMap};
 
 use crate::common::{
     config::Config,
-    logic::{neo_gameboard::Gameboard, units::UnitID, *},
+    logic::{
+        neo_gameboard::Gameboard,
+        structures::StructureKind,
+        units::{UnitID, UnitRegistry},
+        *,
+    },
 };
 
+use self::fog::{hide_unseen_units, update_fog_overlay};
 use self::inputs::{
-    keyboard_input, mouse_click_events, mouse_pan_events, scroll_camera, scroll_events,
-    select_unit, zoom_camera, GridPosClickEvent, PanEvent, TurnCompletedEvent, ZoomEvent,
+    keyboard_input, mouse_click_events, mouse_pan_events, plan_recruit, plan_upgrade,
+    scroll_camera, scroll_events, select_unit, track_hovered_tile, zoom_camera, GridPosClickEvent,
+    HoveredTile, PanEvent, PlannedRecruits, SelectedUnit, TurnCompletedEvent, ZoomEvent,
 };
 
-use super::{ClientState, Spritesheet};
+use super::{ClientState, Spritesheet, CHARACTER_SPRITES};
 
 pub struct GraphicalPlugin;
 
@@ -26,20 +34,37 @@ impl Plugin for GraphicalPlugin {
             .add_event::<PanEvent>()
             .add_event::<TurnCompletedEvent>()
             .add_event::<ZoomEvent>()
+            .init_resource::<HoveredTile>()
+            .init_resource::<PlannedRecruits>()
             .add_startup_system(spawn_gameboard)
-            .add_system(test_spawn_unit.in_schedule(OnEnter(ClientState::Game)))
-            .add_system(render.in_set(OnUpdate(ClientState::Game)))
+            .add_system(fit_map_to_gameboard.run_if(showing_board))
+            .add_system(paint_gameboard.run_if(showing_board))
+            .add_system(render.run_if(showing_board))
+            .add_system(update_fog_overlay.run_if(showing_board))
+            .add_system(hide_unseen_units.run_if(showing_board))
             .add_system(show_movable_tiles.in_set(OnUpdate(ClientState::Game)))
-            .add_system(scroll_events.in_set(OnUpdate(ClientState::Game)))
+            .add_system(render_unit_actions.run_if(showing_board))
+            .add_system(render_move_path.in_set(OnUpdate(ClientState::Game)))
+            .add_system(render_structures.run_if(showing_board))
+            .add_system(render_features.run_if(showing_board))
+            .add_system(scroll_events.run_if(showing_board))
             .add_system(select_unit.in_set(OnUpdate(ClientState::Game)))
-            .add_system(zoom_camera.in_set(OnUpdate(ClientState::Game)))
+            .add_system(plan_upgrade.in_set(OnUpdate(ClientState::Game)))
+            .add_system(plan_recruit.in_set(OnUpdate(ClientState::Game)))
+            .add_system(zoom_camera.run_if(showing_board))
             .add_system(mouse_click_events.in_set(OnUpdate(ClientState::Game)))
-            .add_system(mouse_pan_events.in_set(OnUpdate(ClientState::Game)))
-            .add_system(scroll_camera.in_set(OnUpdate(ClientState::Game)))
+            .add_system(track_hovered_tile.in_set(OnUpdate(ClientState::Game)))
+            .add_system(mouse_pan_events.run_if(showing_board))
+            .add_system(scroll_camera.run_if(showing_board))
             .add_system(keyboard_input.in_set(OnUpdate(ClientState::Game)));
     }
 }
 
+// Replays draw the board the same way, but can't be played
+fn showing_board(state: Res<State<ClientState>>) -> bool {
+    return matches!(state.0, ClientState::Game | ClientState::Replay);
+}
+
 #[derive(Component)]
 pub struct IconTagTemp;
 
@@ -48,6 +73,7 @@ fn show_movable_tiles(
     mut meshes: ResMut<Assets<Mesh>>,
     mut materials: ResMut<Assets<ColorMaterial>>,
     units: Query<&Unit>,
+    selected: Option<Res<SelectedUnit>>,
     prev_icons: Query<Entity, With<IconTagTemp>>,
     gameboard: Query<&Gameboard>,
     map: Query<&Map>,
@@ -57,7 +83,11 @@ fn show_movable_tiles(
 
         prev_icons.iter().for_each(|e| commands.entity(e).despawn());
 
-        for unit in units.iter() {
+        let Some(selected) = selected.as_ref() else {
+            continue;
+        };
+
+        for unit in units.iter().filter(|u| u.pos == selected.0) {
             for tile in unit.calculate_traversible_tiles(gameboard, unit.movement.0 as f32) {
                 let world_pos = map.map_to_world(tile).add(Vec2::new(-8f32, 8f32));
                 commands
@@ -100,6 +130,42 @@ pub enum Icons {
     Selector,
 }
 
+impl Icons {
+    fn atlas_index(&self) -> usize {
+        return match self {
+            Icons::Selector => 0,
+            Icons::Circle => 1,
+            Icons::Cross => 2,
+        };
+    }
+}
+
+// Sprites in tile_icons_atlas.png
+enum TileIcons {
+    Magic,
+    Science,
+    BlueFlag,
+    RedFlag,
+}
+
+impl TileIcons {
+    fn from_archetype(archetype: &Archetypes) -> TileIcons {
+        return match archetype {
+            Archetypes::Science => TileIcons::Science,
+            _ => TileIcons::Magic,
+        };
+    }
+
+    fn atlas_index(&self) -> usize {
+        return match self {
+            TileIcons::Magic => 0,
+            TileIcons::Science => 1,
+            TileIcons::BlueFlag => 2,
+            TileIcons::RedFlag => 3,
+        };
+    }
+}
+
 #[derive(Component)]
 struct RenderedTerrain;
 
@@ -112,6 +178,12 @@ struct RenderedUnit;
 #[derive(Component)]
 struct RenderedUnitAction;
 
+#[derive(Component)]
+struct RenderedStructure;
+
+#[derive(Component)]
+struct RenderedMovePath;
+
 #[derive(Component)]
 pub struct RenderedIcon;
 
@@ -120,16 +192,26 @@ fn spawn_gameboard(
     asset_server: Res<AssetServer>,
     config: Res<Config>,
     mut images: ResMut<Assets<Image>>,
+) {
+    let size = UVec2 {
+        x: config.gameboard_config.width,
+        y: config.gameboard_config.height,
+    };
+    spawn_map(&mut commands, &asset_server, &mut images, size);
+}
+
+fn spawn_map(
+    commands: &mut Commands,
+    asset_server: &AssetServer,
+    images: &mut ResMut<Assets<Image>>,
+    size: UVec2,
 ) {
     let map = Map::builder(
-        UVec2 {
-            x: config.gameboard_config.width,
-            y: config.gameboard_config.height,
-        },
+        size,
         asset_server.load("sprites/tilemap_atlas_new.png"),
         Vec2 { x: 16f32, y: 16f32 },
     )
-    .build(&mut images);
+    .build(images);
 
     commands
         .spawn(MapBundle::new(map))
@@ -137,11 +219,87 @@ fn spawn_gameboard(
         .insert(Name::new("Gameboard"));
 }
 
+// The map starts out the size in config.toml, but a board from the server or a
+// save can be any size. It's rebuilt to match, and painted once it's ready
+fn fit_map_to_gameboard(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    mut images: ResMut<Assets<Image>>,
+    gameboard_q: Query<Ref<Gameboard>>,
+    map_q: Query<(Entity, &Map)>,
+) {
+    let (Ok(gameboard), Ok((entity, map))) = (gameboard_q.get_single(), map_q.get_single()) else {
+        return;
+    };
+    let size = UVec2 {
+        x: gameboard.x(),
+        y: gameboard.y(),
+    };
+    if !gameboard.is_added() || map.map_size() == size {
+        return;
+    }
+
+    commands.entity(entity).despawn_recursive();
+    spawn_map(&mut commands, &asset_server, &mut images, size);
+}
+
+fn paint_gameboard(
+    mut images: ResMut<Assets<Image>>,
+    mut map_ready_evr: EventReader<MapReadyEvent>,
+    mut map_ready: Local<bool>,
+    mut painted: Local<Vec<Option<u16>>>,
+    gameboard_q: Query<Ref<Gameboard>>,
+    map_q: Query<&Map>,
+) {
+    let map_just_ready = map_ready_evr.iter().count() > 0;
+    if map_just_ready {
+        *map_ready = true;
+    }
+
+    let (Ok(gameboard), Ok(map)) = (gameboard_q.get_single(), map_q.get_single()) else {
+        return;
+    };
+    if !*map_ready || !(gameboard.is_changed() || map_just_ready) {
+        return;
+    }
+
+    // Start from scratch whenever a new board is spawned, or once the atlas
+    // has loaded
+    let height = gameboard.y() as usize;
+    if gameboard.is_added() || map_just_ready {
+        *painted = vec![None; gameboard.x() as usize * height];
+    }
+
+    // Otherwise only repaint tiles that look different (i.e. ones the fog
+    // just revealed)
+    if let Ok(mut m) = map.get_mut(&mut *images) {
+        for tile in gameboard.tiles() {
+            let (x, y) = tile.pos_usize();
+            let Some(painted_index) = painted.get_mut(x * height + y) else {
+                continue;
+            };
+            let index = tile.terrain().to_atlas_index(tile.variant());
+            if *painted_index == Some(index) {
+                continue;
+            }
+
+            m.set(x as u32, y as u32, index);
+            *painted_index = Some(index);
+        }
+    }
+}
+
 fn render(
     mut commands: Commands,
     spritesheet: Res<Spritesheet>,
+    registry: Res<UnitRegistry>,
     unrendered_units: Query<(Entity, &Unit, Without<RenderedUnit>)>,
-    mut rendered_units: Query<(&Unit, &mut Transform, With<RenderedUnit>)>,
+    mut rendered_units: Query<(
+        &Unit,
+        &mut Transform,
+        &mut TextureAtlasSprite,
+        With<RenderedUnit>,
+    )>,
     map_q: Query<&Map>,
 ) {
     let map = map_q.single();
@@ -150,7 +308,7 @@ fn render(
 
         let pos = map.map_to_world(unit.pos).add(Vec2::splat(8f32));
         let bundle = SpriteSheetBundle {
-            sprite: TextureAtlasSprite::new(texture_index_from_unit_id(&unit.id)),
+            sprite: TextureAtlasSprite::new(texture_index_from_unit_id(&registry, &unit.id)),
             texture_atlas: spritesheet.characters.clone(), // To optimising Aurora, it's a handle!
             transform: Transform {
                 translation: Vec3 {
@@ -166,22 +324,266 @@ fn render(
         commands.entity(entity).insert(bundle).insert(RenderedUnit);
     }
 
-    for (unit, mut transform, _) in &mut rendered_units {
+    for (unit, mut transform, mut sprite, _) in &mut rendered_units {
         // Align to grid
         let pos = map.map_to_world(unit.pos).add(Vec2::new(8f32, -8f32));
         if transform.translation.xy() != pos {
             transform.translation = Vec3::new(pos.x, pos.y, 10f32);
         }
 
-        // TODO: Set sprite
+        // Units change type when they're upgraded
+        let index = texture_index_from_unit_id(&registry, &unit.id);
+        if sprite.index != index {
+            sprite.index = index;
+        }
     }
 }
 
-fn texture_index_from_unit_id(uid: &UnitID) -> usize {
-    return match uid {
-        UnitID::ScienceGenericTest => 0,
-        UnitID::MagicGenericTest => 1,
+fn render_unit_actions(
+    mut commands: Commands,
+    spritesheet: Res<Spritesheet>,
+    selected: Option<Res<SelectedUnit>>,
+    actions: Query<&UnitAction>,
+    prev_icons: Query<Entity, With<RenderedUnitAction>>,
+    map_q: Query<&Map>,
+) {
+    let map = map_q.single();
+    prev_icons
+        .iter()
+        .for_each(|e| commands.entity(e).despawn_recursive());
+
+    let mut icons = actions
+        .iter()
+        .map(|action| {
+            let icon = match action.action_type {
+                UnitActions::Attack => Icons::Cross,
+                UnitActions::Build(_) => Icons::Selector,
+                _ => Icons::Circle,
+            };
+            return (icon, action.action_pos);
+        })
+        .collect::<Vec<(Icons, Vec2)>>();
+    if let Some(selected) = selected {
+        icons.push((Icons::Selector, selected.0));
+    }
+
+    for (icon, pos) in icons {
+        let pos = map.map_to_world(pos).add(Vec2::new(8f32, -8f32));
+        commands
+            .spawn(SpriteSheetBundle {
+                sprite: TextureAtlasSprite::new(icon.atlas_index()),
+                texture_atlas: spritesheet.selector_icons.clone(),
+                transform: Transform {
+                    translation: Vec3::new(pos.x, pos.y, 20f32),
+                    scale: Vec3::splat(0.5),
+                    ..default()
+                },
+                ..default()
+            })
+            .insert(RenderedUnitAction);
+    }
+}
+
+// Draws arrows along the path the selected unit would take to the hovered tile
+fn render_move_path(
+    mut commands: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<ColorMaterial>>,
+    selected: Option<Res<SelectedUnit>>,
+    hovered: Res<HoveredTile>,
+    units: Query<&Unit>,
+    gameboard_q: Query<&Gameboard>,
+    prev_arrows: Query<Entity, With<RenderedMovePath>>,
+    map_q: Query<&Map>,
+) {
+    prev_arrows
+        .iter()
+        .for_each(|e| commands.entity(e).despawn_recursive());
+
+    let (Some(selected), Some(target), Ok(gameboard), Ok(map)) = (
+        selected,
+        hovered.0,
+        gameboard_q.get_single(),
+        map_q.get_single(),
+    ) else {
+        return;
+    };
+    let Some(unit) = units.iter().find(|u| u.pos == selected.0) else {
+        return;
+    };
+    let Some(path) = unit.path_to(gameboard, target) else {
+        return;
+    };
+
+    let centre = |pos: Vec2| map.map_to_world(pos).add(Vec2::new(8f32, -8f32));
+    let material = materials.add(ColorMaterial::from(Color::rgba(1f32, 1f32, 1f32, 0.8)));
+
+    let mut from = centre(unit.pos);
+    for (i, step) in path.iter().enumerate() {
+        let to = centre(step.pos);
+        let delta = to - from;
+        let rotation = Quat::from_rotation_z(delta.y.atan2(delta.x));
+        let midpoint = from + delta / 2f32;
+
+        commands
+            .spawn(MaterialMesh2dBundle {
+                mesh: meshes
+                    .add(shape::Quad::new(Vec2::new(delta.length(), 2f32)).into())
+                    .into(),
+                material: material.clone(),
+                transform: Transform::from_translation(midpoint.extend(30f32))
+                    .with_rotation(rotation),
+                ..default()
+            })
+            .insert(RenderedMovePath);
+
+        // Only the last step gets an arrow head
+        if i == path.len() - 1 {
+            // The triangle points up by default, so turn it to face along the path
+            let head_rotation = rotation * Quat::from_rotation_z(-std::f32::consts::FRAC_PI_2);
+            commands
+                .spawn(MaterialMesh2dBundle {
+                    mesh: meshes
+                        .add(shape::RegularPolygon::new(4f32, 3).into())
+                        .into(),
+                    material: material.clone(),
+                    transform: Transform::from_translation(to.extend(30f32))
+                        .with_rotation(head_rotation),
+                    ..default()
+                })
+                .insert(RenderedMovePath);
+        }
+
+        from = to;
+    }
+}
+
+fn render_structures(
+    mut commands: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<ColorMaterial>>,
+    gameboard_q: Query<&Gameboard, Changed<Gameboard>>,
+    prev_structures: Query<Entity, With<RenderedStructure>>,
+    map_q: Query<&Map>,
+) {
+    let Ok(gameboard) = gameboard_q.get_single() else {
+        return;
+    };
+    let map = map_q.single();
+
+    prev_structures
+        .iter()
+        .for_each(|e| commands.entity(e).despawn_recursive());
+
+    // No sprites for these yet, so they're coloured squares for now
+    for tile in gameboard.tiles() {
+        let Some(structure) = tile.structure() else {
+            continue;
+        };
+
+        let mut colour = match structure.kind {
+            StructureKind::Wall => Color::GRAY,
+            StructureKind::Watchtower => Color::ORANGE,
+            StructureKind::Outpost => Color::TEAL,
+        };
+        if !structure.is_complete() {
+            colour.set_a(0.4);
+        }
+
+        let pos = map.map_to_world(tile.pos()).add(Vec2::new(8f32, -8f32));
+        commands
+            .spawn(MaterialMesh2dBundle {
+                mesh: meshes
+                    .add(shape::Quad::new(Vec2::splat(12f32)).into())
+                    .into(),
+                material: materials.add(ColorMaterial::from(colour)),
+                transform: Transform::from_translation(Vec3::new(pos.x, pos.y, 5f32)),
+                ..default()
+            })
+            .insert(RenderedStructure);
+    }
+}
+
+// Currency sites and nests, with a flag for whoever holds them
+fn render_features(
+    mut commands: Commands,
+    spritesheet: Res<Spritesheet>,
+    gameboard_q: Query<&Gameboard, Changed<Gameboard>>,
+    prev_features: Query<Entity, With<RenderedFeature>>,
+    map_q: Query<&Map>,
+) {
+    let Ok(gameboard) = gameboard_q.get_single() else {
+        return;
     };
+    let map = map_q.single();
+
+    prev_features
+        .iter()
+        .for_each(|e| commands.entity(e).despawn_recursive());
+
+    for tile in gameboard.tiles() {
+        let Some(feature) = tile.feature() else {
+            continue;
+        };
+
+        let (icon, flag) = match &feature.feature {
+            TileFeatures::CurrencySite(archetype) => (
+                Some(TileIcons::from_archetype(&archetype.0)),
+                feature.controller.as_ref(),
+            ),
+            TileFeatures::Nest(team) => (None, Some(team)),
+        };
+
+        let pos = map.map_to_world(tile.pos()).add(Vec2::new(8f32, -8f32));
+        if let Some(icon) = icon {
+            commands
+                .spawn(SpriteSheetBundle {
+                    sprite: TextureAtlasSprite::new(icon.atlas_index()),
+                    texture_atlas: spritesheet.tile_icons.clone(),
+                    transform: Transform {
+                        translation: Vec3::new(pos.x, pos.y, 6f32),
+                        scale: Vec3::splat(0.5),
+                        ..default()
+                    },
+                    ..default()
+                })
+                .insert(RenderedFeature);
+        }
+
+        if let Some(team) = flag {
+            // Only blue and red have flags of their own, the rest are tinted
+            let (icon, colour) = match team.0 {
+                TeamColour::Blue => (TileIcons::BlueFlag, Color::WHITE),
+                TeamColour::Red => (TileIcons::RedFlag, Color::WHITE),
+                TeamColour::Purple => (TileIcons::BlueFlag, Color::PURPLE),
+                TeamColour::Yellow => (TileIcons::RedFlag, Color::YELLOW),
+            };
+            let mut sprite = TextureAtlasSprite::new(icon.atlas_index());
+            sprite.color = colour;
+
+            commands
+                .spawn(SpriteSheetBundle {
+                    sprite,
+                    texture_atlas: spritesheet.tile_icons.clone(),
+                    transform: Transform {
+                        translation: Vec3::new(pos.x + 4f32, pos.y + 4f32, 7f32),
+                        scale: Vec3::splat(0.3),
+                        ..default()
+                    },
+                    ..default()
+                })
+                .insert(RenderedFeature);
+        }
+    }
+}
+
+fn texture_index_from_unit_id(registry: &UnitRegistry, uid: &UnitID) -> usize {
+    // Fall back to the first sprite rather than indexing off the end of the atlas
+    return registry
+        .get(uid)
+        .map(|definition| definition.sprite)
+        .filter(|sprite| *sprite < CHARACTER_SPRITES)
+        .unwrap_or(0);
 }
 
 #[derive(Component, Reflect)]
@@ -189,22 +591,3 @@ struct GameScalable;
 
 #[derive(Component, Reflect)]
 pub struct GameCamera;
-
-fn test_spawn_unit(mut commands: Commands) {
-    // TEMPORARILY spawn unit
-    // TODO: Remove this
-
-    commands
-        .spawn(Unit {
-            id: UnitID::ScienceGenericTest,
-            pos: Vec2::new(10f32, 10f32),
-            health: Health(10f32),
-            attack: Attack::default(),
-            defense: Defense::default(),
-            movement: Movement(5),
-            turn_execute_stage: TurnExecuteStage::default(),
-            archetype: Archetype::default(),
-            owner: PlayerTeam(TeamColour::Red),
-        })
-        .insert(Name::new("Unit"));
-}
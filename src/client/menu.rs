@@ -0,0 +1,110 @@
+use std::{
+    env, io,
+    net::SocketAddr,
+    process::{Child, Command},
+};
+
+use bevy::{app::AppExit, prelude::*};
+
+use crate::common::config::{Config, RunEnvironment, SERVER_FLAG};
+
+use super::{network::ConnectRequest, save::LoadQuicksave, ClientState};
+
+// A server that was just started gets about five seconds to start listening
+const HOST_CONNECT_ATTEMPTS: u32 = 20;
+
+// What the player picked on the main menu
+#[derive(Clone, Debug)]
+pub enum MainMenuChoice {
+    Singleplayer,
+    Host,
+    Join(String),
+    LoadGame,
+    Settings,
+    Quit,
+}
+
+// A dedicated server started by hosting, stopped again when the game exits
+#[derive(Resource)]
+pub struct HostedServer(Child);
+
+pub fn handle_main_menu_choices(
+    mut commands: Commands,
+    mut choice_evr: EventReader<MainMenuChoice>,
+    mut config: ResMut<Config>,
+    mut next_state: ResMut<NextState<ClientState>>,
+    mut exit_evw: EventWriter<AppExit>,
+    hosted_server: Option<Res<HostedServer>>,
+) {
+    for choice in choice_evr.iter() {
+        match choice {
+            MainMenuChoice::Singleplayer => {
+                config.env = RunEnvironment::Singleplayer;
+                next_state.set(ClientState::Game);
+            }
+            MainMenuChoice::LoadGame => {
+                config.env = RunEnvironment::Singleplayer;
+                commands.insert_resource(LoadQuicksave);
+                next_state.set(ClientState::Game);
+            }
+            // The server listens on the connection address in config.toml
+            MainMenuChoice::Host => {
+                let addr = config.connection_address;
+                if hosted_server.is_none() {
+                    match start_server() {
+                        Ok(server) => commands.insert_resource(HostedServer(server)),
+                        Err(err) => {
+                            error!("Unable to start a server: {}", err);
+                            continue;
+                        }
+                    }
+                }
+
+                config.env = RunEnvironment::Client;
+                commands.insert_resource(ConnectRequest::new(addr, HOST_CONNECT_ATTEMPTS));
+            }
+            MainMenuChoice::Join(address) => {
+                let Some(addr) = parse_address(address) else {
+                    continue;
+                };
+
+                config.env = RunEnvironment::Client;
+                config.connection_address = addr;
+                commands.insert_resource(ConnectRequest::new(addr, 1));
+            }
+            MainMenuChoice::Settings => next_state.set(ClientState::Settings),
+            MainMenuChoice::Quit => exit_evw.send(AppExit),
+        }
+    }
+}
+
+fn parse_address(address: &str) -> Option<SocketAddr> {
+    return match address.trim().parse::<SocketAddr>() {
+        Ok(addr) => Some(addr),
+        Err(err) => {
+            error!("Invalid connection address {}: {}", address, err);
+            None
+        }
+    };
+}
+
+fn start_server() -> io::Result<Child> {
+    let server = Command::new(env::current_exe()?).arg(SERVER_FLAG).spawn()?;
+    info!("Started a server (pid {})", server.id());
+    return Ok(server);
+}
+
+// Nothing else will stop the server once the window is gone
+pub fn stop_hosted_server(
+    mut exit_evr: EventReader<AppExit>,
+    hosted_server: Option<ResMut<HostedServer>>,
+) {
+    if exit_evr.iter().count() == 0 {
+        return;
+    }
+    if let Some(mut server) = hosted_server {
+        if let Err(err) = server.0.kill() {
+            warn!("Unable to stop the server: {}", err);
+        }
+    }
+}
@@ -1,11 +1,38 @@
 use bevy::prelude::*;
 use bevy_fast_tilemap::FastTileMapPlugin;
 
-use crate::common::logic::{neo_gameboard::spawn_gameboard, GameLogicPlugin};
+use crate::common::{
+    config::{Config, RunEnvironment},
+    logic::{
+        neo_gameboard::{spawn_gameboard, Gameboard},
+        recruitment::place_starting_armies,
+        replay::start_recording,
+        turn::{resolve_turns, PendingOrders, ResolveTurnEvent, TurnEvent, TurnResolvedEvent},
+        units::UnitRegistry,
+        victory::MatchState,
+        vision::update_vision,
+        GameLogicPlugin, PlayerTeam, TeamColour,
+    },
+};
 
-use self::{graphical::GraphicalPlugin, ui::UIPlugin};
+use self::{
+    graphical::{
+        inputs::{PlannedRecruits, TurnCompletedEvent},
+        GraphicalPlugin,
+    },
+    menu::{handle_main_menu_choices, stop_hosted_server, MainMenuChoice},
+    save::{load_quicksave_on_start, quickload, quicksave, LoadQuicksave},
+    settings::{apply_graphics_settings, handle_settings_choices, SettingsChoice, SettingsStatus},
+    ui::UIPlugin,
+};
 
+pub mod fog;
 pub mod graphical;
+pub mod menu;
+pub mod network;
+pub mod replay;
+pub mod save;
+pub mod settings;
 pub mod ui;
 
 pub struct ClientPlugin;
@@ -19,7 +46,15 @@ impl Plugin for ClientPlugin {
             .add_plugin(GameLogicPlugin)
             .add_plugin(GraphicalPlugin)
             .add_plugin(UIPlugin)
-            .add_startup_system(load_assets);
+            .add_event::<MainMenuChoice>()
+            .add_event::<SettingsChoice>()
+            .init_resource::<SettingsStatus>()
+            .add_startup_system(load_assets)
+            .add_system(handle_main_menu_choices.in_set(OnUpdate(ClientState::MainMenu)))
+            .add_system(handle_settings_choices.in_set(OnUpdate(ClientState::Settings)))
+            .add_system(apply_graphics_settings)
+            .add_system(stop_hosted_server.in_base_set(CoreSet::Last))
+            .add_system(announce_match_events.in_set(OnUpdate(ClientState::Game)));
     }
 }
 
@@ -27,7 +62,90 @@ pub struct SingleplayerPlugin;
 
 impl Plugin for SingleplayerPlugin {
     fn build(&self, app: &mut App) {
-        app.add_system(spawn_gameboard.in_set(OnUpdate(ClientState::Game)));
+        app.add_systems(
+            (
+                spawn_gameboard,
+                apply_system_buffers,
+                spawn_singleplayer_armies,
+                apply_system_buffers,
+                start_recording,
+            )
+                .chain()
+                .distributive_run_if(starting_new_match)
+                .in_schedule(OnEnter(ClientState::Game)),
+        )
+        .add_system(
+            load_quicksave_on_start
+                .run_if(resource_exists::<LoadQuicksave>())
+                .after(start_recording)
+                .in_schedule(OnEnter(ClientState::Game)),
+        )
+        .add_system(
+            end_turn
+                .run_if(playing_singleplayer)
+                .in_set(OnUpdate(ClientState::Game)),
+        )
+        .add_system(
+            quicksave
+                .run_if(playing_singleplayer)
+                .in_set(OnUpdate(ClientState::Game)),
+        )
+        .add_system(
+            quickload
+                .run_if(playing_singleplayer)
+                .in_set(OnUpdate(ClientState::Game)),
+        )
+        // Over the network the server works out what's visible instead
+        .add_system(
+            update_vision
+                .run_if(playing_singleplayer)
+                .after(resolve_turns),
+        );
+    }
+}
+
+// Both plugins are there until the main menu picks one
+fn playing_singleplayer(config: Res<Config>) -> bool {
+    return config.env == RunEnvironment::Singleplayer;
+}
+
+fn starting_new_match(config: Res<Config>, load: Option<Res<LoadQuicksave>>) -> bool {
+    return config.env == RunEnvironment::Singleplayer && load.is_none();
+}
+
+// Hotseat is always blue against red
+fn spawn_singleplayer_armies(
+    mut commands: Commands,
+    config: Res<Config>,
+    registry: Res<UnitRegistry>,
+    mut match_state: ResMut<MatchState>,
+    mut gameboard_q: Query<&mut Gameboard>,
+) {
+    let Ok(mut gameboard) = gameboard_q.get_single_mut() else {
+        error!("Unable to place armies without a gameboard");
+        return;
+    };
+
+    let teams = [PlayerTeam(TeamColour::Blue), PlayerTeam(TeamColour::Red)];
+    for unit in place_starting_armies(&mut gameboard, &registry, &teams) {
+        commands.spawn(unit).insert(Name::new("Unit"));
+    }
+    *match_state = MatchState::new(config.victory_config.clone(), &teams);
+}
+
+// There's nobody else to wait for, so resolve as soon as the player is done
+fn end_turn(
+    mut turn_evr: EventReader<TurnCompletedEvent>,
+    mut resolve_evw: EventWriter<ResolveTurnEvent>,
+    mut planned_recruits: ResMut<PlannedRecruits>,
+    mut pending_orders: ResMut<PendingOrders>,
+) {
+    if turn_evr.iter().count() > 0 {
+        pending_orders
+            .0
+            .recruits
+            .append(&mut planned_recruits.orders);
+        resolve_evw.send(ResolveTurnEvent);
     }
 }
 
@@ -35,9 +153,58 @@ impl Plugin for SingleplayerPlugin {
 pub enum ClientState {
     #[default]
     MainMenu,
+    Lobby,
     Game,
+    // Watching a recorded match
+    Replay,
+    Settings,
+}
+
+// The team this client is playing as
+#[derive(Resource)]
+pub struct LocalPlayer {
+    pub team: PlayerTeam,
 }
 
+// The local player has been knocked out, and is watching the rest of the match
+// with the fog lifted
+#[derive(Resource)]
+pub struct Spectating;
+
+fn announce_match_events(
+    mut commands: Commands,
+    mut resolved_evr: EventReader<TurnResolvedEvent>,
+    local_player: Option<Res<LocalPlayer>>,
+) {
+    let local_team = local_player.map(|p| p.team.clone());
+    for ev in resolved_evr.iter() {
+        for event in ev.0.events.iter() {
+            match event {
+                TurnEvent::Eliminated { owner } if Some(owner) == local_team.as_ref() => {
+                    info!("You have been knocked out, spectating the rest of the match");
+                    commands.insert_resource(Spectating);
+                }
+                TurnEvent::Eliminated { owner } => info!("{:?} has been knocked out", owner.0),
+                TurnEvent::MatchOver { winners } if winners.is_empty() => {
+                    info!("The match is over, nobody won")
+                }
+                TurnEvent::MatchOver { winners } => {
+                    let winners = winners
+                        .iter()
+                        .map(|team| format!("{:?}", team.0))
+                        .collect::<Vec<String>>()
+                        .join(" and ");
+                    info!("The match is over, won by {}", winners);
+                }
+                _ => {}
+            }
+        }
+    }
+}
+
+// How many sprites are in character_atlas.png
+pub const CHARACTER_SPRITES: usize = 2;
+
 #[derive(Resource, Default)]
 pub struct Spritesheet {
     pub characters: Handle<TextureAtlas>,
@@ -55,7 +222,7 @@ pub fn load_assets(
     let chatacter_texture_atlas = TextureAtlas::from_grid(
         character_img_handle,
         Vec2::new(19f32, 23f32),
-        2,
+        CHARACTER_SPRITES,
         1,
         Some(Vec2::new(1f32, 0f32)),
         None,
@@ -0,0 +1,307 @@
+use std::{
+    net::{SocketAddr, TcpStream},
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+
+use bevy::prelude::*;
+
+use crate::common::{
+    config::Config,
+    logic::{
+        economy::Economy, neo_gameboard::Gameboard, turn::TurnResolvedEvent, units::UnitID,
+        TurnCounter, Unit, UnitAction,
+    },
+    network::{
+        protocol::{ClientMessage, LobbyInfo, ServerMessage, PROTOCOL_VERSION},
+        Connection,
+    },
+};
+
+use super::{
+    fog::{apply_delta, board_from_snapshot, FogMemory},
+    graphical::inputs::{PlannedRecruits, TurnCompletedEvent},
+    ClientState, LocalPlayer,
+};
+
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+// How long to wait between attempts to connect
+const CONNECT_RETRY: Duration = Duration::from_millis(250);
+
+pub struct ClientNetworkPlugin;
+
+impl Plugin for ClientNetworkPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_system(connect_to_server.in_set(OnUpdate(ClientState::MainMenu)))
+            .add_system(receive_server_messages)
+            .add_system(lobby_input.in_set(OnUpdate(ClientState::Lobby)))
+            .add_system(
+                submit_orders
+                    .run_if(resource_exists::<ServerConnection>())
+                    .in_set(OnUpdate(ClientState::Game)),
+            );
+    }
+}
+
+#[derive(Resource)]
+pub struct ServerConnection(pub Connection);
+
+// The most recent lobby the server told us about
+#[derive(Resource)]
+pub struct ClientLobby(pub LobbyInfo);
+
+// Set by the main menu. A server that was only just started won't be
+// listening straight away, so this keeps trying for a bit. Each attempt runs
+// on its own thread so a slow connect doesn't freeze the menu
+#[derive(Resource)]
+pub struct ConnectRequest {
+    addr: SocketAddr,
+    attempts_left: u32,
+    retry: Timer,
+    attempt: Option<JoinHandle<Result<Connection, String>>>,
+}
+
+impl ConnectRequest {
+    pub fn new(addr: SocketAddr, attempts: u32) -> Self {
+        return Self {
+            addr,
+            attempts_left: attempts,
+            retry: Timer::new(CONNECT_RETRY, TimerMode::Repeating),
+            attempt: None,
+        };
+    }
+}
+
+fn connect_to_server(
+    mut commands: Commands,
+    config: Res<Config>,
+    time: Res<Time>,
+    request: Option<ResMut<ConnectRequest>>,
+    mut next_state: ResMut<NextState<ClientState>>,
+) {
+    let Some(mut request) = request else {
+        return;
+    };
+
+    if let Some(attempt) = request.attempt.take() {
+        if !attempt.is_finished() {
+            request.attempt = Some(attempt);
+            return;
+        }
+
+        let result = attempt
+            .join()
+            .unwrap_or_else(|_| Err(format!("Connecting to {} panicked", request.addr)));
+        match result {
+            Ok(connection) => {
+                commands.insert_resource(ServerConnection(connection));
+                commands.remove_resource::<ConnectRequest>();
+                // Wait in the lobby until the host starts the match
+                next_state.set(ClientState::Lobby);
+                return;
+            }
+            Err(err) if request.attempts_left == 0 => {
+                error!("{}", err);
+                commands.remove_resource::<ConnectRequest>();
+                return;
+            }
+            Err(_) => {}
+        }
+    }
+
+    if !request.retry.tick(time.delta()).just_finished() {
+        return;
+    }
+    request.attempts_left = request.attempts_left.saturating_sub(1);
+
+    let addr = request.addr;
+    let username = config.client_config.username.clone();
+    request.attempt = Some(thread::spawn(move || connect(addr, &username)));
+}
+
+fn connect(addr: SocketAddr, username: &str) -> Result<Connection, String> {
+    info!("Connecting to {}", addr);
+    let stream = TcpStream::connect_timeout(&addr, CONNECT_TIMEOUT)
+        .map_err(|err| format!("Unable to connect to {}: {}", addr, err))?;
+
+    let mut connection = Connection::new(stream)
+        .map_err(|err| format!("Unable to set up connection to {}: {}", addr, err))?;
+
+    connection
+        .send(&ClientMessage::Handshake {
+            version: PROTOCOL_VERSION,
+            username: username.to_string(),
+        })
+        .map_err(|err| format!("Unable to send handshake to {}: {}", addr, err))?;
+
+    return Ok(connection);
+}
+
+fn receive_server_messages(
+    mut commands: Commands,
+    connection: Option<ResMut<ServerConnection>>,
+    local_player: Option<Res<LocalPlayer>>,
+    mut fog_memory: Option<ResMut<FogMemory>>,
+    mut turn: ResMut<TurnCounter>,
+    mut economy: ResMut<Economy>,
+    mut next_state: ResMut<NextState<ClientState>>,
+    mut resolved_evw: EventWriter<TurnResolvedEvent>,
+    mut gameboards: Query<(Entity, &mut Gameboard)>,
+    units: Query<Entity, With<Unit>>,
+) {
+    let Some(mut connection) = connection else {
+        return;
+    };
+
+    let messages = match connection.0.receive::<ServerMessage>() {
+        Ok(messages) => messages,
+        Err(err) => {
+            error!("Lost connection to server: {}", err);
+            commands.remove_resource::<ServerConnection>();
+            return;
+        }
+    };
+
+    // A snapshot replaces the board, but the new one won't be spawned until
+    // the end of the frame, so keep hold of it in case a turn comes in too
+    let mut team = local_player.map(|p| p.team.clone());
+    let mut fresh_board = None::<(Gameboard, FogMemory)>;
+
+    for message in messages {
+        match message {
+            ServerMessage::Handshake {
+                team: joined_team, ..
+            } => {
+                info!("Joined server as {:?}", joined_team.0);
+                team = Some(joined_team.clone());
+                commands.insert_resource(LocalPlayer { team: joined_team });
+            }
+            ServerMessage::LobbyUpdate(lobby) => {
+                commands.insert_resource(ClientLobby(lobby));
+            }
+            ServerMessage::MatchStarted => {
+                info!("Match started");
+                next_state.set(ClientState::Game);
+            }
+            ServerMessage::BoardSnapshot(snapshot) => {
+                let Some(team) = team.as_ref() else {
+                    warn!("Got a board before joining, ignoring it");
+                    continue;
+                };
+
+                fresh_board = Some(board_from_snapshot(&snapshot, team));
+                economy.set_balance(team, snapshot.balance);
+                replace_units(&mut commands, &units, snapshot.units);
+                turn.0 = snapshot.turn;
+            }
+            ServerMessage::TurnResult { result, delta } => {
+                let Some(team) = team.as_ref() else {
+                    continue;
+                };
+
+                match (
+                    fresh_board.as_mut(),
+                    gameboards.get_single_mut(),
+                    fog_memory.as_mut(),
+                ) {
+                    (Some((gameboard, memory)), _, _) => {
+                        apply_delta(gameboard, memory, &delta, team, result.turn)
+                    }
+                    (None, Ok((_, mut gameboard)), Some(memory)) => {
+                        apply_delta(&mut gameboard, memory, &delta, team, result.turn)
+                    }
+                    _ => warn!("Got a turn without a board to apply it to"),
+                }
+
+                economy.set_balance(team, delta.balance);
+                replace_units(&mut commands, &units, delta.units);
+                turn.0 = result.turn + 1;
+                resolved_evw.send(TurnResolvedEvent(result));
+            }
+            ServerMessage::Error(err) => error!("Server error: {:?}", err),
+        }
+    }
+
+    if let Some((gameboard, memory)) = fresh_board {
+        gameboards
+            .iter()
+            .for_each(|(e, _)| commands.entity(e).despawn_recursive());
+        commands.spawn(gameboard).insert(Name::new("Gameboard"));
+        commands.insert_resource(memory);
+    }
+
+    let _ = connection.0.flush();
+}
+
+fn replace_units(commands: &mut Commands, existing: &Query<Entity, With<Unit>>, units: Vec<Unit>) {
+    existing
+        .iter()
+        .for_each(|e| commands.entity(e).despawn_recursive());
+    for unit in units {
+        commands.spawn(unit).insert(Name::new("Unit"));
+    }
+}
+
+fn lobby_input(
+    keys: Res<Input<KeyCode>>,
+    connection: Option<ResMut<ServerConnection>>,
+    lobby: Option<Res<ClientLobby>>,
+    local_player: Option<Res<LocalPlayer>>,
+) {
+    let (Some(mut connection), Some(lobby), Some(local_player)) = (connection, lobby, local_player)
+    else {
+        return;
+    };
+    let Some(me) = lobby.0.players.iter().find(|p| p.team == local_player.team) else {
+        return;
+    };
+
+    if keys.just_pressed(KeyCode::R) {
+        if let Err(err) = connection.0.send(&ClientMessage::SetReady(!me.ready)) {
+            error!("Unable to send ready state: {}", err);
+        }
+    }
+
+    if keys.just_pressed(KeyCode::Return) && me.host {
+        if let Err(err) = connection.0.send(&ClientMessage::StartMatch) {
+            error!("Unable to start match: {}", err);
+        }
+    }
+}
+
+fn submit_orders(
+    mut turn_evr: EventReader<TurnCompletedEvent>,
+    connection: Option<ResMut<ServerConnection>>,
+    local_player: Option<Res<LocalPlayer>>,
+    mut planned_recruits: ResMut<PlannedRecruits>,
+    planned_actions: Query<(&Unit, &UnitAction)>,
+) {
+    if turn_evr.iter().count() == 0 {
+        return;
+    }
+    let (Some(mut connection), Some(local_player)) = (connection, local_player) else {
+        warn!("Not connected to a server, unable to submit orders");
+        return;
+    };
+
+    let actions = planned_actions
+        .iter()
+        .filter(|(unit, _)| unit.owner == local_player.team)
+        .map(|(_, action)| action.clone())
+        .collect::<Vec<UnitAction>>();
+
+    // The server knows who's asking, so only the unit types are sent
+    let recruits = planned_recruits
+        .orders
+        .drain(..)
+        .filter(|order| order.owner == local_player.team)
+        .map(|order| order.unit)
+        .collect::<Vec<UnitID>>();
+
+    if let Err(err) = connection
+        .0
+        .send(&ClientMessage::SubmitOrders { actions, recruits })
+    {
+        error!("Unable to submit orders: {}", err);
+    }
+}
@@ -0,0 +1,229 @@
+use std::path::PathBuf;
+
+use bevy::prelude::*;
+
+use crate::common::{
+    config::Config,
+    logic::{
+        neo_gameboard::Gameboard,
+        replay::{latest_replay, Replay, ReplayFrame},
+        units::UnitRegistry,
+        TurnCounter, Unit,
+    },
+};
+
+use super::ClientState;
+
+pub struct ReplayPlugin;
+
+impl Plugin for ReplayPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_system(load_replay.in_schedule(OnEnter(ClientState::Replay)))
+            .add_system(replay_controls.in_set(OnUpdate(ClientState::Replay)))
+            .add_system(
+                show_frame
+                    .after(replay_controls)
+                    .in_set(OnUpdate(ClientState::Replay)),
+            );
+    }
+}
+
+#[derive(Resource)]
+pub struct ReplayViewer {
+    replay: Replay,
+    // frames[n] is the start of the nth turn played. Only simulated once
+    // they're asked for
+    frames: Vec<ReplayFrame>,
+    current: usize,
+    shown: Option<usize>,
+    // Digits typed so far towards jumping to a turn
+    typed: String,
+}
+
+impl ReplayViewer {
+    fn new(replay: Replay) -> Self {
+        return Self {
+            frames: vec![replay.start.clone()],
+            replay,
+            current: 0,
+            shown: None,
+            typed: String::new(),
+        };
+    }
+
+    // The frame after the last turn, i.e. how the match ended (so far)
+    fn last(&self) -> usize {
+        return self.replay.turns.len();
+    }
+
+    fn go_to(&mut self, index: usize) {
+        self.current = usize::min(index, self.last());
+    }
+
+    // Simulates every turn up to `index` that hasn't been yet. Anything that
+    // plays out differently to the recording means the rules changed since
+    fn frame(&mut self, index: usize, registry: &UnitRegistry) -> &ReplayFrame {
+        while self.frames.len() <= index {
+            let played = &self.replay.turns[self.frames.len() - 1];
+            let (next, result) = self.frames.last().unwrap().step(registry, &played.orders);
+            if result != played.result {
+                warn!(
+                    "Turn {} played out differently to the recording",
+                    result.turn
+                );
+            }
+            self.frames.push(next);
+        }
+        return &self.frames[index];
+    }
+}
+
+fn load_replay(mut commands: Commands, config: Res<Config>) {
+    let Some(path) = config
+        .replay_config
+        .file
+        .as_ref()
+        .map(PathBuf::from)
+        .or_else(latest_replay)
+    else {
+        error!("No replay to watch, play a match first");
+        return;
+    };
+
+    match Replay::read(&path) {
+        Ok(replay) => {
+            info!(
+                "Watching {} ({} turns, seed {})",
+                path.display(),
+                replay.turns.len(),
+                replay.seed
+            );
+            commands.insert_resource(ReplayViewer::new(replay));
+        }
+        Err(err) => error!("Unable to load replay {}: {}", path.display(), err),
+    }
+}
+
+// Right and left step a turn, home and end jump to either end, and a turn
+// number followed by return jumps straight to it
+fn replay_controls(keys: Res<Input<KeyCode>>, viewer: Option<ResMut<ReplayViewer>>) {
+    let Some(mut viewer) = viewer else {
+        return;
+    };
+
+    if keys.just_pressed(KeyCode::Right) {
+        let next = viewer.current + 1;
+        viewer.go_to(next);
+    }
+    if keys.just_pressed(KeyCode::Left) {
+        let previous = viewer.current.saturating_sub(1);
+        viewer.go_to(previous);
+    }
+    if keys.just_pressed(KeyCode::Home) {
+        viewer.go_to(0);
+    }
+    if keys.just_pressed(KeyCode::End) {
+        let last = viewer.last();
+        viewer.go_to(last);
+    }
+
+    for key in keys.get_just_pressed() {
+        if let Some(digit) = digit_of(*key) {
+            viewer.typed.push(digit);
+        }
+    }
+    if keys.just_pressed(KeyCode::Back) {
+        viewer.typed.pop();
+    }
+    if keys.just_pressed(KeyCode::Return) {
+        let typed = std::mem::take(&mut viewer.typed);
+        match typed.parse::<u32>() {
+            Ok(turn) => {
+                let first = viewer.replay.start.turn;
+                viewer.go_to(turn.saturating_sub(first) as usize);
+            }
+            Err(_) => warn!("Type a turn number before pressing return"),
+        }
+    }
+}
+
+fn digit_of(key: KeyCode) -> Option<char> {
+    return match key {
+        KeyCode::Key0 | KeyCode::Numpad0 => Some('0'),
+        KeyCode::Key1 | KeyCode::Numpad1 => Some('1'),
+        KeyCode::Key2 | KeyCode::Numpad2 => Some('2'),
+        KeyCode::Key3 | KeyCode::Numpad3 => Some('3'),
+        KeyCode::Key4 | KeyCode::Numpad4 => Some('4'),
+        KeyCode::Key5 | KeyCode::Numpad5 => Some('5'),
+        KeyCode::Key6 | KeyCode::Numpad6 => Some('6'),
+        KeyCode::Key7 | KeyCode::Numpad7 => Some('7'),
+        KeyCode::Key8 | KeyCode::Numpad8 => Some('8'),
+        KeyCode::Key9 | KeyCode::Numpad9 => Some('9'),
+        _ => None,
+    };
+}
+
+// Puts the current frame on the board, with the orders given that turn on the
+// units they were given to
+fn show_frame(
+    mut commands: Commands,
+    registry: Res<UnitRegistry>,
+    viewer: Option<ResMut<ReplayViewer>>,
+    mut gameboard_q: Query<&mut Gameboard>,
+    units: Query<Entity, With<Unit>>,
+) {
+    let Some(mut viewer) = viewer else {
+        return;
+    };
+    let index = viewer.current;
+    if viewer.shown == Some(index) {
+        return;
+    }
+    viewer.shown = Some(index);
+
+    let frame = viewer.frame(index, &registry).clone();
+    let played = viewer.replay.turns.get(index).cloned();
+
+    match gameboard_q.get_single_mut() {
+        Ok(mut gameboard) => *gameboard = frame.gameboard,
+        Err(_) => {
+            commands
+                .spawn(frame.gameboard)
+                .insert(Name::new("Gameboard"));
+        }
+    }
+
+    units
+        .iter()
+        .for_each(|e| commands.entity(e).despawn_recursive());
+    for unit in frame.units {
+        let action = played.as_ref().and_then(|played| {
+            played
+                .orders
+                .actions
+                .iter()
+                .find(|a| a.curr_pos == unit.pos)
+                .cloned()
+        });
+        let mut entity = commands.spawn(unit);
+        entity.insert(Name::new("Unit"));
+        if let Some(action) = action {
+            entity.insert(action);
+        }
+    }
+
+    commands.insert_resource(TurnCounter(frame.turn));
+    commands.insert_resource(frame.economy);
+    commands.insert_resource(frame.match_state);
+
+    match played {
+        Some(played) => info!(
+            "Turn {} of {} ({} orders, {} events)",
+            frame.turn,
+            viewer.replay.start.turn + viewer.last() as u32,
+            played.orders.actions.len() + played.orders.recruits.len(),
+            played.result.events.len()
+        ),
+        None => info!("Turn {}, the end of the replay", frame.turn),
+    }
+}
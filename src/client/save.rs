@@ -0,0 +1,155 @@
+use bevy::prelude::*;
+
+use crate::common::logic::{
+    economy::Economy,
+    neo_gameboard::Gameboard,
+    recruitment::RecruitQueue,
+    replay::{ReplayFrame, ReplayRecorder},
+    rng::GameRng,
+    save::{SaveGame, QUICKSAVE_PATH},
+    turn::{PendingOrders, TurnOrders},
+    victory::MatchState,
+    TurnCounter, Unit, UnitAction,
+};
+
+use super::{
+    graphical::inputs::{PlannedRecruits, SelectedUnit},
+    ClientState,
+};
+
+// F5 saves the match as it is right now, planned orders included
+pub fn quicksave(
+    keys: Res<Input<KeyCode>>,
+    turn: Res<TurnCounter>,
+    economy: Res<Economy>,
+    recruit_queue: Res<RecruitQueue>,
+    match_state: Res<MatchState>,
+    rng: Res<GameRng>,
+    pending_orders: Res<PendingOrders>,
+    planned_recruits: Res<PlannedRecruits>,
+    gameboard_q: Query<&Gameboard>,
+    units: Query<(&Unit, Option<&UnitAction>)>,
+) {
+    if !keys.just_pressed(KeyCode::F5) {
+        return;
+    }
+    let Ok(gameboard) = gameboard_q.get_single() else {
+        warn!("Nothing to save without a gameboard");
+        return;
+    };
+
+    // Planned recruits haven't made it into the pending orders yet
+    let mut orders = pending_orders.0.clone();
+    orders
+        .recruits
+        .extend(planned_recruits.orders.iter().cloned());
+
+    let save = SaveGame {
+        turn: turn.0,
+        gameboard: gameboard.clone(),
+        units: units
+            .iter()
+            .map(|(unit, action)| (unit.clone(), action.cloned()))
+            .collect(),
+        economy: economy.clone(),
+        recruit_queue: recruit_queue.clone(),
+        match_state: match_state.clone(),
+        rng: rng.clone(),
+        pending_orders: orders,
+    };
+
+    match save.write(QUICKSAVE_PATH) {
+        Ok(()) => info!("Saved turn {} to {}", turn.0, QUICKSAVE_PATH),
+        Err(err) => error!("Unable to save to {}: {}", QUICKSAVE_PATH, err),
+    }
+}
+
+// Load Game on the main menu. The match starts from the quicksave instead of a
+// new board
+#[derive(Resource)]
+pub struct LoadQuicksave;
+
+// F9 throws away the current match and carries on from the quicksave
+pub fn quickload(
+    mut commands: Commands,
+    keys: Res<Input<KeyCode>>,
+    gameboards: Query<Entity, With<Gameboard>>,
+    units: Query<Entity, With<Unit>>,
+) {
+    if keys.just_pressed(KeyCode::F9) {
+        load_quicksave(&mut commands, &gameboards, &units);
+    }
+}
+
+pub fn load_quicksave_on_start(
+    mut commands: Commands,
+    mut next_state: ResMut<NextState<ClientState>>,
+    gameboards: Query<Entity, With<Gameboard>>,
+    units: Query<Entity, With<Unit>>,
+) {
+    commands.remove_resource::<LoadQuicksave>();
+    if !load_quicksave(&mut commands, &gameboards, &units) {
+        next_state.set(ClientState::MainMenu);
+    }
+}
+
+fn load_quicksave(
+    commands: &mut Commands,
+    gameboards: &Query<Entity, With<Gameboard>>,
+    units: &Query<Entity, With<Unit>>,
+) -> bool {
+    let save = match SaveGame::read(QUICKSAVE_PATH) {
+        Ok(save) => save,
+        Err(err) => {
+            error!("Unable to load {}: {}", QUICKSAVE_PATH, err);
+            return false;
+        }
+    };
+
+    gameboards
+        .iter()
+        .chain(units.iter())
+        .for_each(|e| commands.entity(e).despawn_recursive());
+
+    // The old replay doesn't lead here any more, so start a new one
+    let start = ReplayFrame {
+        turn: save.turn,
+        gameboard: save.gameboard.clone(),
+        units: save.units.iter().map(|(unit, _)| unit.clone()).collect(),
+        economy: save.economy.clone(),
+        recruit_queue: save.recruit_queue.clone(),
+        match_state: save.match_state.clone(),
+    };
+    commands.insert_resource(ReplayRecorder::new(save.rng.seed(), start));
+
+    // A new board entity, so everything that draws it starts from scratch
+    commands
+        .spawn(save.gameboard)
+        .insert(Name::new("Gameboard"));
+    for (unit, action) in save.units {
+        let mut entity = commands.spawn(unit);
+        entity.insert(Name::new("Unit"));
+        if let Some(action) = action {
+            entity.insert(action);
+        }
+    }
+
+    let TurnOrders { actions, recruits } = save.pending_orders;
+    commands.insert_resource(TurnCounter(save.turn));
+    commands.insert_resource(save.economy);
+    commands.insert_resource(save.recruit_queue);
+    commands.insert_resource(save.match_state);
+    commands.insert_resource(save.rng);
+    commands.insert_resource(PendingOrders(TurnOrders {
+        actions,
+        recruits: Vec::new(),
+    }));
+    commands.insert_resource(PlannedRecruits {
+        orders: recruits,
+        ..default()
+    });
+    commands.remove_resource::<SelectedUnit>();
+
+    info!("Loaded turn {} from {}", save.turn, QUICKSAVE_PATH);
+    return true;
+}
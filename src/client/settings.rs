@@ -0,0 +1,372 @@
+use std::{fs, io, net::SocketAddr};
+
+use bevy::{
+    prelude::*,
+    window::{PresentMode, PrimaryWindow, WindowMode},
+};
+use toml_edit::{Document, Item, Value};
+
+use crate::common::config::{Config, CONFIG_PATH, MAX_BOARD_SIZE, MIN_BOARD_SIZE};
+
+use super::ClientState;
+
+// What the settings screen shows, as typed. Nothing in here has been checked
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct SettingsForm {
+    pub username: String,
+    pub connection_address: String,
+    pub debug: bool,
+    pub width: String,
+    pub height: String,
+    pub scale: String,
+    pub fullscreen: bool,
+    pub vsync: bool,
+    pub volume: String,
+}
+
+impl SettingsForm {
+    pub fn from_config(config: &Config) -> Self {
+        return Self {
+            username: config.client_config.username.clone(),
+            connection_address: config.connection_address.to_string(),
+            debug: config.debug,
+            width: config.gameboard_config.width.to_string(),
+            height: config.gameboard_config.height.to_string(),
+            scale: config.gameboard_config.scale.to_string(),
+            fullscreen: config.graphics_config.fullscreen,
+            vsync: config.graphics_config.vsync,
+            volume: config.audio_config.volume.to_string(),
+        };
+    }
+
+    fn parse(&self) -> Result<Settings, String> {
+        let username = self.username.trim();
+        if username.is_empty() {
+            return Err("Username can't be empty".to_string());
+        }
+        let connection_address = self
+            .connection_address
+            .trim()
+            .parse::<SocketAddr>()
+            .map_err(|_| "Address must look like 127.0.0.1:2360".to_string())?;
+
+        let board_size = |name: &str, size: &str| {
+            return match size.trim().parse::<u32>() {
+                Ok(size) if (MIN_BOARD_SIZE..=MAX_BOARD_SIZE).contains(&size) => Ok(size),
+                _ => Err(format!(
+                    "{} must be a whole number from {} to {}",
+                    name, MIN_BOARD_SIZE, MAX_BOARD_SIZE
+                )),
+            };
+        };
+        let width = board_size("Width", &self.width)?;
+        let height = board_size("Height", &self.height)?;
+
+        // Kept as f64 until they're applied, so they're written back exactly
+        // as typed rather than with f32 rounding
+        let scale = match self.scale.trim().parse::<f64>() {
+            Ok(scale) if scale > 0f64 => scale,
+            _ => return Err("Scale must be a number above 0".to_string()),
+        };
+        let volume = match self.volume.trim().parse::<f64>() {
+            Ok(volume) if (0f64..=1f64).contains(&volume) => volume,
+            _ => return Err("Volume must be a number from 0 to 1".to_string()),
+        };
+
+        return Ok(Settings {
+            username: username.to_string(),
+            connection_address,
+            debug: self.debug,
+            width,
+            height,
+            scale,
+            fullscreen: self.fullscreen,
+            vsync: self.vsync,
+            volume,
+        });
+    }
+}
+
+// A SettingsForm that's been checked
+struct Settings {
+    username: String,
+    connection_address: SocketAddr,
+    debug: bool,
+    width: u32,
+    height: u32,
+    scale: f64,
+    fullscreen: bool,
+    vsync: bool,
+    volume: f64,
+}
+
+impl Settings {
+    // Everything takes effect straight away except debug, which decides which
+    // plugins are added. Boards change size from the next match on. Returns
+    // whether a restart is needed
+    fn apply(&self, config: &mut Config) -> bool {
+        let restart = config.debug != self.debug;
+
+        config.client_config.username = self.username.clone();
+        config.connection_address = self.connection_address;
+        config.debug = self.debug;
+        config.gameboard_config.width = self.width;
+        config.gameboard_config.height = self.height;
+        config.gameboard_config.scale = self.scale as f32;
+        config.graphics_config.fullscreen = self.fullscreen;
+        config.graphics_config.vsync = self.vsync;
+        config.audio_config.volume = self.volume as f32;
+        return restart;
+    }
+
+    fn write(&self, path: &str) -> Result<(), String> {
+        let contents = match fs::read_to_string(path) {
+            Ok(contents) => contents,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => String::new(),
+            Err(err) => return Err(format!("Unable to read {}: {}", path, err)),
+        };
+        let mut doc = contents
+            .parse::<Document>()
+            .map_err(|err| format!("Unable to parse {}: {}", path, err))?;
+        self.update(&mut doc);
+
+        return fs::write(path, doc.to_string())
+            .map_err(|err| format!("Unable to write {}: {}", path, err));
+    }
+
+    // Only the values change. Comments, formatting and everything the
+    // settings screen doesn't know about are left as they were
+    fn update(&self, doc: &mut Document) {
+        set(doc, "client", "username", self.username.as_str().into());
+        set(
+            doc,
+            "configuration",
+            "connection_address",
+            self.connection_address.to_string().into(),
+        );
+        set(doc, "configuration", "debug", self.debug.into());
+        set(doc, "gameboard", "width", (self.width as i64).into());
+        set(doc, "gameboard", "height", (self.height as i64).into());
+        set(doc, "gameboard", "scale", self.scale.into());
+        set(doc, "graphics", "fullscreen", self.fullscreen.into());
+        set(doc, "graphics", "vsync", self.vsync.into());
+        set(doc, "audio", "volume", self.volume.into());
+    }
+}
+
+// Keeps any comment on the same line as the old value
+fn set(doc: &mut Document, table: &str, key: &str, mut value: Value) {
+    let item = &mut doc[table][key];
+    if let Some(old) = item.as_value() {
+        *value.decor_mut() = old.decor().clone();
+    }
+    *item = Item::Value(value);
+}
+
+pub enum SettingsChoice {
+    Save(SettingsForm),
+    Back,
+}
+
+// The result of the last save, shown on the settings screen
+#[derive(Default, Resource)]
+pub struct SettingsStatus(pub Option<String>);
+
+pub fn handle_settings_choices(
+    keys: Res<Input<KeyCode>>,
+    mut choice_evr: EventReader<SettingsChoice>,
+    mut config: ResMut<Config>,
+    mut status: ResMut<SettingsStatus>,
+    mut next_state: ResMut<NextState<ClientState>>,
+) {
+    if keys.just_pressed(KeyCode::Escape) {
+        next_state.set(ClientState::MainMenu);
+    }
+
+    for choice in choice_evr.iter() {
+        let form = match choice {
+            SettingsChoice::Save(form) => form,
+            SettingsChoice::Back => {
+                next_state.set(ClientState::MainMenu);
+                continue;
+            }
+        };
+
+        let settings = match form.parse() {
+            Ok(settings) => settings,
+            Err(err) => {
+                status.0 = Some(err);
+                continue;
+            }
+        };
+        if let Err(err) = settings.write(CONFIG_PATH) {
+            error!("{}", err);
+            status.0 = Some(err);
+            continue;
+        }
+
+        let restart = settings.apply(&mut config);
+        info!("Saved settings to {}", CONFIG_PATH);
+        status.0 = Some(if restart {
+            "Saved. Restart to change debug mode".to_string()
+        } else {
+            "Saved".to_string()
+        });
+    }
+}
+
+// Nothing plays sounds yet, so volume is only stored for now
+pub fn apply_graphics_settings(
+    config: Res<Config>,
+    mut windows: Query<&mut Window, With<PrimaryWindow>>,
+) {
+    if !config.is_changed() {
+        return;
+    }
+    let Ok(mut window) = windows.get_single_mut() else {
+        return;
+    };
+
+    let graphics = &config.graphics_config;
+    let mode = if graphics.fullscreen {
+        WindowMode::BorderlessFullscreen
+    } else {
+        WindowMode::Windowed
+    };
+    let present_mode = if graphics.vsync {
+        PresentMode::AutoVsync
+    } else {
+        PresentMode::AutoNoVsync
+    };
+    if window.mode != mode {
+        window.mode = mode;
+    }
+    if window.present_mode != present_mode {
+        window.present_mode = present_mode;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn form() -> SettingsForm {
+        return SettingsForm {
+            username: " Alice ".to_string(),
+            connection_address: "10.0.0.2:2360".to_string(),
+            debug: true,
+            width: "64".to_string(),
+            height: "32".to_string(),
+            scale: "1.5".to_string(),
+            fullscreen: true,
+            vsync: false,
+            volume: "0.25".to_string(),
+        };
+    }
+
+    #[test]
+    fn saving_keeps_comments_and_unknown_keys() {
+        let mut doc = r#"# Written by hand
+[client]
+username = "Bob" # who I am
+
+[gameboard]
+# Bigger boards take longer
+width = 256
+seed = 42
+
+[modding]
+enabled = true
+"#
+        .parse::<Document>()
+        .unwrap();
+
+        form().parse().unwrap().update(&mut doc);
+        let written = doc.to_string();
+
+        for kept in [
+            "# Written by hand",
+            "# who I am",
+            "# Bigger boards take longer",
+            "seed = 42",
+            "[modding]\nenabled = true",
+        ] {
+            assert!(written.contains(kept), "lost {:?} from:\n{}", kept, written);
+        }
+        assert!(written.contains("username = \"Alice\" # who I am"));
+        assert_eq!(doc["gameboard"]["width"].as_integer(), Some(64));
+        assert_eq!(doc["gameboard"]["seed"].as_integer(), Some(42));
+        assert_eq!(doc["audio"]["volume"].as_float(), Some(0.25));
+        assert_eq!(
+            doc["configuration"]["connection_address"].as_str(),
+            Some("10.0.0.2:2360")
+        );
+    }
+
+    #[test]
+    fn saving_to_an_empty_file_fills_it_in() {
+        let mut doc = Document::new();
+        form().parse().unwrap().update(&mut doc);
+
+        let config = toml::from_str::<toml::Table>(&doc.to_string()).unwrap();
+        assert_eq!(config["client"]["username"].as_str(), Some("Alice"));
+        assert_eq!(config["configuration"]["debug"].as_bool(), Some(true));
+        assert_eq!(config["graphics"]["vsync"].as_bool(), Some(false));
+        assert_eq!(config["gameboard"]["scale"].as_float(), Some(1.5));
+    }
+
+    #[test]
+    fn valid_forms_are_trimmed_and_parsed() {
+        let settings = form().parse().unwrap();
+        assert_eq!(settings.username, "Alice");
+        assert_eq!(
+            settings.connection_address,
+            SocketAddr::from(([10, 0, 0, 2], 2360))
+        );
+        assert_eq!((settings.width, settings.height), (64, 32));
+        assert_eq!(settings.scale, 1.5);
+        assert_eq!(settings.volume, 0.25);
+    }
+
+    #[test]
+    fn bad_values_are_turned_down() {
+        let too_big = (MAX_BOARD_SIZE + 1).to_string();
+        let too_small = (MIN_BOARD_SIZE - 1).to_string();
+        let cases: [(&str, fn(&mut SettingsForm, &str), &str); 12] = [
+            ("username", |f, v| f.username = v.to_string(), "  "),
+            (
+                "address",
+                |f, v| f.connection_address = v.to_string(),
+                "localhost",
+            ),
+            (
+                "address",
+                |f, v| f.connection_address = v.to_string(),
+                "1.2.3.4",
+            ),
+            ("width", |f, v| f.width = v.to_string(), &too_big),
+            ("width", |f, v| f.width = v.to_string(), &too_small),
+            ("width", |f, v| f.width = v.to_string(), "sixty"),
+            ("height", |f, v| f.height = v.to_string(), "-32"),
+            ("scale", |f, v| f.scale = v.to_string(), "0"),
+            ("scale", |f, v| f.scale = v.to_string(), "big"),
+            ("volume", |f, v| f.volume = v.to_string(), "1.5"),
+            ("volume", |f, v| f.volume = v.to_string(), "-0.1"),
+            ("volume", |f, v| f.volume = v.to_string(), "loud"),
+        ];
+
+        for (field, change, value) in cases {
+            let mut form = form();
+            change(&mut form, value);
+            let err = form.parse().err();
+            assert!(
+                err.as_deref()
+                    .map_or(false, |err| err.to_lowercase().starts_with(field)),
+                "{} = {:?} gave {:?}",
+                field,
+                value,
+                err
+            );
+        }
+    }
+}
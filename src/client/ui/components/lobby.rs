@@ -0,0 +1,111 @@
+use bevy::prelude::*;
+use kayak_ui::prelude::{widgets::*, *};
+
+use crate::{
+    client::{network::ClientLobby, ui::panel_styles, ClientState, LocalPlayer},
+    common::{logic::PlayerTeam, network::protocol::LobbyInfo},
+};
+
+#[derive(Component, Clone, PartialEq, Default)]
+pub struct LobbyWidget;
+
+impl Widget for LobbyWidget {}
+
+#[derive(Component, Default, PartialEq, Clone)]
+pub struct LobbyWidgetState {
+    pub visible: bool,
+    pub lobby: LobbyInfo,
+    pub local_team: Option<PlayerTeam>,
+}
+
+widget_bundle!(LobbyWidgetBundle, LobbyWidget);
+
+// Kayak only re-renders when props or state change, so mirror the lobby
+// resource into the widget state whenever it changes
+pub fn update_lobby_widget_state(
+    state: Res<State<ClientState>>,
+    lobby: Option<Res<ClientLobby>>,
+    local_player: Option<Res<LocalPlayer>>,
+    mut widget_states: Query<&mut LobbyWidgetState>,
+) {
+    let new_state = LobbyWidgetState {
+        visible: state.0 == ClientState::Lobby,
+        lobby: lobby.map(|l| l.0.clone()).unwrap_or_default(),
+        local_team: local_player.map(|p| p.team.clone()),
+    };
+
+    for mut widget_state in widget_states.iter_mut() {
+        if *widget_state != new_state {
+            *widget_state = new_state.clone();
+        }
+    }
+}
+
+pub fn lobby_widget_render(
+    In(entity): In<Entity>,
+    widget_context: Res<KayakWidgetContext>,
+    mut commands: Commands,
+    state_q: Query<&LobbyWidgetState>,
+) -> bool {
+    let state_entity = widget_context.use_state(&mut commands, entity, LobbyWidgetState::default());
+    let Ok(state) = state_q.get(state_entity) else {
+        return true;
+    };
+    if !state.visible {
+        return true;
+    }
+
+    let is_host = state
+        .lobby
+        .players
+        .iter()
+        .any(|p| p.host && Some(&p.team) == state.local_team.as_ref());
+    let hint = if is_host {
+        "R - toggle ready    ENTER - start match"
+    } else {
+        "R - toggle ready"
+    };
+
+    let parent_id = Some(entity);
+
+    rsx! {
+        <BackgroundBundle styles={panel_styles()}>
+            <TextWidgetBundle
+                text={TextProps {
+                    content: format!("LOBBY ({}/{})", state.lobby.players.len(), state.lobby.max_players),
+                    size: 24f32,
+                    alignment: Alignment::Middle,
+                    ..Default::default()
+                }}
+            />
+            {state.lobby.players.iter().for_each(|player| {
+                let you = if Some(&player.team) == state.local_team.as_ref() { " (you)" } else { "" };
+                let host = if player.host { " [host]" } else { "" };
+                let ready = if player.ready { "READY" } else { "not ready" };
+                constructor! {
+                    <TextWidgetBundle
+                        text={TextProps {
+                            content: format!("{:?} - {}{}{} - {}", player.team.0, player.username, you, host, ready),
+                            size: 16f32,
+                            ..Default::default()
+                        }}
+                    />
+                }
+            })}
+            <TextWidgetBundle
+                styles={KStyle {
+                    padding_top: StyleProp::Value(Units::Pixels(10f32)),
+                    ..Default::default()
+                }}
+                text={TextProps {
+                    content: hint.into(),
+                    size: 12f32,
+                    alignment: Alignment::Middle,
+                    ..Default::default()
+                }}
+            />
+        </BackgroundBundle>
+    };
+
+    return true;
+}
@@ -0,0 +1,251 @@
+use bevy::prelude::*;
+use kayak_ui::prelude::{widgets::*, *};
+
+use crate::{
+    client::{menu::MainMenuChoice, ui::panel_styles, ClientState},
+    common::config::Config,
+};
+
+#[derive(Component, Clone, PartialEq, Default)]
+pub struct MenuButton {
+    pub text: String,
+}
+
+impl Widget for MenuButton {}
+
+#[derive(Bundle)]
+pub struct MenuButtonBundle {
+    pub button: MenuButton,
+    pub styles: KStyle,
+    pub on_event: OnEvent,
+    pub widget_name: WidgetName,
+}
+
+impl Default for MenuButtonBundle {
+    fn default() -> Self {
+        Self {
+            button: MenuButton::default(),
+            styles: KStyle {
+                bottom: StyleProp::Value(Units::Pixels(10f32)),
+                cursor: KCursorIcon(CursorIcon::Hand).into(),
+                ..Default::default()
+            },
+            on_event: OnEvent::default(),
+            widget_name: MenuButton::default().get_name(),
+        }
+    }
+}
+
+pub fn menu_button_render(
+    In(entity): In<Entity>,
+    widget_context: Res<KayakWidgetContext>,
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    button_q: Query<&MenuButton>,
+    state_q: Query<&ButtonState>,
+) -> bool {
+    let state_entity = widget_context.use_state(&mut commands, entity, ButtonState { hovering: false });
+    let (Ok(button), Ok(state)) = (button_q.get(entity), state_q.get(state_entity)) else {
+        return true;
+    };
+
+    let image = if state.hovering {
+        asset_server.load("images/menu_button_hover.png")
+    } else {
+        asset_server.load("images/menu_button.png")
+    };
+
+    // Clicks are left to bubble up to whoever placed the button
+    let on_event = OnEvent::new(
+        move |In(_entity): In<Entity>, event: ResMut<KEvent>, mut state_q: Query<&mut ButtonState>| {
+            if let Ok(mut state) = state_q.get_mut(state_entity) {
+                match event.event_type {
+                    EventType::MouseIn(..) => state.hovering = true,
+                    EventType::MouseOut(..) => state.hovering = false,
+                    _ => {}
+                }
+            }
+        },
+    );
+
+    let parent_id = Some(entity);
+
+    rsx! {
+        <NinePatchBundle
+            nine_patch={NinePatch {
+                handle: image,
+                border: Edge::all(20f32),
+            }}
+            styles={KStyle {
+                width: StyleProp::Value(Units::Stretch(1f32)),
+                height: StyleProp::Value(Units::Pixels(48f32)),
+                ..Default::default()
+            }}
+            on_event={on_event}
+        >
+            <TextWidgetBundle
+                styles={KStyle {
+                    top: StyleProp::Value(Units::Stretch(1f32)),
+                    bottom: StyleProp::Value(Units::Stretch(1f32)),
+                    ..Default::default()
+                }}
+                text={TextProps {
+                    content: button.text.clone(),
+                    size: 20f32,
+                    alignment: Alignment::Middle,
+                    ..Default::default()
+                }}
+            />
+        </NinePatchBundle>
+    };
+
+    return true;
+}
+
+#[derive(Component, Clone, PartialEq, Default)]
+pub struct MainMenuWidget;
+
+impl Widget for MainMenuWidget {}
+
+#[derive(Component, Default, PartialEq, Clone)]
+pub struct MainMenuWidgetState {
+    pub visible: bool,
+    // What's in the address field, for joining
+    pub address: String,
+}
+
+widget_bundle!(MainMenuWidgetBundle, MainMenuWidget);
+
+// The address belongs to the text field, but starts over from the config
+// whenever the menu is shown again (i.e. after changing it in the settings)
+pub fn update_main_menu_widget_state(
+    state: Res<State<ClientState>>,
+    config: Res<Config>,
+    mut widget_states: Query<&mut MainMenuWidgetState>,
+) {
+    let visible = state.0 == ClientState::MainMenu;
+    for mut widget_state in widget_states.iter_mut() {
+        if visible && !widget_state.visible {
+            widget_state.address = config.connection_address.to_string();
+        }
+        if widget_state.visible != visible {
+            widget_state.visible = visible;
+        }
+    }
+}
+
+fn on_click(choice: MainMenuChoice) -> OnEvent {
+    return OnEvent::new(
+        move |In(_entity): In<Entity>, event: ResMut<KEvent>, mut choice_evw: EventWriter<MainMenuChoice>| {
+            if let EventType::Click(..) = event.event_type {
+                choice_evw.send(choice.clone());
+            }
+        },
+    );
+}
+
+pub fn main_menu_widget_render(
+    In(entity): In<Entity>,
+    widget_context: Res<KayakWidgetContext>,
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    config: Res<Config>,
+    state_q: Query<&MainMenuWidgetState>,
+) -> bool {
+    let state_entity = widget_context.use_state(
+        &mut commands,
+        entity,
+        MainMenuWidgetState {
+            visible: false,
+            address: config.connection_address.to_string(),
+        },
+    );
+    let Ok(state) = state_q.get(state_entity) else {
+        return true;
+    };
+    if !state.visible {
+        return true;
+    }
+
+    let on_address_change = OnChange::new(
+        move |In((_, value)): In<(Entity, String)>, mut state_q: Query<&mut MainMenuWidgetState>| {
+            if let Ok(mut state) = state_q.get_mut(state_entity) {
+                state.address = value;
+            }
+        },
+    );
+    let on_join = OnEvent::new(
+        move |In(_entity): In<Entity>,
+              event: ResMut<KEvent>,
+              state_q: Query<&MainMenuWidgetState>,
+              mut choice_evw: EventWriter<MainMenuChoice>| {
+            if let (EventType::Click(..), Ok(state)) = (&event.event_type, state_q.get(state_entity)) {
+                choice_evw.send(MainMenuChoice::Join(state.address.clone()));
+            }
+        },
+    );
+
+    let parent_id = Some(entity);
+
+    rsx! {
+        <BackgroundBundle styles={panel_styles()}>
+            <KImageBundle
+                image={KImage(asset_server.load("images/project_calamity_title_image.png"))}
+                styles={KStyle {
+                    width: StyleProp::Value(Units::Pixels(160f32)),
+                    height: StyleProp::Value(Units::Pixels(160f32)),
+                    left: StyleProp::Value(Units::Stretch(1f32)),
+                    right: StyleProp::Value(Units::Stretch(1f32)),
+                    ..Default::default()
+                }}
+            />
+            <KImageBundle
+                image={KImage(asset_server.load("images/project_calamity_wordmark.png"))}
+                styles={KStyle {
+                    width: StyleProp::Value(Units::Pixels(360f32)),
+                    height: StyleProp::Value(Units::Pixels(120f32)),
+                    bottom: StyleProp::Value(Units::Pixels(10f32)),
+                    ..Default::default()
+                }}
+            />
+            <MenuButtonBundle
+                button={MenuButton { text: "SINGLEPLAYER".into() }}
+                on_event={on_click(MainMenuChoice::Singleplayer)}
+            />
+            <MenuButtonBundle
+                button={MenuButton { text: "HOST".into() }}
+                on_event={on_click(MainMenuChoice::Host)}
+            />
+            <TextBoxBundle
+                styles={KStyle {
+                    bottom: StyleProp::Value(Units::Pixels(10f32)),
+                    ..Default::default()
+                }}
+                text_box={TextBoxProps {
+                    value: state.address.clone(),
+                    placeholder: Some("Address, e.g. 127.0.0.1:2360".into()),
+                    ..Default::default()
+                }}
+                on_change={on_address_change}
+            />
+            <MenuButtonBundle
+                button={MenuButton { text: "JOIN".into() }}
+                on_event={on_join}
+            />
+            <MenuButtonBundle
+                button={MenuButton { text: "LOAD GAME".into() }}
+                on_event={on_click(MainMenuChoice::LoadGame)}
+            />
+            <MenuButtonBundle
+                button={MenuButton { text: "SETTINGS".into() }}
+                on_event={on_click(MainMenuChoice::Settings)}
+            />
+            <MenuButtonBundle
+                button={MenuButton { text: "QUIT".into() }}
+                on_event={on_click(MainMenuChoice::Quit)}
+            />
+        </BackgroundBundle>
+    };
+
+    return true;
+}
@@ -1 +1,33 @@
+// The bundle kayak needs to spawn a widget that only has its props and
+// children, for screens that keep everything else in their state
+macro_rules! widget_bundle {
+    ($bundle:ident, $widget:ident) => {
+        #[derive(Bundle)]
+        pub struct $bundle {
+            pub props: $widget,
+            pub styles: KStyle,
+            pub computed_styles: ComputedStyles,
+            pub children: KChildren,
+            pub on_event: OnEvent,
+            pub widget_name: WidgetName,
+        }
+
+        impl Default for $bundle {
+            fn default() -> Self {
+                Self {
+                    props: $widget::default(),
+                    styles: KStyle::default(),
+                    computed_styles: ComputedStyles::default(),
+                    children: KChildren::default(),
+                    on_event: OnEvent::default(),
+                    widget_name: $widget::default().get_name(),
+                }
+            }
+        }
+    };
+}
+
+pub mod lobby;
+pub mod main_menu;
+pub mod settings;
 pub mod turn_timer;
\ No newline at end of file
@@ -0,0 +1,195 @@
+use bevy::prelude::*;
+use kayak_ui::prelude::{widgets::*, *};
+
+use crate::{
+    client::{
+        settings::{SettingsChoice, SettingsForm, SettingsStatus},
+        ui::{components::main_menu::{MenuButton, MenuButtonBundle}, panel_styles},
+        ClientState,
+    },
+    common::config::Config,
+};
+
+#[derive(Component, Clone, PartialEq, Default)]
+pub struct SettingsWidget;
+
+impl Widget for SettingsWidget {}
+
+#[derive(Component, Default, PartialEq, Clone)]
+pub struct SettingsWidgetState {
+    pub visible: bool,
+    pub form: SettingsForm,
+    pub status: Option<String>,
+}
+
+widget_bundle!(SettingsWidgetBundle, SettingsWidget);
+
+// The form starts from the current config every time the screen is opened, so
+// anything left unsaved last time is thrown away
+pub fn update_settings_widget_state(
+    state: Res<State<ClientState>>,
+    config: Res<Config>,
+    mut status: ResMut<SettingsStatus>,
+    mut widget_states: Query<&mut SettingsWidgetState>,
+) {
+    let visible = state.0 == ClientState::Settings;
+    for mut widget_state in widget_states.iter_mut() {
+        if visible && !widget_state.visible {
+            status.0 = None;
+            widget_state.form = SettingsForm::from_config(&config);
+        }
+        if widget_state.visible != visible {
+            widget_state.visible = visible;
+        }
+        if widget_state.status != status.0 {
+            widget_state.status = status.0.clone();
+        }
+    }
+}
+
+fn on_field_change(state_entity: Entity, field: fn(&mut SettingsForm) -> &mut String) -> OnChange {
+    return OnChange::new(
+        move |In((_, value)): In<(Entity, String)>, mut state_q: Query<&mut SettingsWidgetState>| {
+            if let Ok(mut state) = state_q.get_mut(state_entity) {
+                *field(&mut state.form) = value;
+            }
+        },
+    );
+}
+
+fn on_toggle(state_entity: Entity, field: fn(&mut SettingsForm) -> &mut bool) -> OnEvent {
+    return OnEvent::new(
+        move |In(_entity): In<Entity>, event: ResMut<KEvent>, mut state_q: Query<&mut SettingsWidgetState>| {
+            if let (EventType::Click(..), Ok(mut state)) = (&event.event_type, state_q.get_mut(state_entity)) {
+                let value = field(&mut state.form);
+                *value = !*value;
+            }
+        },
+    );
+}
+
+fn on_off(value: bool) -> &'static str {
+    return if value { "ON" } else { "OFF" };
+}
+
+pub fn settings_widget_render(
+    In(entity): In<Entity>,
+    widget_context: Res<KayakWidgetContext>,
+    mut commands: Commands,
+    state_q: Query<&SettingsWidgetState>,
+) -> bool {
+    let state_entity = widget_context.use_state(&mut commands, entity, SettingsWidgetState::default());
+    let Ok(state) = state_q.get(state_entity) else {
+        return true;
+    };
+    if !state.visible {
+        return true;
+    }
+
+    let row_styles = KStyle {
+        layout_type: StyleProp::Value(LayoutType::Row),
+        height: StyleProp::Value(Units::Auto),
+        bottom: StyleProp::Value(Units::Pixels(8f32)),
+        ..Default::default()
+    };
+    let label_styles = KStyle {
+        width: StyleProp::Value(Units::Pixels(120f32)),
+        ..Default::default()
+    };
+
+    let form = &state.form;
+    let fields: [(&str, String, fn(&mut SettingsForm) -> &mut String); 6] = [
+        ("Username", form.username.clone(), |f| &mut f.username),
+        ("Address", form.connection_address.clone(), |f| &mut f.connection_address),
+        ("Board width", form.width.clone(), |f| &mut f.width),
+        ("Board height", form.height.clone(), |f| &mut f.height),
+        ("Scale", form.scale.clone(), |f| &mut f.scale),
+        ("Volume (0-1)", form.volume.clone(), |f| &mut f.volume),
+    ];
+    let toggles: [(String, fn(&mut SettingsForm) -> &mut bool); 3] = [
+        (format!("FULLSCREEN: {}", on_off(form.fullscreen)), |f| &mut f.fullscreen),
+        (format!("VSYNC: {}", on_off(form.vsync)), |f| &mut f.vsync),
+        (format!("DEBUG: {}", on_off(form.debug)), |f| &mut f.debug),
+    ];
+
+    let on_save = OnEvent::new(
+        move |In(_entity): In<Entity>,
+              event: ResMut<KEvent>,
+              state_q: Query<&SettingsWidgetState>,
+              mut choice_evw: EventWriter<SettingsChoice>| {
+            if let (EventType::Click(..), Ok(state)) = (&event.event_type, state_q.get(state_entity)) {
+                choice_evw.send(SettingsChoice::Save(state.form.clone()));
+            }
+        },
+    );
+    let on_back = OnEvent::new(
+        move |In(_entity): In<Entity>, event: ResMut<KEvent>, mut choice_evw: EventWriter<SettingsChoice>| {
+            if let EventType::Click(..) = event.event_type {
+                choice_evw.send(SettingsChoice::Back);
+            }
+        },
+    );
+
+    let parent_id = Some(entity);
+
+    rsx! {
+        <BackgroundBundle styles={panel_styles()}>
+            <TextWidgetBundle
+                styles={KStyle {
+                    bottom: StyleProp::Value(Units::Pixels(10f32)),
+                    ..Default::default()
+                }}
+                text={TextProps {
+                    content: "SETTINGS".into(),
+                    size: 24f32,
+                    alignment: Alignment::Middle,
+                    ..Default::default()
+                }}
+            />
+            {fields.into_iter().for_each(|(label, value, field)| {
+                constructor! {
+                    <ElementBundle styles={row_styles.clone()}>
+                        <TextWidgetBundle
+                            styles={label_styles.clone()}
+                            text={TextProps {
+                                content: label.into(),
+                                size: 16f32,
+                                ..Default::default()
+                            }}
+                        />
+                        <TextBoxBundle
+                            text_box={TextBoxProps { value, ..Default::default() }}
+                            on_change={on_field_change(state_entity, field)}
+                        />
+                    </ElementBundle>
+                }
+            })}
+            {toggles.into_iter().for_each(|(text, field)| {
+                constructor! {
+                    <MenuButtonBundle
+                        button={MenuButton { text }}
+                        on_event={on_toggle(state_entity, field)}
+                    />
+                }
+            })}
+            <MenuButtonBundle
+                button={MenuButton { text: "SAVE".into() }}
+                on_event={on_save}
+            />
+            <MenuButtonBundle
+                button={MenuButton { text: "BACK".into() }}
+                on_event={on_back}
+            />
+            <TextWidgetBundle
+                text={TextProps {
+                    content: state.status.clone().unwrap_or_default(),
+                    size: 12f32,
+                    alignment: Alignment::Middle,
+                    ..Default::default()
+                }}
+            />
+        </BackgroundBundle>
+    };
+
+    return true;
+}
@@ -3,7 +3,10 @@ pub mod components;
 use bevy::prelude::*;
 use kayak_ui::prelude::{*, widgets::*};
 
-use crate::client::{graphical::GameCamera, ui::components::turn_timer::{TurnTimerWidget, TurnTimerWidgetState, turn_timer_widget_render}};
+use crate::{
+    client::{graphical::GameCamera, ui::components::{lobby::{LobbyWidget, LobbyWidgetBundle, LobbyWidgetState, lobby_widget_render, update_lobby_widget_state}, main_menu::{MainMenuWidget, MainMenuWidgetBundle, MainMenuWidgetState, MenuButton, main_menu_widget_render, menu_button_render, update_main_menu_widget_state}, settings::{SettingsWidget, SettingsWidgetBundle, SettingsWidgetState, settings_widget_render, update_settings_widget_state}, turn_timer::{TurnTimerWidget, TurnTimerWidgetState, turn_timer_widget_render}}},
+    common::config::{Config, RunEnvironment},
+};
 
 use super::ClientState;
 
@@ -14,7 +17,10 @@ impl Plugin for UIPlugin {
         app
             .add_plugin(KayakContextPlugin)
             .add_plugin(KayakWidgets)
-            .add_startup_system(startup);
+            .add_startup_system(startup)
+            .add_system(update_lobby_widget_state)
+            .add_system(update_main_menu_widget_state)
+            .add_system(update_settings_widget_state);
     }
 }
 
@@ -24,6 +30,22 @@ impl ProjectCalamityConsts {
     pub const BUTTON_BACKGROUND: Color = Color::Rgba { red: 0f32, green: 0f32, blue: 0f32, alpha: 0.4f32 };
 }
 
+// The box the menu screens are drawn in, in the middle of the window
+pub fn panel_styles() -> KStyle {
+    return KStyle {
+        background_color: StyleProp::Value(ProjectCalamityConsts::BUTTON_BACKGROUND),
+        width: StyleProp::Value(Units::Pixels(400f32)),
+        height: StyleProp::Value(Units::Auto),
+        left: StyleProp::Value(Units::Stretch(1f32)),
+        right: StyleProp::Value(Units::Stretch(1f32)),
+        top: StyleProp::Value(Units::Stretch(1f32)),
+        bottom: StyleProp::Value(Units::Stretch(1f32)),
+        border_radius: Corner::all(20f32).into(),
+        padding: StyleProp::Value(Edge::all(Units::Pixels(20f32))),
+        ..Default::default()
+    };
+}
+
 #[derive(Component, Default, Reflect)]
 pub struct ScalableComponent {
     base_pos: Vec2,
@@ -41,6 +63,7 @@ fn startup(
     mut commands: Commands,
     mut font_mapping: ResMut<FontMapping>,
     asset_server: Res<AssetServer>,
+    config: Res<Config>,
     mut state: ResMut<State<ClientState>>,
 ) {
     font_mapping.set_default(asset_server.load("fonts/atkinson_hyperlegible_regular.kayak_font"));
@@ -61,6 +84,30 @@ fn startup(
         widget_update::<TurnTimerWidget, TurnTimerWidgetState>,
         turn_timer_widget_render,
     );
+    widget_context.add_widget_data::<LobbyWidget, LobbyWidgetState>();
+    widget_context.add_widget_system(
+        LobbyWidget::default().get_name(),
+        widget_update::<LobbyWidget, LobbyWidgetState>,
+        lobby_widget_render,
+    );
+    widget_context.add_widget_data::<MenuButton, ButtonState>();
+    widget_context.add_widget_system(
+        MenuButton::default().get_name(),
+        widget_update::<MenuButton, ButtonState>,
+        menu_button_render,
+    );
+    widget_context.add_widget_data::<MainMenuWidget, MainMenuWidgetState>();
+    widget_context.add_widget_system(
+        MainMenuWidget::default().get_name(),
+        widget_update::<MainMenuWidget, MainMenuWidgetState>,
+        main_menu_widget_render,
+    );
+    widget_context.add_widget_data::<SettingsWidget, SettingsWidgetState>();
+    widget_context.add_widget_system(
+        SettingsWidget::default().get_name(),
+        widget_update::<SettingsWidget, SettingsWidgetState>,
+        settings_widget_render,
+    );
 
     
     
@@ -68,6 +115,9 @@ fn startup(
 
     rsx! {
         <KayakAppBundle>
+            <MainMenuWidgetBundle/>
+            <SettingsWidgetBundle/>
+            <LobbyWidgetBundle/>
             // Note: This is for in-game UI
             // <ElementBundle
             //     styles = {
@@ -84,7 +134,8 @@ fn startup(
 
     commands.spawn((widget_context, EventDispatcher::default()));
 
-    // SKIP UI
-    info!("Skipping UI");
-    state.0 = ClientState::Game;
+    // Replays skip straight past the main menu
+    if config.env == RunEnvironment::Replay {
+        state.0 = ClientState::Replay;
+    }
 }
\ No newline at end of file
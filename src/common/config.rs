@@ -1,33 +1,136 @@
-use std::fs;
+use std::{env, fs, net::SocketAddr};
 
-use bevy::prelude::{warn, Resource};
+use bevy::prelude::Resource;
+use serde::{
+    de::{self, DeserializeOwned},
+    Deserialize, Deserializer, Serialize,
+};
 use toml::Table;
 
-#[derive(Debug, Default, Resource)]
+use super::logic::{neo_gameboard::MapPreset, victory::VictoryMode};
+
+pub const CONFIG_PATH: &str = "./config.toml";
+
+// Runs as a dedicated server whatever config.toml says
+pub const SERVER_FLAG: &str = "--server";
+const DEFAULT_USERNAME: &str = "Player";
+const DEFAULT_PORT: u16 = 2360;
+
+// Anything smaller can't fit four nests, anything bigger takes too long to
+// generate
+pub const MIN_BOARD_SIZE: u32 = 16;
+pub const MAX_BOARD_SIZE: u32 = 512;
+const MIN_PLAYERS: u32 = 2;
+const MAX_PLAYERS: u32 = 4;
+
+#[derive(Debug, Resource)]
 pub struct Config {
     pub env: RunEnvironment,
     pub debug: bool,
-    pub connection_address: String,
+    pub connection_address: SocketAddr,
     pub server_config: ServerConfig,
     pub client_config: ClientConfig,
+    pub replay_config: ReplayConfig,
     pub gameboard_config: GameboardConfig,
+    pub victory_config: VictoryConfig,
+    pub graphics_config: GraphicsConfig,
+    pub audio_config: AudioConfig,
 }
 
-#[derive(Debug, Default, Resource)]
+#[derive(Debug, Deserialize, Resource)]
+#[serde(default)]
 pub struct ServerConfig {
     pub max_players: u32,
 }
 
-#[derive(Debug, Default, Resource)]
+impl Default for ServerConfig {
+    fn default() -> Self {
+        Self {
+            max_players: MIN_PLAYERS,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Resource)]
+#[serde(default)]
 pub struct ClientConfig {
     pub username: String,
 }
 
-#[derive(Debug, Resource)]
+impl Default for ClientConfig {
+    fn default() -> Self {
+        Self {
+            username: DEFAULT_USERNAME.to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Default, Deserialize, Resource)]
+#[serde(default)]
+pub struct ReplayConfig {
+    // The newest replay if not set
+    pub file: Option<String>,
+}
+
+#[derive(Debug, Deserialize, Resource)]
+#[serde(default)]
 pub struct GameboardConfig {
     pub width: u32,
     pub height: u32,
     pub scale: f32,
+    // The same seed always gives the same board. Random if not set
+    pub seed: Option<u64>,
+    // From [mapgen]
+    #[serde(skip)]
+    pub mapgen: MapgenConfig,
+}
+
+// Noise heights run from roughly -1 to 1. Everything below sea_level is
+// water (deep below deep_water), then beaches up to beach, and mountains above
+// mountains. The rest of the land is split by climate and rainfall, which have
+// to be further than biome from 0 to leave grassland
+#[derive(Clone, Debug, Resource)]
+pub struct MapgenConfig {
+    pub preset: MapPreset,
+    pub deep_water: f64,
+    pub sea_level: f64,
+    pub beach: f64,
+    pub mountains: f64,
+    pub biome: f64,
+    pub octaves: usize,
+    pub frequency: f64,
+    // Tiles per unit of noise. Bigger gives bigger landmasses
+    pub zoom: f64,
+}
+
+impl Default for MapgenConfig {
+    fn default() -> Self {
+        return MapPreset::default().mapgen_config();
+    }
+}
+
+impl MapgenConfig {
+    fn validate(&self) -> Result<(), String> {
+        if !(self.deep_water <= self.sea_level
+            && self.sea_level <= self.beach
+            && self.beach <= self.mountains)
+        {
+            return Err(
+                "deep_water, sea_level, beach and mountains must go from lowest to highest"
+                    .to_string(),
+            );
+        }
+        if self.biome < 0f64 {
+            return Err("biome can't be negative".to_string());
+        }
+        if self.octaves < 1 || self.octaves > 32 {
+            return Err("octaves must be between 1 and 32".to_string());
+        }
+        if self.frequency <= 0f64 || self.zoom <= 0f64 {
+            return Err("frequency and zoom must be above 0".to_string());
+        }
+        return Ok(());
+    }
 }
 
 impl Default for GameboardConfig {
@@ -36,97 +139,379 @@ impl Default for GameboardConfig {
             width: 256,
             height: 256,
             scale: 1f32,
+            seed: None,
+            mapgen: MapgenConfig::default(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, Resource, Serialize)]
+#[serde(default)]
+pub struct VictoryConfig {
+    pub mode: VictoryMode,
+    // Domination: the share of currency sites (0 to 1) that has to be held,
+    // and for how many turns in a row
+    pub site_share: f32,
+    pub hold_turns: u32,
+    // Points: the match ends once this many turns have been played
+    pub turn_limit: u32,
+}
+
+impl Default for VictoryConfig {
+    fn default() -> Self {
+        Self {
+            mode: VictoryMode::Conquest,
+            site_share: 0.6,
+            hold_turns: 5,
+            turn_limit: 50,
         }
     }
 }
 
+#[derive(Clone, Debug, Deserialize, Resource)]
+#[serde(default)]
+pub struct GraphicsConfig {
+    pub fullscreen: bool,
+    pub vsync: bool,
+}
+
+impl Default for GraphicsConfig {
+    fn default() -> Self {
+        Self {
+            fullscreen: false,
+            vsync: true,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, Resource)]
+#[serde(default)]
+pub struct AudioConfig {
+    // From 0 to 1
+    pub volume: f32,
+}
+
+impl Default for AudioConfig {
+    fn default() -> Self {
+        Self { volume: 1f32 }
+    }
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        return Config::from_file(ConfigFile::default());
+    }
+}
+
 impl Config {
+    // Anything missing from config.toml is left at its default. Anything that
+    // can't be read is reported with where it is, and the section it's in is
+    // left at its defaults too. This runs before logging is set up, so
+    // problems go straight to stderr
     pub fn load() -> Self {
-        let mut config = Config {
-            ..Default::default()
+        let contents = match fs::read_to_string(CONFIG_PATH) {
+            Ok(contents) => contents,
+            Err(err) => {
+                eprintln!(
+                    "Unable to read {}: {}. Continuing with default values.",
+                    CONFIG_PATH, err
+                );
+                String::new()
+            }
         };
 
-        match fs::read_to_string("./config.toml") {
-            Ok(conf_str) => {
-                println!("Reading config.toml");
-                let toml = conf_str.parse::<Table>().unwrap();
-                let conf_toml = &toml["configuration"];
-
-                // Environment
-                match conf_toml["environment"].as_str() {
-                    Some("client") => config.env = RunEnvironment::Client,
-                    Some("server") => config.env = RunEnvironment::Server,
-                    Some("singleplayer") => config.env = RunEnvironment::Singleplayer,
-                    _ => {
-                        warn!("Unable to read environment due to an invalid character sequence. Continuing with default values.")
-                    }
-                }
-
-                // Debug
-                match conf_toml["debug"].as_str() {
-                    Some("true") => config.debug = true,
-                    Some("false") => config.debug = false,
-                    _ => {
-                        warn!("Unable to read environment due to an invalid character sequence. Continuing with default values.")
-                    }
-                }
-                let conn_adr = conf_toml["connection_address"].to_string();
-
-                // Oh that's some painful shit, yes daddy
-                config.connection_address = conn_adr
-                    .split_at(1)
-                    .1
-                    .split_at(conn_adr.len() - 2)
-                    .0
-                    .to_string();
-
-                // Gameboard config
-                let gameboard_conf_toml = &toml["gameboard"];
-                let mut gameboard_config = GameboardConfig::default();
-
-                gameboard_config.width = gameboard_conf_toml["width"].as_integer().unwrap() as u32;
-                gameboard_config.height =
-                    gameboard_conf_toml["height"].as_integer().unwrap() as u32;
-                gameboard_config.scale = gameboard_conf_toml["scale"].as_float().unwrap() as f32;
-
-                config.gameboard_config = gameboard_config;
-
-                if config.env == RunEnvironment::Server {
-                    let mut server_conf = ServerConfig::default();
-
-                    let conf_toml_server = &toml["server"];
-                    let mut players = conf_toml_server["max_players"].as_integer().unwrap() as u32;
-                    if players > 4 {
-                        players = 4
-                    } else if players < 2 {
-                        players = 2
-                    }
-
-                    server_conf.max_players = players;
-                    config.server_config = server_conf;
-                } else if config.env == RunEnvironment::Client {
-                    let mut client_conf = ClientConfig::default();
-
-                    let conf_toml_client = &toml["client"];
-                    client_conf.username =
-                        conf_toml_client["username"].as_str().unwrap().to_string();
-
-                    config.client_config = client_conf;
-                }
-            }
+        let file = match toml::from_str::<ConfigFile>(&contents) {
+            Ok(file) => file,
             Err(err) => {
-                println!("Error reading config.toml: {:?}", err);
+                eprintln!("Invalid {}: {}", CONFIG_PATH, err);
+                ConfigFile::salvage(&contents)
             }
         };
+        let mut config = Config::from_file(file);
 
+        // Hosting from the main menu starts another copy of the game with
+        // this, using the same config.toml
+        if env::args().any(|arg| arg == SERVER_FLAG) {
+            config.env = RunEnvironment::Server;
+        }
         return config;
     }
+
+    fn from_file(file: ConfigFile) -> Self {
+        let ConfigFile {
+            configuration,
+            mut gameboard,
+            mapgen,
+            mut victory,
+            graphics,
+            mut audio,
+            mut server,
+            mut client,
+            replay,
+        } = file;
+
+        for (name, size) in [
+            ("width", &mut gameboard.width),
+            ("height", &mut gameboard.height),
+        ] {
+            if !(MIN_BOARD_SIZE..=MAX_BOARD_SIZE).contains(size) {
+                eprintln!(
+                    "[gameboard] {} must be from {} to {}.",
+                    name, MIN_BOARD_SIZE, MAX_BOARD_SIZE
+                );
+                *size = (*size).clamp(MIN_BOARD_SIZE, MAX_BOARD_SIZE);
+            }
+        }
+        if gameboard.scale <= 0f32 {
+            eprintln!("[gameboard] scale must be above 0. Continuing with 1.");
+            gameboard.scale = 1f32;
+        }
+        gameboard.mapgen = mapgen.into_config();
+
+        victory.site_share = victory.site_share.clamp(0f32, 1f32);
+        victory.hold_turns = victory.hold_turns.max(1);
+        victory.turn_limit = victory.turn_limit.max(1);
+        audio.volume = audio.volume.clamp(0f32, 1f32);
+        if !(MIN_PLAYERS..=MAX_PLAYERS).contains(&server.max_players) {
+            eprintln!(
+                "[server] max_players must be from {} to {}.",
+                MIN_PLAYERS, MAX_PLAYERS
+            );
+            server.max_players = server.max_players.clamp(MIN_PLAYERS, MAX_PLAYERS);
+        }
+
+        if client.username.trim().is_empty() {
+            eprintln!(
+                "[client] username can't be empty. Continuing as {}.",
+                DEFAULT_USERNAME
+            );
+            client.username = DEFAULT_USERNAME.to_string();
+        }
+
+        return Config {
+            env: configuration.environment,
+            debug: configuration.debug,
+            connection_address: configuration.connection_address,
+            server_config: server,
+            client_config: client,
+            replay_config: replay,
+            gameboard_config: gameboard,
+            victory_config: victory,
+            graphics_config: graphics,
+            audio_config: audio,
+        };
+    }
+}
+
+// config.toml as it's laid out. Every section and key is optional, and keys
+// nothing knows about are ignored
+#[derive(Default, Deserialize)]
+#[serde(default)]
+struct ConfigFile {
+    configuration: ConfigurationSection,
+    gameboard: GameboardConfig,
+    mapgen: MapgenSection,
+    victory: VictoryConfig,
+    graphics: GraphicsConfig,
+    audio: AudioConfig,
+    server: ServerConfig,
+    client: ClientConfig,
+    replay: ReplayConfig,
+}
+
+impl ConfigFile {
+    // Keeps every section that can be read on its own when the file as a
+    // whole can't be
+    fn salvage(contents: &str) -> Self {
+        let Ok(table) = contents.parse::<Table>() else {
+            eprintln!("Continuing with default values.");
+            return ConfigFile::default();
+        };
+
+        return ConfigFile {
+            configuration: section(&table, "configuration"),
+            gameboard: section(&table, "gameboard"),
+            mapgen: section(&table, "mapgen"),
+            victory: section(&table, "victory"),
+            graphics: section(&table, "graphics"),
+            audio: section(&table, "audio"),
+            server: section(&table, "server"),
+            client: section(&table, "client"),
+            replay: section(&table, "replay"),
+        };
+    }
+}
+
+fn section<T: DeserializeOwned + Default>(table: &Table, name: &str) -> T {
+    let Some(value) = table.get(name) else {
+        return T::default();
+    };
+    // Whatever's wrong with it has already been reported, with where it is,
+    // when the whole file was read
+    return match value.clone().try_into::<T>() {
+        Ok(section) => section,
+        Err(_) => {
+            eprintln!("Continuing with default values for [{}].", name);
+            T::default()
+        }
+    };
+}
+
+#[derive(Deserialize)]
+#[serde(default)]
+struct ConfigurationSection {
+    environment: RunEnvironment,
+    #[serde(deserialize_with = "bool_or_string")]
+    debug: bool,
+    connection_address: SocketAddr,
+}
+
+impl Default for ConfigurationSection {
+    fn default() -> Self {
+        Self {
+            environment: RunEnvironment::default(),
+            debug: false,
+            connection_address: SocketAddr::from(([127, 0, 0, 1], DEFAULT_PORT)),
+        }
+    }
+}
+
+// Older configs have debug = "true"
+fn bool_or_string<'de, D: Deserializer<'de>>(deserializer: D) -> Result<bool, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum BoolOrString {
+        Bool(bool),
+        String(String),
+    }
+
+    return match BoolOrString::deserialize(deserializer)? {
+        BoolOrString::Bool(value) => Ok(value),
+        BoolOrString::String(value) => value
+            .parse::<bool>()
+            .map_err(|_| de::Error::custom("expected true or false")),
+    };
+}
+
+// Starts from the preset and overrides whatever else is set
+#[derive(Default, Deserialize)]
+#[serde(default)]
+struct MapgenSection {
+    preset: MapPreset,
+    deep_water: Option<f64>,
+    sea_level: Option<f64>,
+    beach: Option<f64>,
+    mountains: Option<f64>,
+    biome: Option<f64>,
+    octaves: Option<usize>,
+    frequency: Option<f64>,
+    zoom: Option<f64>,
+}
+
+impl MapgenSection {
+    fn into_config(self) -> MapgenConfig {
+        let preset = self.preset.mapgen_config();
+        let mapgen = MapgenConfig {
+            deep_water: self.deep_water.unwrap_or(preset.deep_water),
+            sea_level: self.sea_level.unwrap_or(preset.sea_level),
+            beach: self.beach.unwrap_or(preset.beach),
+            mountains: self.mountains.unwrap_or(preset.mountains),
+            biome: self.biome.unwrap_or(preset.biome),
+            octaves: self.octaves.unwrap_or(preset.octaves),
+            frequency: self.frequency.unwrap_or(preset.frequency),
+            zoom: self.zoom.unwrap_or(preset.zoom),
+            ..preset
+        };
+
+        return match mapgen.validate() {
+            Ok(()) => mapgen,
+            Err(err) => {
+                eprintln!(
+                    "Invalid [mapgen]: {}. Continuing with the {:?} preset.",
+                    err, mapgen.preset
+                );
+                mapgen.preset.mapgen_config()
+            }
+        };
+    }
 }
 
-#[derive(Debug, Default, PartialEq)]
+#[derive(Debug, Default, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
 pub enum RunEnvironment {
     Client,
+    Replay,
     Server,
     #[default]
     Singleplayer,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn missing_sections_are_left_at_their_defaults() {
+        let file = toml::from_str::<ConfigFile>("[client]\nusername = \"Alice\"\n").unwrap();
+        let config = Config::from_file(file);
+
+        assert_eq!(config.client_config.username, "Alice");
+        assert_eq!(config.env, RunEnvironment::Singleplayer);
+        assert!(!config.debug);
+        assert_eq!(
+            config.connection_address,
+            SocketAddr::from(([127, 0, 0, 1], DEFAULT_PORT))
+        );
+        assert_eq!(config.gameboard_config.width, 256);
+        assert_eq!(config.server_config.max_players, MIN_PLAYERS);
+        assert_eq!(config.audio_config.volume, 1f32);
+    }
+
+    #[test]
+    fn debug_can_be_a_string() {
+        for (contents, debug) in [
+            ("[configuration]\ndebug = \"true\"\n", true),
+            ("[configuration]\ndebug = \"false\"\n", false),
+            ("[configuration]\ndebug = true\n", true),
+        ] {
+            let file = toml::from_str::<ConfigFile>(contents).unwrap();
+            assert_eq!(file.configuration.debug, debug);
+        }
+        assert!(toml::from_str::<ConfigFile>("[configuration]\ndebug = \"yes\"\n").is_err());
+    }
+
+    #[test]
+    fn a_bad_address_only_loses_its_own_section() {
+        let contents = "[configuration]\n\
+                        connection_address = \"not an address\"\n\
+                        [client]\n\
+                        username = \"Alice\"\n";
+        assert!(toml::from_str::<ConfigFile>(contents).is_err());
+
+        let config = Config::from_file(ConfigFile::salvage(contents));
+        assert_eq!(
+            config.connection_address,
+            SocketAddr::from(([127, 0, 0, 1], DEFAULT_PORT))
+        );
+        assert_eq!(config.client_config.username, "Alice");
+    }
+
+    #[test]
+    fn unreadable_files_fall_back_to_defaults() {
+        let config = Config::from_file(ConfigFile::salvage("[client\nusername = "));
+        assert_eq!(config.client_config.username, DEFAULT_USERNAME);
+    }
+
+    #[test]
+    fn out_of_range_values_are_clamped() {
+        let contents = "[gameboard]\nwidth = 4\nheight = 10000\n\
+                        [server]\nmax_players = 9\n\
+                        [audio]\nvolume = 3.0\n";
+        let config = Config::from_file(toml::from_str::<ConfigFile>(contents).unwrap());
+
+        assert_eq!(config.gameboard_config.width, MIN_BOARD_SIZE);
+        assert_eq!(config.gameboard_config.height, MAX_BOARD_SIZE);
+        assert_eq!(config.server_config.max_players, MAX_PLAYERS);
+        assert_eq!(config.audio_config.volume, 1f32);
+    }
+}
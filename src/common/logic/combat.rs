@@ -0,0 +1,286 @@
+use bevy::prelude::*;
+
+use super::{
+    neo_gameboard::Gameboard,
+    turn::{ActionFailure, TurnEvent},
+    Archetypes, Unit, UnitAction,
+};
+
+// Splash attacks hit every enemy next to the target for
+// (0.3 * base * splash_multiplier)
+pub const SPLASH_FRACTION: f32 = 0.3;
+
+// Number of steps between two tiles, where a diagonal counts as one step.
+// This matches what Gameboard::adjacent_tiles considers adjacent
+pub fn tile_distance(a: Vec2, b: Vec2) -> i32 {
+    return i32::max(
+        (a.x - b.x).abs().round() as i32,
+        (a.y - b.y).abs().round() as i32,
+    );
+}
+
+// Damage dealt by `attacker` to `defender` for an attack of strength `base`.
+// The attacker's multiplier depends on what the defender is, and the
+// defender's multiplier on what the attacker is
+pub fn calculate_damage(attacker: &Unit, defender: &Unit, base: f32) -> f32 {
+    let attack_multiplier = attack_multiplier(attacker, defender);
+    let defense_multiplier = match attacker.archetype.0 {
+        Archetypes::Magic => defender.defense.magic_multiplier,
+        Archetypes::Science => defender.defense.science_multiplier,
+        Archetypes::None => 1f32,
+    };
+
+    return f32::max(
+        0f32,
+        base * attack_multiplier - defender.defense.base * defense_multiplier,
+    );
+}
+
+// Splash is already a fraction of the attack, so flat defense would soak it up
+// entirely. Only the attacker's multiplier against the defender applies
+pub fn calculate_splash_damage(attacker: &Unit, defender: &Unit) -> f32 {
+    return SPLASH_FRACTION
+        * attacker.attack.base
+        * attacker.attack.splash_multiplier
+        * attack_multiplier(attacker, defender);
+}
+
+fn attack_multiplier(attacker: &Unit, defender: &Unit) -> f32 {
+    return match defender.archetype.0 {
+        Archetypes::Magic => attacker.attack.magic_multiplier,
+        Archetypes::Science => attacker.attack.science_multiplier,
+        Archetypes::None => 1f32,
+    };
+}
+
+// Works out every attack in a stage against the same snapshot of the board,
+// then applies all the damage at once, so the order attacks are listed in
+// never matters
+pub fn resolve_attacks(
+    gameboard: &mut Gameboard,
+    units: &mut [Unit],
+    attacks: Vec<(usize, UnitAction)>,
+    events: &mut Vec<TurnEvent>,
+) {
+    let mut damage = Vec::<(usize, f32, Vec2)>::new();
+    let mut structure_damage = Vec::<(Vec2, f32, Vec2)>::new();
+
+    for (i, action) in attacks {
+        let attacker = &units[i];
+        if tile_distance(attacker.pos, action.action_pos) > attacker.attack.range {
+            events.push(TurnEvent::ActionFailed {
+                action,
+                reason: ActionFailure::OutOfRange,
+            });
+            continue;
+        }
+
+        let Some(target) = units
+            .iter()
+            .position(|u| u.is_alive() && u.pos == action.action_pos)
+        else {
+            // Nobody's standing there, so go for whatever's built there
+            let (x, y) = (action.action_pos.x as usize, action.action_pos.y as usize);
+            match gameboard.tile(x, y).and_then(|t| t.structure()) {
+                Some(structure) if structure.owner != attacker.owner => {
+                    structure_damage.push((action.action_pos, attacker.attack.base, attacker.pos))
+                }
+                Some(_) => events.push(TurnEvent::ActionFailed {
+                    action,
+                    reason: ActionFailure::InvalidTarget,
+                }),
+                None => events.push(TurnEvent::ActionFailed {
+                    action,
+                    reason: ActionFailure::NoTarget,
+                }),
+            }
+            continue;
+        };
+        if units[target].owner == attacker.owner {
+            events.push(TurnEvent::ActionFailed {
+                action,
+                reason: ActionFailure::InvalidTarget,
+            });
+            continue;
+        }
+
+        damage.push((
+            target,
+            calculate_damage(attacker, &units[target], attacker.attack.base),
+            attacker.pos,
+        ));
+
+        if attacker.attack.splash {
+            for (j, unit) in units.iter().enumerate() {
+                if j != target
+                    && unit.is_alive()
+                    && unit.owner != attacker.owner
+                    && tile_distance(unit.pos, action.action_pos) == 1
+                {
+                    damage.push((j, calculate_splash_damage(attacker, unit), attacker.pos));
+                }
+            }
+        }
+    }
+
+    for (i, amount, source) in damage {
+        units[i].health.0 -= amount;
+        events.push(TurnEvent::Damaged {
+            pos: units[i].pos,
+            amount,
+            source: Some(source),
+        });
+    }
+
+    for (pos, amount, source) in structure_damage {
+        let Some(tile) = gameboard.tile_mut(pos.x as usize, pos.y as usize) else {
+            continue;
+        };
+        let Some(structure) = tile.structure_mut() else {
+            // Already knocked down by an earlier attack this stage
+            continue;
+        };
+
+        structure.health.0 -= amount;
+        events.push(TurnEvent::StructureDamaged {
+            pos,
+            amount,
+            source: Some(source),
+        });
+        if structure.health.0 <= 0f32 {
+            let structure = tile.remove_structure().unwrap();
+            events.push(TurnEvent::StructureDestroyed {
+                pos,
+                kind: structure.kind,
+                owner: structure.owner,
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::logic::{
+        testing::{grass_board, order, unit},
+        Attack, TeamColour, TurnExecuteStages, UnitActions,
+    };
+
+    fn attack(units: &mut [Unit], attacks: Vec<(usize, (f32, f32))>) -> Vec<TurnEvent> {
+        let attacks = attacks
+            .into_iter()
+            .map(|(i, target)| {
+                let from = (units[i].pos.x, units[i].pos.y);
+                (
+                    i,
+                    order(
+                        UnitActions::Attack,
+                        TurnExecuteStages::MidTurn,
+                        from,
+                        target,
+                    ),
+                )
+            })
+            .collect();
+        let mut events = Vec::new();
+        resolve_attacks(&mut grass_board(8, 8), units, attacks, &mut events);
+        return events;
+    }
+
+    fn failure(events: &[TurnEvent]) -> Option<ActionFailure> {
+        return events.iter().find_map(|event| match event {
+            TurnEvent::ActionFailed { reason, .. } => Some(reason.clone()),
+            _ => None,
+        });
+    }
+
+    #[test]
+    fn archetypes_pick_the_multipliers() {
+        let mut attacker = unit(TeamColour::Blue, 0f32, 0f32);
+        attacker.archetype.0 = Archetypes::Magic;
+        attacker.attack.magic_multiplier = 0.5;
+        attacker.attack.science_multiplier = 2f32;
+        let mut defender = unit(TeamColour::Red, 1f32, 0f32);
+        defender.defense.base = 1f32;
+        defender.defense.magic_multiplier = 3f32;
+        defender.defense.science_multiplier = 0f32;
+
+        // Against science, the attacker's science multiplier and the
+        // defender's magic one
+        defender.archetype.0 = Archetypes::Science;
+        assert_eq!(
+            calculate_damage(&attacker, &defender, 4f32),
+            4f32 * 2f32 - 3f32
+        );
+
+        defender.archetype.0 = Archetypes::Magic;
+        assert_eq!(calculate_damage(&attacker, &defender, 4f32), 0f32);
+
+        defender.archetype.0 = Archetypes::None;
+        assert_eq!(calculate_damage(&attacker, &defender, 4f32), 4f32 - 3f32);
+    }
+
+    #[test]
+    fn attacks_need_an_enemy_in_range() {
+        let mut units = vec![
+            unit(TeamColour::Blue, 0f32, 0f32),
+            unit(TeamColour::Blue, 1f32, 0f32),
+            unit(TeamColour::Red, 3f32, 0f32),
+        ];
+
+        let out_of_range = attack(&mut units, vec![(0, (3f32, 0f32))]);
+        assert_eq!(failure(&out_of_range), Some(ActionFailure::OutOfRange));
+        let friendly = attack(&mut units, vec![(0, (1f32, 0f32))]);
+        assert_eq!(failure(&friendly), Some(ActionFailure::InvalidTarget));
+        let empty = attack(&mut units, vec![(0, (0f32, 1f32))]);
+        assert_eq!(failure(&empty), Some(ActionFailure::NoTarget));
+
+        assert!(units.iter().all(|u| u.health.0 == 10f32));
+    }
+
+    #[test]
+    fn attacks_land_at_the_same_time() {
+        let mut units = vec![
+            unit(TeamColour::Blue, 0f32, 0f32),
+            unit(TeamColour::Red, 1f32, 0f32),
+        ];
+        units[0].attack.base = 20f32;
+        units[1].attack.base = 20f32;
+
+        attack(&mut units, vec![(0, (1f32, 0f32)), (1, (0f32, 0f32))]);
+
+        // Both still hit, even though either would kill the other first
+        assert!(!units[0].is_alive());
+        assert!(!units[1].is_alive());
+    }
+
+    // With defense 1 against a splash of 0.3 * 2 * 0.5, subtracting defense
+    // would leave nothing. Splash skips it on purpose
+    #[test]
+    fn splash_hits_adjacent_enemies_through_defense() {
+        let mut units = vec![
+            unit(TeamColour::Blue, 0f32, 0f32),
+            unit(TeamColour::Red, 2f32, 0f32),
+            unit(TeamColour::Red, 3f32, 1f32),
+            unit(TeamColour::Blue, 2f32, 1f32),
+            unit(TeamColour::Red, 4f32, 0f32),
+        ];
+        units[0].attack = Attack {
+            base: 2f32,
+            range: 2,
+            splash: true,
+            splash_multiplier: 0.5,
+            magic_multiplier: 1f32,
+            science_multiplier: 1f32,
+        };
+
+        attack(&mut units, vec![(0, (2f32, 0f32))]);
+
+        let splash = 0.3 * 2f32 * 0.5;
+        assert_eq!(units[1].health.0, 10f32 - (2f32 - 1f32));
+        assert_eq!(units[2].health.0, 10f32 - splash);
+        // Allies and anything further away are left alone
+        assert_eq!(units[3].health.0, 10f32);
+        assert_eq!(units[4].health.0, 10f32);
+    }
+}
@@ -0,0 +1,283 @@
+use std::collections::HashMap;
+
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use super::{
+    combat::tile_distance, neo_gameboard::Gameboard, turn::TurnEvent, Archetypes, Cost, PlayerTeam,
+    TileFeatures, Unit,
+};
+
+// How close a unit has to be to a currency site to take it over
+pub const CAPTURE_RANGE: i32 = 1;
+// What every controlled site pays out each turn
+pub const SITE_INCOME: u32 = 1;
+// What every player starts the match with
+pub const STARTING_BALANCE: Cost = Cost {
+    magic: 2,
+    science: 2,
+};
+
+// How much of each currency every player has. Clients only know their own
+#[derive(Clone, Debug, Default, Deserialize, Resource, Serialize)]
+pub struct Economy {
+    balances: HashMap<PlayerTeam, Cost>,
+}
+
+impl Economy {
+    pub fn balance(&self, team: &PlayerTeam) -> Cost {
+        return self.balances.get(team).copied().unwrap_or(STARTING_BALANCE);
+    }
+
+    pub fn set_balance(&mut self, team: &PlayerTeam, balance: Cost) {
+        self.balances.insert(team.clone(), balance);
+    }
+
+    pub fn can_afford(&self, team: &PlayerTeam, cost: Cost) -> bool {
+        let balance = self.balance(team);
+        return balance.magic >= cost.magic && balance.science >= cost.science;
+    }
+
+    // Takes the cost out of the team's balance, if they have enough
+    pub fn spend(&mut self, team: &PlayerTeam, cost: Cost) -> bool {
+        if !self.can_afford(team, cost) {
+            return false;
+        }
+
+        let balance = self.balance(team);
+        self.set_balance(
+            team,
+            Cost {
+                magic: balance.magic - cost.magic,
+                science: balance.science - cost.science,
+            },
+        );
+        return true;
+    }
+
+    pub fn earn(&mut self, team: &PlayerTeam, amount: Cost) {
+        let balance = self.balance(team);
+        self.set_balance(
+            team,
+            Cost {
+                magic: balance.magic + amount.magic,
+                science: balance.science + amount.science,
+            },
+        );
+    }
+}
+
+// What a site of the given archetype pays out. Sites without one pay a bit of
+// both
+fn site_income(archetype: &Archetypes) -> Cost {
+    return match archetype {
+        Archetypes::Magic => Cost {
+            magic: SITE_INCOME,
+            science: 0,
+        },
+        Archetypes::Science => Cost {
+            magic: 0,
+            science: SITE_INCOME,
+        },
+        Archetypes::None => Cost {
+            magic: SITE_INCOME,
+            science: SITE_INCOME,
+        },
+    };
+}
+
+// Run once everything else in the turn is done. A site changes hands when
+// exactly one team has a unit on or next to it, contested and abandoned sites
+// stay with whoever had them. Then every site pays its controller
+pub fn resolve_economy(
+    gameboard: &mut Gameboard,
+    units: &[Unit],
+    economy: &mut Economy,
+    events: &mut Vec<TurnEvent>,
+) {
+    let mut income = Vec::<(PlayerTeam, Cost)>::new();
+
+    let (width, height) = (gameboard.x() as usize, gameboard.y() as usize);
+    for x in 0..width {
+        for y in 0..height {
+            let tile = gameboard.tile_mut(x, y).unwrap();
+            let pos = tile.pos();
+            let Some(feature) = tile.feature_mut() else {
+                continue;
+            };
+            let TileFeatures::CurrencySite(archetype) = &feature.feature else {
+                continue;
+            };
+
+            let mut nearby = Vec::<PlayerTeam>::new();
+            for unit in units
+                .iter()
+                .filter(|u| u.is_alive() && tile_distance(u.pos, pos) <= CAPTURE_RANGE)
+            {
+                if !nearby.contains(&unit.owner) {
+                    nearby.push(unit.owner.clone());
+                }
+            }
+            if let [team] = &nearby[..] {
+                if feature.controller.as_ref() != Some(team) {
+                    feature.controller = Some(team.clone());
+                    events.push(TurnEvent::SiteCaptured {
+                        pos,
+                        owner: team.clone(),
+                    });
+                }
+            }
+
+            if let Some(controller) = &feature.controller {
+                let amount = site_income(&archetype.0);
+                match income.iter_mut().find(|(team, _)| team == controller) {
+                    Some((_, total)) => {
+                        total.magic += amount.magic;
+                        total.science += amount.science;
+                    }
+                    None => income.push((controller.clone(), amount)),
+                }
+            }
+        }
+    }
+
+    for (team, amount) in income {
+        economy.earn(&team, amount);
+        events.push(TurnEvent::Income {
+            owner: team,
+            amount,
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::logic::{
+        testing::{grass_board, unit},
+        Archetype, TeamColour,
+    };
+
+    // A board with a site at (3, 3) and another at (6, 6)
+    fn board(archetype: Archetypes) -> Gameboard {
+        let mut gameboard = grass_board(8, 8);
+        for (x, y) in [(3, 3), (6, 6)] {
+            gameboard
+                .tile_mut(x, y)
+                .unwrap()
+                .set_feature(TileFeatures::CurrencySite(Archetype(archetype.clone())));
+        }
+        return gameboard;
+    }
+
+    fn controller(gameboard: &Gameboard, x: usize, y: usize) -> Option<PlayerTeam> {
+        return gameboard.tile(x, y)?.feature()?.controller.clone();
+    }
+
+    #[test]
+    fn a_lone_team_in_range_captures_the_site() {
+        let mut gameboard = board(Archetypes::Magic);
+        let mut economy = Economy::default();
+        let units = vec![
+            unit(TeamColour::Blue, 4f32, 4f32),
+            // Too far from either site
+            unit(TeamColour::Red, 0f32, 0f32),
+        ];
+        let blue = PlayerTeam(TeamColour::Blue);
+
+        let mut events = Vec::new();
+        resolve_economy(&mut gameboard, &units, &mut economy, &mut events);
+
+        assert_eq!(controller(&gameboard, 3, 3), Some(blue.clone()));
+        assert_eq!(controller(&gameboard, 6, 6), None);
+        assert_eq!(
+            events,
+            vec![
+                TurnEvent::SiteCaptured {
+                    pos: Vec2::new(3f32, 3f32),
+                    owner: blue.clone(),
+                },
+                TurnEvent::Income {
+                    owner: blue.clone(),
+                    amount: Cost {
+                        magic: SITE_INCOME,
+                        science: 0,
+                    },
+                },
+            ]
+        );
+        assert_eq!(
+            economy.balance(&blue),
+            Cost {
+                magic: STARTING_BALANCE.magic + SITE_INCOME,
+                science: STARTING_BALANCE.science,
+            }
+        );
+    }
+
+    #[test]
+    fn contested_sites_stay_with_their_holder() {
+        let mut gameboard = board(Archetypes::Science);
+        let mut economy = Economy::default();
+        let red = PlayerTeam(TeamColour::Red);
+        gameboard
+            .tile_mut(3, 3)
+            .unwrap()
+            .feature_mut()
+            .unwrap()
+            .controller = Some(red.clone());
+        let units = vec![
+            unit(TeamColour::Blue, 3f32, 4f32),
+            unit(TeamColour::Red, 2f32, 2f32),
+        ];
+
+        let mut events = Vec::new();
+        resolve_economy(&mut gameboard, &units, &mut economy, &mut events);
+
+        assert_eq!(controller(&gameboard, 3, 3), Some(red.clone()));
+        assert!(!events
+            .iter()
+            .any(|e| matches!(e, TurnEvent::SiteCaptured { .. })));
+        assert_eq!(
+            economy.balance(&red).science,
+            STARTING_BALANCE.science + SITE_INCOME
+        );
+        assert_eq!(
+            economy.balance(&PlayerTeam(TeamColour::Blue)),
+            STARTING_BALANCE
+        );
+    }
+
+    #[test]
+    fn every_site_held_pays_its_controller() {
+        let mut gameboard = board(Archetypes::None);
+        let mut economy = Economy::default();
+        let blue = PlayerTeam(TeamColour::Blue);
+        let units = vec![
+            unit(TeamColour::Blue, 3f32, 3f32),
+            unit(TeamColour::Blue, 7f32, 7f32),
+        ];
+
+        let mut events = Vec::new();
+        resolve_economy(&mut gameboard, &units, &mut economy, &mut events);
+        // Nobody near the sites any more, but they're still held
+        resolve_economy(&mut gameboard, &[], &mut economy, &mut events);
+
+        assert_eq!(controller(&gameboard, 6, 6), Some(blue.clone()));
+        assert_eq!(
+            economy.balance(&blue),
+            Cost {
+                magic: STARTING_BALANCE.magic + 4 * SITE_INCOME,
+                science: STARTING_BALANCE.science + 4 * SITE_INCOME,
+            }
+        );
+        // Paid as one lump per turn
+        assert_eq!(
+            events
+                .iter()
+                .filter(|e| matches!(e, TurnEvent::Income { .. }))
+                .count(),
+            2
+        );
+    }
+}
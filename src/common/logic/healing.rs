@@ -0,0 +1,214 @@
+use bevy::prelude::*;
+
+use super::{
+    combat::{tile_distance, SPLASH_FRACTION},
+    turn::{ActionFailure, TurnEvent},
+    Unit, UnitAction,
+};
+
+// Heals go out after the stage's damage has landed. A unit destroyed this
+// stage stays destroyed, and nobody can be healed past their max health
+pub fn resolve_heals(
+    units: &mut [Unit],
+    heals: Vec<(usize, UnitAction)>,
+    events: &mut Vec<TurnEvent>,
+) {
+    let mut healing = Vec::<(usize, f32, Vec2)>::new();
+
+    for (i, action) in heals {
+        let healer = &units[i];
+        let Some(heal) = healer.heal.as_ref() else {
+            events.push(TurnEvent::ActionFailed {
+                action,
+                reason: ActionFailure::MissingAbility,
+            });
+            continue;
+        };
+        let Some(target) = units
+            .iter()
+            .position(|u| u.is_alive() && u.pos == action.action_pos)
+        else {
+            events.push(TurnEvent::ActionFailed {
+                action,
+                reason: ActionFailure::NoTarget,
+            });
+            continue;
+        };
+        if units[target].owner != healer.owner {
+            events.push(TurnEvent::ActionFailed {
+                action,
+                reason: ActionFailure::InvalidTarget,
+            });
+            continue;
+        }
+        if tile_distance(healer.pos, action.action_pos) > heal.range {
+            events.push(TurnEvent::ActionFailed {
+                action,
+                reason: ActionFailure::OutOfRange,
+            });
+            continue;
+        }
+
+        healing.push((target, heal.base, healer.pos));
+
+        if heal.splash {
+            for (j, unit) in units.iter().enumerate() {
+                if j != target
+                    && unit.is_alive()
+                    && unit.owner == healer.owner
+                    && tile_distance(unit.pos, action.action_pos) == 1
+                {
+                    healing.push((j, SPLASH_FRACTION * heal.base, healer.pos));
+                }
+            }
+        }
+    }
+
+    for (i, amount, source) in healing {
+        let unit = &mut units[i];
+        let healed = f32::min(amount, unit.max_health.0 - unit.health.0);
+        if !unit.is_alive() || healed <= 0f32 {
+            continue;
+        }
+
+        unit.health.0 += healed;
+        events.push(TurnEvent::Healed {
+            pos: unit.pos,
+            amount: healed,
+            source: Some(source),
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::logic::{
+        testing::{order, unit},
+        HealAction, Health, TeamColour, TurnExecuteStages, UnitActions,
+    };
+
+    fn healer(x: f32, y: f32, splash: bool) -> Unit {
+        let mut healer = unit(TeamColour::Blue, x, y);
+        healer.heal = Some(HealAction {
+            base: 4f32,
+            splash,
+            range: 1,
+        });
+        return healer;
+    }
+
+    fn wounded(team: TeamColour, x: f32, y: f32, health: f32) -> Unit {
+        let mut wounded = unit(team, x, y);
+        wounded.health = Health(health);
+        return wounded;
+    }
+
+    fn heal(units: &mut [Unit], i: usize, target: (f32, f32)) -> Vec<TurnEvent> {
+        let from = (units[i].pos.x, units[i].pos.y);
+        let heals = vec![(
+            i,
+            order(UnitActions::Heal, TurnExecuteStages::MidTurn, from, target),
+        )];
+        let mut events = Vec::new();
+        resolve_heals(units, heals, &mut events);
+        return events;
+    }
+
+    fn failure(events: &[TurnEvent]) -> Option<ActionFailure> {
+        return events.iter().find_map(|event| match event {
+            TurnEvent::ActionFailed { reason, .. } => Some(reason.clone()),
+            _ => None,
+        });
+    }
+
+    #[test]
+    fn heals_need_the_target_in_range() {
+        let mut units = vec![
+            healer(0f32, 0f32, false),
+            wounded(TeamColour::Blue, 2f32, 0f32, 5f32),
+        ];
+        let events = heal(&mut units, 0, (2f32, 0f32));
+
+        assert_eq!(failure(&events), Some(ActionFailure::OutOfRange));
+        assert_eq!(units[1].health, Health(5f32));
+    }
+
+    #[test]
+    fn heals_stop_at_max_health() {
+        let mut units = vec![
+            healer(0f32, 0f32, false),
+            wounded(TeamColour::Blue, 1f32, 0f32, 8f32),
+        ];
+        let events = heal(&mut units, 0, (1f32, 0f32));
+
+        assert_eq!(units[1].health, Health(10f32));
+        assert_eq!(
+            events,
+            vec![TurnEvent::Healed {
+                pos: Vec2::new(1f32, 0f32),
+                amount: 2f32,
+                source: Some(Vec2::new(0f32, 0f32)),
+            }]
+        );
+
+        // Nothing to heal, so nothing to report
+        assert!(heal(&mut units, 0, (1f32, 0f32)).is_empty());
+    }
+
+    #[test]
+    fn splash_heals_adjacent_allies() {
+        let mut units = vec![
+            healer(0f32, 0f32, true),
+            wounded(TeamColour::Blue, 1f32, 0f32, 1f32),
+            wounded(TeamColour::Blue, 2f32, 0f32, 1f32),
+            wounded(TeamColour::Red, 1f32, 1f32, 1f32),
+            wounded(TeamColour::Blue, 3f32, 0f32, 1f32),
+        ];
+        heal(&mut units, 0, (1f32, 0f32));
+
+        assert_eq!(units[1].health, Health(5f32));
+        assert_eq!(units[2].health, Health(1f32 + SPLASH_FRACTION * 4f32));
+        // Enemies and anyone further away are left alone
+        assert_eq!(units[3].health, Health(1f32));
+        assert_eq!(units[4].health, Health(1f32));
+    }
+
+    #[test]
+    fn the_dead_and_the_missing_cant_be_healed() {
+        let mut units = vec![
+            healer(0f32, 0f32, false),
+            wounded(TeamColour::Blue, 1f32, 0f32, 0f32),
+        ];
+
+        let events = heal(&mut units, 0, (1f32, 0f32));
+        assert_eq!(failure(&events), Some(ActionFailure::NoTarget));
+        assert_eq!(units[1].health, Health(0f32));
+
+        let events = heal(&mut units, 0, (0f32, 1f32));
+        assert_eq!(failure(&events), Some(ActionFailure::NoTarget));
+    }
+
+    #[test]
+    fn enemies_cant_be_healed() {
+        let mut units = vec![
+            healer(0f32, 0f32, false),
+            wounded(TeamColour::Red, 1f32, 0f32, 5f32),
+        ];
+        let events = heal(&mut units, 0, (1f32, 0f32));
+
+        assert_eq!(failure(&events), Some(ActionFailure::InvalidTarget));
+        assert_eq!(units[1].health, Health(5f32));
+    }
+
+    #[test]
+    fn units_without_a_heal_cant_heal() {
+        let mut units = vec![
+            unit(TeamColour::Blue, 0f32, 0f32),
+            wounded(TeamColour::Blue, 1f32, 0f32, 5f32),
+        ];
+        let events = heal(&mut units, 0, (1f32, 0f32));
+
+        assert_eq!(failure(&events), Some(ActionFailure::MissingAbility));
+    }
+}
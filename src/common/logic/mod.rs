@@ -1,32 +1,76 @@
+pub mod combat;
+pub mod economy;
+pub mod healing;
 pub mod neo_gameboard;
+pub mod pathfinding;
+pub mod placement;
+pub mod recruitment;
+pub mod replay;
+pub mod rng;
+pub mod save;
+pub mod structures;
+#[cfg(test)]
+pub mod testing;
+pub mod turn;
 pub mod units;
+pub mod upgrades;
+pub mod victory;
+pub mod vision;
 
 use bevy::prelude::*;
-use rand::{rngs::ThreadRng, Rng};
 use serde::{Deserialize, Serialize};
 
 use self::{
-    neo_gameboard::{Gameboard, Tile},
-    units::UnitID,
+    economy::Economy,
+    neo_gameboard::Gameboard,
+    recruitment::RecruitQueue,
+    rng::seed_game_rng,
+    structures::{Structure, StructureKind},
+    turn::{resolve_turns, PendingOrders, ResolveTurnEvent, TurnResolvedEvent},
+    units::{UnitID, UnitRegistry, UNITS_PATH},
+    victory::MatchState,
 };
 pub struct GameLogicPlugin;
 
 impl Plugin for GameLogicPlugin {
     fn build(&self, app: &mut App) {
-        app.register_type::<Archetype>()
+        // Nothing can be spawned without these, so there's no point carrying on
+        let unit_registry = match UnitRegistry::load(UNITS_PATH) {
+            Ok(registry) => registry,
+            Err(err) => panic!("Unable to load {}: {}", UNITS_PATH, err),
+        };
+
+        app.insert_resource(unit_registry)
+            .register_type::<Archetype>()
             .register_type::<Attack>()
             .register_type::<Defense>()
+            .register_type::<HealAction>()
             .register_type::<Health>()
             .register_type::<Movement>()
             .register_type::<PlayerTeam>()
+            .register_type::<Structure>()
+            .register_type::<StructureKind>()
             .register_type::<TileFeature>()
             .register_type::<TileFeatures>()
             .register_type::<TurnExecuteStage>()
             .register_type::<Unit>()
-            .register_type::<UnitAction>();
+            .register_type::<UnitAction>()
+            .register_type::<UnitID>()
+            .add_event::<ResolveTurnEvent>()
+            .add_event::<TurnResolvedEvent>()
+            .init_resource::<Economy>()
+            .init_resource::<MatchState>()
+            .init_resource::<PendingOrders>()
+            .init_resource::<RecruitQueue>()
+            .init_resource::<TurnCounter>()
+            .add_startup_system(seed_game_rng.in_base_set(StartupSet::PreStartup))
+            .add_system(resolve_turns);
     }
 }
 
+#[derive(Debug, Default, Resource)]
+pub struct TurnCounter(pub u32);
+
 #[derive(Clone, Copy, Debug, Default, Deserialize, FromReflect, PartialEq, Reflect, Serialize)]
 pub enum Terrain {
     Desert,
@@ -40,18 +84,23 @@ pub enum Terrain {
     Water,
 }
 
+// Every terrain has this many sprites in the tile atlas to pick from
+pub const TERRAIN_VARIANTS: u8 = 4;
+
 impl Terrain {
-    fn to_atlas_index(&self, rand: &mut ThreadRng) -> u16 {
-        match self {
-            Terrain::Desert => return rand.gen_range(16..20),
-            Terrain::Forest => return rand.gen_range(24..28),
-            Terrain::Grass => return rand.gen_range(0..4),
-            Terrain::Jungle => return rand.gen_range(12..16),
-            Terrain::Mountains => return rand.gen_range(20..24),
-            Terrain::Savanna => return rand.gen_range(28..32),
-            Terrain::ShallowWater => return rand.gen_range(8..12),
-            Terrain::Water => return rand.gen_range(4..8),
+    // `variant` picks between the terrain's sprites, see Tile::variant
+    pub fn to_atlas_index(&self, variant: u8) -> u16 {
+        let first = match self {
+            Terrain::Desert => 16,
+            Terrain::Forest => 24,
+            Terrain::Grass => 0,
+            Terrain::Jungle => 12,
+            Terrain::Mountains => 20,
+            Terrain::Savanna => 28,
+            Terrain::ShallowWater => 8,
+            Terrain::Water => 4,
         };
+        return first + (variant % TERRAIN_VARIANTS) as u16;
     }
 }
 
@@ -60,7 +109,7 @@ struct UnitActionBundle {
     unit_action: UnitAction,
 }
 
-#[derive(Clone, Component, Debug, Deserialize, Reflect, Serialize)]
+#[derive(Clone, Component, Debug, Deserialize, PartialEq, Reflect, Serialize)]
 pub struct UnitAction {
     pub action_type: UnitActions,
     pub turn_stage: TurnExecuteStage,
@@ -73,7 +122,9 @@ pub enum UnitActions {
     Move,
     Attack,
     Heal,
-    Build,
+    Build(StructureKind),
+    // Promote the unit to another type, see UnitRegistry::upgrades_for
+    Upgrade(UnitID),
 }
 
 #[derive(Bundle, Default, Reflect, FromReflect)]
@@ -86,7 +137,10 @@ pub struct Unit {
     pub id: UnitID,
     pub pos: Vec2,
     pub health: Health,
+    // Healing never takes a unit above this
+    pub max_health: Health,
     pub attack: Attack,
+    pub heal: Option<HealAction>,
     pub defense: Defense,
     pub movement: Movement,
     pub turn_execute_stage: TurnExecuteStage,
@@ -95,46 +149,36 @@ pub struct Unit {
 }
 
 impl Unit {
+    pub fn is_alive(&self) -> bool {
+        return self.health.0 > 0f32;
+    }
+
     pub fn calculate_traversible_tiles(&self, gameboard: &Gameboard, movement: f32) -> Vec<Vec2> {
-        let mut tile_movement_costs =
-            vec![vec![None::<f32>; gameboard.y() as usize]; gameboard.x() as usize];
-
-        let (x, y) = (self.pos.x as usize, self.pos.y as usize);
-        tile_movement_costs[x][y] = Some(0f32);
-
-        // Recursion me harder
-        gameboard.tile(x, y).unwrap().propogate_movement_costs(
-            vec![gameboard.tile(x, y).unwrap()],
-            &mut tile_movement_costs,
-            gameboard,
-        );
-
-        let mut movable_tiles = Vec::<Vec2>::new();
-        for x in 0..tile_movement_costs.len() {
-            for y in 0..tile_movement_costs[0].len() {
-                print!(" | {:?}", tile_movement_costs[x][y].unwrap());
-                if tile_movement_costs[x][y].unwrap() <= movement {
-                    movable_tiles.push(gameboard.tile(x, y).unwrap().pos());
-                }
-            }
-            println!("");
-        }
-        println!("\n\n\n NEW TABLE \n\n\n");
-
-        // Let's quickly print our thingy
-
-        return movable_tiles;
+        return gameboard
+            .reachable_tiles(self.pos, &self.owner, movement)
+            .into_iter()
+            .map(|(pos, _)| pos)
+            .collect();
     }
 }
 
-#[derive(Clone, Component, Debug, Default, Deserialize, FromReflect, Reflect, Serialize)]
+#[derive(
+    Clone, Component, Debug, Default, Deserialize, FromReflect, PartialEq, Reflect, Serialize,
+)]
 pub struct Health(pub f32);
 
+// What something costs in each currency
+#[derive(Clone, Copy, Debug, Default, Deserialize, FromReflect, PartialEq, Reflect, Serialize)]
+pub struct Cost {
+    pub magic: u32,
+    pub science: u32,
+}
+
 #[derive(Clone, Component, Debug, Default, Deserialize, FromReflect, Reflect, Serialize)]
 pub struct Attack {
     pub base: f32,
     pub range: i32,
-    pub splash: bool, // (0.3 * base) per adjacent unit
+    pub splash: bool, // (0.3 * base * splash_multiplier) per adjacent enemy
     pub splash_multiplier: f32,
     pub magic_multiplier: f32,
     pub science_multiplier: f32,
@@ -156,7 +200,18 @@ pub struct Movement(pub i32);
 pub struct TurnExecuteStage(pub TurnExecuteStages);
 
 #[derive(
-    Clone, Component, Debug, Default, Deserialize, FromReflect, PartialEq, Reflect, Serialize,
+    Clone,
+    Component,
+    Debug,
+    Default,
+    Deserialize,
+    Eq,
+    FromReflect,
+    Ord,
+    PartialEq,
+    PartialOrd,
+    Reflect,
+    Serialize,
 )]
 pub enum TurnExecuteStages {
     PreTurn,
@@ -180,11 +235,11 @@ pub enum Archetypes {
     None,
 }
 
-#[derive(Component, Reflect, FromReflect)]
-struct HealAction {
-    base: f32,
-    splash: bool, // (0.3 * base) per adjacent unit
-    range: i32,
+#[derive(Clone, Component, Debug, Default, Deserialize, FromReflect, Reflect, Serialize)]
+pub struct HealAction {
+    pub base: f32,
+    pub splash: bool, // (0.3 * base) per adjacent unit
+    pub range: i32,
 }
 
 #[derive(Clone, Component, Debug, Deserialize, FromReflect, PartialEq, Reflect, Serialize)]
@@ -192,6 +247,9 @@ pub struct TileFeature {
     pub pos: [i32; 2],
     pub feature: TileFeatures,
     pub visible_to_players: Vec<PlayerTeam>,
+    // Who holds a currency site, see economy::resolve_economy
+    #[serde(default)]
+    pub controller: Option<PlayerTeam>,
 }
 
 #[derive(Clone, Component, Debug, Deserialize, FromReflect, PartialEq, Reflect, Serialize)]
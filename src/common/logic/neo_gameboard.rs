@@ -1,16 +1,23 @@
 use bevy::prelude::*;
-use bevy_fast_tilemap::{Map, MapReadyEvent};
 use noise::{
     utils::{NoiseMap, NoiseMapBuilder, PlaneMapBuilder},
-    Fbm, Perlin,
+    Fbm, MultiFractal, Perlin,
 };
 use rand::Rng;
+use serde::{Deserialize, Serialize};
 
-use crate::common::config::Config;
+use crate::common::config::{Config, GameboardConfig, MapgenConfig, RunEnvironment};
 
-use super::{TeamColour, Terrain, TileFeature};
+use super::{
+    placement::place_features, rng::GameRng, structures::Structure, PlayerTeam, TeamColour,
+    Terrain, TileFeature, TileFeatures, TERRAIN_VARIANTS,
+};
+
+// How many boards to throw away looking for a fair one before settling for
+// whatever came up last
+const MAX_REROLLS: u32 = 8;
 
-#[derive(Component, Default, Reflect)]
+#[derive(Clone, Component, Debug, Default, Deserialize, Reflect, Serialize)]
 pub struct Gameboard {
     tiles: Vec<Vec<Tile>>,
     x: u32,
@@ -18,6 +25,32 @@ pub struct Gameboard {
 }
 
 impl Gameboard {
+    // A board where nothing is known yet, for clients to fill in as they see it
+    pub fn unexplored(x: u32, y: u32) -> Gameboard {
+        return Gameboard {
+            tiles: (0..x)
+                .map(|tile_x| {
+                    (0..y)
+                        .map(|tile_y| Tile {
+                            pos: Vec2::new(tile_x as f32, tile_y as f32),
+                            ..default()
+                        })
+                        .collect()
+                })
+                .collect(),
+            x,
+            y,
+        };
+    }
+
+    // Overwrites the tile at the new tile's position
+    pub fn set_tile(&mut self, tile: Tile) {
+        let (x, y) = tile.pos_usize();
+        if let Some(existing) = self.tile_mut(x, y) {
+            *existing = tile;
+        }
+    }
+
     pub fn tile(&self, x: usize, y: usize) -> Option<&Tile> {
         // So we don't have to deal with the pain of Vec::get(), which always
         // returns Option<&T>, we do this logic ourselves, because his is a 2D
@@ -31,6 +64,14 @@ impl Gameboard {
         return None;
     }
 
+    pub fn tile_mut(&mut self, x: usize, y: usize) -> Option<&mut Tile> {
+        if self.tiles.len() > x && self.tiles[0].len() > y {
+            return Some(&mut self.tiles[x][y]);
+        }
+
+        return None;
+    }
+
     pub fn adjacent_tiles(&self, x: usize, y: usize) -> Vec<&Tile> {
         let mut adjacent_tiles = Vec::<&Tile>::with_capacity(9);
         // [-1, 1] - x is +1 from its actual value
@@ -50,6 +91,10 @@ impl Gameboard {
         return adjacent_tiles;
     }
 
+    pub fn tiles(&self) -> impl Iterator<Item = &Tile> {
+        return self.tiles.iter().flatten();
+    }
+
     pub fn x(&self) -> u32 {
         return self.x;
     }
@@ -59,12 +104,18 @@ impl Gameboard {
     }
 }
 
-#[derive(Component, Debug, Default, FromReflect, Reflect)]
+#[derive(
+    Clone, Component, Debug, Default, Deserialize, FromReflect, PartialEq, Reflect, Serialize,
+)]
 pub struct Tile {
     contents: Terrain,
     feature: Option<TileFeature>,
+    structure: Option<Structure>,
     visible_for: Vec<TeamColour>,
     pos: Vec2,
+    // Which of the terrain's sprites to draw. Picked when the board is
+    // generated, so it looks the same to everyone
+    variant: u8,
 }
 
 impl Tile {
@@ -76,6 +127,77 @@ impl Tile {
         return self.pos;
     }
 
+    pub fn terrain(&self) -> Terrain {
+        return self.contents;
+    }
+
+    pub fn set_terrain(&mut self, terrain: Terrain) {
+        self.contents = terrain;
+    }
+
+    pub fn variant(&self) -> u8 {
+        return self.variant;
+    }
+
+    pub fn feature(&self) -> Option<&TileFeature> {
+        return self.feature.as_ref();
+    }
+
+    pub fn feature_mut(&mut self) -> Option<&mut TileFeature> {
+        return self.feature.as_mut();
+    }
+
+    pub fn set_feature(&mut self, feature: TileFeatures) {
+        self.feature = Some(TileFeature {
+            pos: [self.pos.x as i32, self.pos.y as i32],
+            feature,
+            visible_to_players: Vec::new(),
+            controller: None,
+        });
+    }
+
+    pub fn remove_feature(&mut self) -> Option<TileFeature> {
+        return self.feature.take();
+    }
+
+    // Teams that can currently see this tile
+    pub fn visible_for(&self) -> &[TeamColour] {
+        return &self.visible_for;
+    }
+
+    pub fn set_visible_for(&mut self, teams: Vec<TeamColour>) {
+        self.visible_for = teams;
+    }
+
+    pub fn structure(&self) -> Option<&Structure> {
+        return self.structure.as_ref();
+    }
+
+    pub fn structure_mut(&mut self) -> Option<&mut Structure> {
+        return self.structure.as_mut();
+    }
+
+    pub fn set_structure(&mut self, structure: Structure) -> &mut Structure {
+        return self.structure.insert(structure);
+    }
+
+    pub fn remove_structure(&mut self) -> Option<Structure> {
+        return self.structure.take();
+    }
+
+    // Cost for a unit of `team` to move onto this tile, taking finished
+    // structures into account. None if they can't move here at all
+    pub fn movement_cost_for(&self, team: &PlayerTeam) -> Option<f32> {
+        let modifier = match &self.structure {
+            Some(structure) if structure.is_complete() => {
+                structure.kind.movement_modifier(structure.owner == *team)?
+            }
+            _ => 1f32,
+        };
+
+        return Some(self.movement_cost() * modifier);
+    }
+
     pub fn movement_cost(&self) -> f32 {
         let speed_modifier = match self.contents {
             Terrain::Desert => 1.2,
@@ -90,125 +212,178 @@ impl Tile {
 
         return 1f32 / speed_modifier;
     }
+}
 
-    pub fn propogate_movement_costs(
-        &self,
-        tile_ring: Vec<&Tile>,
-        tile_movement_costs: &mut Vec<Vec<Option<f32>>>,
-        gameboard: &Gameboard,
-    ) {
-        let mut propogate_tiles = Vec::<&Tile>::with_capacity(8);
-
-        for (og_x, og_y) in tile_ring.iter().map(|t| t.pos_usize()) {
-            // We only want the unset tiles, so we always propogate outwards
-            let surrounding_tiles = gameboard.adjacent_tiles(og_x, og_y);
-            for tile in surrounding_tiles {
-                let (x, y) = tile.pos_usize();
-                if tile_movement_costs[x][y] == None {
-                    tile_movement_costs[x][y] =
-                        Some(tile.movement_cost() + tile_movement_costs[og_x][og_y].unwrap());
-                    propogate_tiles.push(tile);
-                }
-            }
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum MapPreset {
+    // A few large landmasses split by the sea
+    #[default]
+    Continents,
+    // Lots of small islands, mostly shallow water between them
+    Archipelago,
+    // One big landmass with a little sea around it
+    Pangaea,
+    // Mostly land, broken up by mountain ranges
+    Highlands,
+}
 
-            // Order from low to high, so we lock in the lowest value. Because
-            // floats can't be ordered for some reason, multiply by 10000 and then
-            // use a u32
-            propogate_tiles
-                .iter()
-                .map(|t| {
-                    let (x, y) = t.pos_usize();
-                    return (tile_movement_costs[x][y].unwrap() * 10000f32) as u32;
-                })
-                .collect::<Vec<u32>>()
-                .sort();
-        }
+impl MapPreset {
+    pub fn mapgen_config(&self) -> MapgenConfig {
+        let continents = MapgenConfig {
+            preset: *self,
+            deep_water: -0.5,
+            sea_level: 0.0,
+            beach: 0.1,
+            mountains: 0.92,
+            biome: 0.1,
+            octaves: 6,
+            frequency: 1.0,
+            zoom: 32.0,
+        };
 
-        // Recursion is a bitch
-        if propogate_tiles.len() > 0 {
-            self.propogate_movement_costs(propogate_tiles, tile_movement_costs, gameboard);
-        }
+        return match self {
+            MapPreset::Continents => continents,
+            MapPreset::Archipelago => MapgenConfig {
+                deep_water: -0.15,
+                sea_level: 0.15,
+                beach: 0.22,
+                mountains: 0.95,
+                frequency: 1.8,
+                ..continents
+            },
+            MapPreset::Pangaea => MapgenConfig {
+                deep_water: -0.75,
+                sea_level: -0.35,
+                beach: -0.28,
+                mountains: 0.9,
+                octaves: 4,
+                frequency: 0.6,
+                zoom: 48.0,
+                ..continents
+            },
+            MapPreset::Highlands => MapgenConfig {
+                deep_water: -0.6,
+                sea_level: -0.25,
+                beach: -0.2,
+                mountains: 0.55,
+                octaves: 7,
+                frequency: 1.3,
+                ..continents
+            },
+        };
     }
 }
 
 pub fn spawn_gameboard(
     mut commands: Commands,
     config: Res<Config>,
-    mut images: ResMut<Assets<Image>>,
+    mut rng: ResMut<GameRng>,
     gameboard_q: Query<&Gameboard>,
-    mut evs: EventReader<MapReadyEvent>,
-    mut maps: Query<&mut Map>,
 ) {
     if gameboard_q.iter().len() > 0 {
         return;
     }
 
-    for ev in evs.iter() {
-        let map = maps.get_mut(ev.map).unwrap();
-
-        let gameboard_config = &config.gameboard_config;
-
-        let mut gameboard = Gameboard {
-            tiles: Vec::with_capacity(gameboard_config.width as usize),
-            x: gameboard_config.width,
-            y: gameboard_config.height,
-        };
+    // Singleplayer is always two teams. The server doesn't know how many will
+    // turn up, so it makes room for as many as could, and the nests nobody
+    // uses are cleared away when the match starts
+    let players = match config.env {
+        RunEnvironment::Server => config.server_config.max_players as usize,
+        _ => 2,
+    };
+    let gameboard = generate_gameboard(&config.gameboard_config, players, &mut rng);
+    commands.spawn(gameboard).insert(Name::new("Gameboard"));
+}
 
-        let mut rand = rand::thread_rng();
-        let seed = rand.gen_range(0..u128::MAX);
-
-        let scale =
-            u32::max(gameboard_config.width, gameboard_config.height) as f64 * (1f64 / 32f64);
-
-        let heightmap_seed = (seed >> 96) as u32;
-        let inlandness_seed = ((seed >> 64) & 0xFFFF_FFFF) as u32;
-        let climate_seed = ((seed >> 32) & 0xFFFF_FFFF) as u32;
-        let rainfall_seed = (seed & 0xFFFF_FFFF) as u32;
-
-        let heightmap = new_perlin_noise(
-            scale,
-            heightmap_seed,
-            gameboard_config.width,
-            gameboard_config.height,
-        );
-        let inlandness = new_perlin_noise(
-            scale,
-            inlandness_seed,
-            gameboard_config.width,
-            gameboard_config.height,
-        );
-        let climate = new_perlin_noise(
-            scale,
-            climate_seed,
-            gameboard_config.width,
-            gameboard_config.height,
-        );
-
-        for x in 0..gameboard_config.width {
-            gameboard
-                .tiles
-                .push(Vec::with_capacity(gameboard_config.height as usize));
-            for y in 0..gameboard_config.height {
-                let tile = tile_at_position(x, y, &heightmap, &inlandness, &climate);
-                if let Ok(mut m) = map.get_mut(&mut *images) {
-                    m.set(x, y, tile.to_atlas_index(&mut rand));
-                }
-                gameboard.tiles.get_mut(x as usize).unwrap().push(Tile {
-                    contents: tile,
-                    feature: None,
-                    visible_for: Vec::new(),
-                    pos: Vec2::new(x as f32, y as f32),
-                });
+pub fn generate_gameboard(
+    gameboard_config: &GameboardConfig,
+    players: usize,
+    rng: &mut GameRng,
+) -> Gameboard {
+    let mut attempt = 0;
+    loop {
+        let mut gameboard = generate_terrain(gameboard_config, rng);
+        match place_features(&mut gameboard, players, rng) {
+            Ok(()) => return gameboard,
+            Err(err) if attempt < MAX_REROLLS => {
+                info!("Rerolling board: {}", err);
+                attempt += 1;
+            }
+            Err(err) => {
+                warn!(
+                    "Unable to generate a fair board, keeping the last one: {}",
+                    err
+                );
+                return gameboard;
             }
         }
+    }
+}
+
+fn generate_terrain(gameboard_config: &GameboardConfig, rng: &mut GameRng) -> Gameboard {
+    let mut gameboard = Gameboard {
+        tiles: Vec::with_capacity(gameboard_config.width as usize),
+        x: gameboard_config.width,
+        y: gameboard_config.height,
+    };
+
+    let seed = rng.gen::<u128>();
 
-        commands.spawn(gameboard).insert(Name::new("Gameboard"));
+    let mapgen = &gameboard_config.mapgen;
+    let scale = u32::max(gameboard_config.width, gameboard_config.height) as f64 / mapgen.zoom;
+
+    let heightmap_seed = (seed >> 96) as u32;
+    let inlandness_seed = ((seed >> 64) & 0xFFFF_FFFF) as u32;
+    let climate_seed = ((seed >> 32) & 0xFFFF_FFFF) as u32;
+    let rainfall_seed = (seed & 0xFFFF_FFFF) as u32;
+
+    let heightmap = new_perlin_noise(
+        mapgen,
+        scale,
+        heightmap_seed,
+        gameboard_config.width,
+        gameboard_config.height,
+    );
+    let inlandness = new_perlin_noise(
+        mapgen,
+        scale,
+        inlandness_seed,
+        gameboard_config.width,
+        gameboard_config.height,
+    );
+    let climate = new_perlin_noise(
+        mapgen,
+        scale,
+        climate_seed,
+        gameboard_config.width,
+        gameboard_config.height,
+    );
+
+    for x in 0..gameboard_config.width {
+        gameboard
+            .tiles
+            .push(Vec::with_capacity(gameboard_config.height as usize));
+        for y in 0..gameboard_config.height {
+            let tile = tile_at_position(x, y, mapgen, &heightmap, &inlandness, &climate);
+            gameboard.tiles.get_mut(x as usize).unwrap().push(Tile {
+                contents: tile,
+                feature: None,
+                structure: None,
+                visible_for: Vec::new(),
+                pos: Vec2::new(x as f32, y as f32),
+                variant: rng.gen_range(0..TERRAIN_VARIANTS),
+            });
+        }
     }
+
+    return gameboard;
 }
 
 fn tile_at_position(
     x: u32,
     y: u32,
+    mapgen: &MapgenConfig,
     heightmap: &NoiseMap,
     climate: &NoiseMap,
     rainfall: &NoiseMap,
@@ -216,10 +391,10 @@ fn tile_at_position(
     let x = x as usize;
     let y = y as usize;
 
-    // First, deal with water/land. If height < 0, we're underwater.
-    if heightmap.get_value(x, y) < 0f64 {
+    // First, deal with water/land. If height < sea level, we're underwater.
+    if heightmap.get_value(x, y) < mapgen.sea_level {
         // Underwater
-        if heightmap.get_value(x, y) < -0.5f64 {
+        if heightmap.get_value(x, y) < mapgen.deep_water {
             // We're low underwater, so we use deep water
             return Terrain::Water;
         } else {
@@ -228,12 +403,14 @@ fn tile_at_position(
         }
     }
     // By the same token, if we're really high, we're getting mountains regardless.
-    else if heightmap.get_value(x, y) > 0.92f64 {
+    else if heightmap.get_value(x, y) > mapgen.mountains {
         // Mountain time
         return Terrain::Mountains;
     }
     // Finally, a special beaches exception
-    else if heightmap.get_value(x, y) > 0f64 && heightmap.get_value(x, y) < 0.1f64 {
+    else if heightmap.get_value(x, y) > mapgen.sea_level
+        && heightmap.get_value(x, y) < mapgen.beach
+    {
         return Terrain::Desert;
     }
     // Now we know that we're on land, we look to the other variables
@@ -250,13 +427,14 @@ fn tile_at_position(
            l Forest  Savanna
            d
         */
-        if rainfall.get_value(x, y) < -0.1f64 && climate.get_value(x, y) < -0.1f64 {
+        let biome = mapgen.biome;
+        if rainfall.get_value(x, y) < -biome && climate.get_value(x, y) < -biome {
             return Terrain::Savanna;
-        } else if rainfall.get_value(x, y) > 0.1f64 && climate.get_value(x, y) < -0.1f64 {
+        } else if rainfall.get_value(x, y) > biome && climate.get_value(x, y) < -biome {
             return Terrain::Forest;
-        } else if rainfall.get_value(x, y) < -0.1f64 && climate.get_value(x, y) > 0.1f64 {
+        } else if rainfall.get_value(x, y) < -biome && climate.get_value(x, y) > biome {
             return Terrain::Desert;
-        } else if rainfall.get_value(x, y) > 0.1f64 && climate.get_value(x, y) > 0.1f64 {
+        } else if rainfall.get_value(x, y) > biome && climate.get_value(x, y) > biome {
             return Terrain::Jungle;
         }
         // Just a default. Nothing *should* get through to here...
@@ -266,8 +444,16 @@ fn tile_at_position(
     }
 }
 
-fn new_perlin_noise(scale: f64, seed: u32, width: u32, height: u32) -> NoiseMap {
-    let fbm = Fbm::<Perlin>::new(seed);
+fn new_perlin_noise(
+    mapgen: &MapgenConfig,
+    scale: f64,
+    seed: u32,
+    width: u32,
+    height: u32,
+) -> NoiseMap {
+    let fbm = Fbm::<Perlin>::new(seed)
+        .set_octaves(mapgen.octaves)
+        .set_frequency(mapgen.frequency);
     let noisemap = PlaneMapBuilder::<_, 2>::new(&fbm)
         .set_size(width as usize, height as usize)
         .set_x_bounds(-scale, scale)
@@ -275,3 +461,35 @@ fn new_perlin_noise(scale: f64, seed: u32, width: u32, height: u32) -> NoiseMap
         .build();
     return noisemap;
 }
+
+#[cfg(test)]
+mod tests {
+    use rand::RngCore;
+
+    use super::*;
+
+    fn generate(seed: u64) -> (Gameboard, GameRng) {
+        let config = GameboardConfig {
+            width: 32,
+            height: 32,
+            ..default()
+        };
+        let mut rng = GameRng::from_seed(seed);
+        let gameboard = generate_gameboard(&config, 2, &mut rng);
+        return (gameboard, rng);
+    }
+
+    #[test]
+    fn the_same_seed_gives_the_same_board() {
+        let (a, mut a_rng) = generate(1234);
+        let (b, mut b_rng) = generate(1234);
+
+        assert_eq!((a.x(), a.y()), (b.x(), b.y()));
+        assert!(a.tiles().eq(b.tiles()));
+        // Anything drawn after the board carries on the same too
+        assert_eq!(a_rng.next_u64(), b_rng.next_u64());
+
+        let (c, _) = generate(4321);
+        assert!(!a.tiles().eq(c.tiles()));
+    }
+}
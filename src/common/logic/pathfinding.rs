@@ -0,0 +1,336 @@
+use std::{cmp::Ordering, collections::BinaryHeap};
+
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use super::{
+    neo_gameboard::{Gameboard, Tile},
+    PlayerTeam, Unit,
+};
+
+// Movement costs are sums of 1/speed, so a unit with exactly enough movement
+// can land a hair over budget due to float error
+const COST_EPSILON: f32 = 1e-4;
+
+// An entry in the search frontier. BinaryHeap is a max-heap, so the ordering
+// is reversed to pop the cheapest tile first. Ties break on position so the
+// search is the same on every machine
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct Frontier {
+    cost: f32,
+    x: usize,
+    y: usize,
+}
+
+impl Eq for Frontier {}
+
+impl Ord for Frontier {
+    fn cmp(&self, other: &Self) -> Ordering {
+        return other
+            .cost
+            .total_cmp(&self.cost)
+            .then_with(|| other.x.cmp(&self.x))
+            .then_with(|| other.y.cmp(&self.y));
+    }
+}
+
+impl PartialOrd for Frontier {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        return Some(self.cmp(other));
+    }
+}
+
+// One step along a path, with the total cost of getting this far
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+pub struct PathStep {
+    pub pos: Vec2,
+    pub cost: f32,
+}
+
+// The cheapest cost to reach every tile from a single start tile, and the
+// tile each one was reached from
+pub struct ShortestPaths {
+    height: usize,
+    costs: Vec<Option<f32>>,
+    previous: Vec<Option<usize>>,
+}
+
+impl ShortestPaths {
+    fn index(&self, x: usize, y: usize) -> usize {
+        return x * self.height + y;
+    }
+
+    fn pos_at(&self, index: usize) -> Vec2 {
+        return Vec2::new((index / self.height) as f32, (index % self.height) as f32);
+    }
+
+    fn index_of(&self, pos: Vec2) -> Option<usize> {
+        if pos.x < 0f32 || pos.y < 0f32 || pos.y as usize >= self.height {
+            return None;
+        }
+        let index = self.index(pos.x as usize, pos.y as usize);
+        if index >= self.costs.len() {
+            return None;
+        }
+        return Some(index);
+    }
+
+    pub fn cost(&self, pos: Vec2) -> Option<f32> {
+        return self.costs[self.index_of(pos)?];
+    }
+
+    // The cheapest path from the start to `pos`, not including the start
+    // itself. Empty if `pos` is the start, None if it wasn't reached
+    pub fn path_to(&self, pos: Vec2) -> Option<Vec<PathStep>> {
+        let mut index = self.index_of(pos)?;
+        self.costs[index]?;
+
+        let mut steps = Vec::<PathStep>::new();
+        while let Some(previous) = self.previous[index] {
+            steps.push(PathStep {
+                pos: self.pos_at(index),
+                cost: self.costs[index]?,
+            });
+            index = previous;
+        }
+
+        steps.reverse();
+        return Some(steps);
+    }
+
+    // Every tile that was reached, with what it costs to get there
+    pub fn reachable(&self) -> Vec<(Vec2, f32)> {
+        return self
+            .costs
+            .iter()
+            .enumerate()
+            .filter_map(|(i, cost)| cost.map(|c| (self.pos_at(i), c)))
+            .collect();
+    }
+}
+
+impl Gameboard {
+    // Dijkstra out from `start` for a unit on `team`. Entering a tile costs
+    // that tile's movement cost, and tiles the team can't enter are skipped.
+    // With a budget, nothing more expensive than it is explored
+    pub fn shortest_paths(
+        &self,
+        start: Vec2,
+        team: &PlayerTeam,
+        budget: Option<f32>,
+    ) -> ShortestPaths {
+        return self.shortest_paths_by(start, budget, |tile| tile.movement_cost_for(team));
+    }
+
+    // The same search, with `step_cost` giving what it costs to enter a tile,
+    // or None if it can't be entered
+    pub fn shortest_paths_by(
+        &self,
+        start: Vec2,
+        budget: Option<f32>,
+        step_cost: impl Fn(&Tile) -> Option<f32>,
+    ) -> ShortestPaths {
+        let (width, height) = (self.x() as usize, self.y() as usize);
+        let mut paths = ShortestPaths {
+            height,
+            costs: vec![None; width * height],
+            previous: vec![None; width * height],
+        };
+
+        let (start_x, start_y) = (start.x as usize, start.y as usize);
+        if self.tile(start_x, start_y).is_none() {
+            return paths;
+        }
+
+        let mut frontier = BinaryHeap::<Frontier>::new();
+        let start_index = paths.index(start_x, start_y);
+        paths.costs[start_index] = Some(0f32);
+        frontier.push(Frontier {
+            cost: 0f32,
+            x: start_x,
+            y: start_y,
+        });
+
+        while let Some(Frontier { cost, x, y }) = frontier.pop() {
+            // A cheaper route to this tile was already expanded
+            if let Some(best) = paths.costs[paths.index(x, y)] {
+                if cost > best {
+                    continue;
+                }
+            }
+
+            for tile in self.adjacent_tiles(x, y) {
+                let (next_x, next_y) = tile.pos_usize();
+                let Some(step_cost) = step_cost(tile) else {
+                    continue;
+                };
+
+                let next_cost = cost + step_cost;
+                if budget.map_or(false, |b| next_cost > b + COST_EPSILON) {
+                    continue;
+                }
+
+                let next_index = paths.index(next_x, next_y);
+                if paths.costs[next_index].map_or(true, |c| next_cost < c) {
+                    paths.costs[next_index] = Some(next_cost);
+                    paths.previous[next_index] = Some(paths.index(x, y));
+                    frontier.push(Frontier {
+                        cost: next_cost,
+                        x: next_x,
+                        y: next_y,
+                    });
+                }
+            }
+        }
+
+        return paths;
+    }
+
+    pub fn reachable_tiles(&self, start: Vec2, team: &PlayerTeam, budget: f32) -> Vec<(Vec2, f32)> {
+        return self.shortest_paths(start, team, Some(budget)).reachable();
+    }
+}
+
+impl Unit {
+    // The path this unit would take to `target` this turn, if it can get there
+    pub fn path_to(&self, gameboard: &Gameboard, target: Vec2) -> Option<Vec<PathStep>> {
+        return gameboard
+            .shortest_paths(self.pos, &self.owner, Some(self.movement.0 as f32))
+            .path_to(target);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::logic::{
+        combat::tile_distance,
+        structures::{Structure, StructureKind},
+        testing::{grass_board, unit},
+        Movement, TeamColour, Terrain,
+    };
+
+    const GRASS: f32 = 1f32 / 1.5;
+
+    fn blue() -> PlayerTeam {
+        return PlayerTeam(TeamColour::Blue);
+    }
+
+    fn wall(gameboard: &mut Gameboard, x: usize, y: usize, team: TeamColour) {
+        let mut wall = Structure::new(StructureKind::Wall, PlayerTeam(team));
+        wall.build_progress = StructureKind::Wall.build_turns();
+        gameboard.tile_mut(x, y).unwrap().set_structure(wall);
+    }
+
+    fn assert_cost(paths: &ShortestPaths, pos: Vec2, expected: f32) {
+        let cost = paths.cost(pos).unwrap();
+        assert!(
+            (cost - expected).abs() < COST_EPSILON,
+            "{} costs {}, expected {}",
+            pos,
+            cost,
+            expected
+        );
+    }
+
+    #[test]
+    fn diagonals_cost_the_same_as_straight_steps() {
+        let paths = grass_board(5, 5).shortest_paths(Vec2::ZERO, &blue(), None);
+
+        assert_cost(&paths, Vec2::ZERO, 0f32);
+        assert_cost(&paths, Vec2::new(2f32, 2f32), 2f32 * GRASS);
+        assert_cost(&paths, Vec2::new(4f32, 1f32), 4f32 * GRASS);
+    }
+
+    #[test]
+    fn cheaper_terrain_wins_over_fewer_steps() {
+        let mut gameboard = grass_board(5, 3);
+        for y in 0..2 {
+            gameboard
+                .tile_mut(2, y)
+                .unwrap()
+                .set_terrain(Terrain::Mountains);
+        }
+
+        // Going round through (2, 2) is two grass steps more, but still
+        // cheaper than one mountain
+        let paths = gameboard.shortest_paths(Vec2::ZERO, &blue(), None);
+        assert_cost(&paths, Vec2::new(4f32, 0f32), 4f32 * GRASS);
+        assert_cost(&paths, Vec2::new(2f32, 0f32), GRASS + 2f32);
+    }
+
+    #[test]
+    fn only_the_owner_can_get_through_walls() {
+        let mut gameboard = grass_board(5, 3);
+        for y in 0..3 {
+            wall(&mut gameboard, 2, y, TeamColour::Blue);
+        }
+
+        let own = gameboard.shortest_paths(Vec2::ZERO, &blue(), None);
+        assert_cost(&own, Vec2::new(4f32, 0f32), 4f32 * GRASS);
+
+        let enemy = gameboard.shortest_paths(Vec2::ZERO, &PlayerTeam(TeamColour::Red), None);
+        assert_eq!(enemy.cost(Vec2::new(2f32, 0f32)), None);
+        assert_eq!(enemy.cost(Vec2::new(4f32, 0f32)), None);
+    }
+
+    #[test]
+    fn nothing_past_the_budget_is_reached() {
+        let gameboard = grass_board(5, 5);
+
+        let reachable = gameboard.reachable_tiles(Vec2::new(2f32, 2f32), &blue(), 1f32);
+        // The start and the eight tiles around it
+        assert_eq!(reachable.len(), 9);
+        assert!(reachable
+            .iter()
+            .all(|(pos, _)| pos.distance(Vec2::new(2f32, 2f32)) < 2f32));
+
+        // Exactly enough movement still gets there despite float error
+        let paths = gameboard.shortest_paths(Vec2::ZERO, &blue(), Some(3f32 * GRASS));
+        assert!(paths.cost(Vec2::new(3f32, 0f32)).is_some());
+        assert!(paths.cost(Vec2::new(4f32, 0f32)).is_none());
+    }
+
+    #[test]
+    fn paths_step_from_tile_to_tile_adding_up_the_cost() {
+        let mut gameboard = grass_board(5, 3);
+        for y in 0..2 {
+            wall(&mut gameboard, 2, y, TeamColour::Red);
+        }
+        let paths = gameboard.shortest_paths(Vec2::ZERO, &blue(), None);
+
+        let path = paths.path_to(Vec2::new(4f32, 0f32)).unwrap();
+        assert_eq!(path.len(), 4);
+        assert_eq!(path[1].pos, Vec2::new(2f32, 2f32));
+        assert_eq!(path.last().unwrap().pos, Vec2::new(4f32, 0f32));
+
+        let mut previous = PathStep {
+            pos: Vec2::ZERO,
+            cost: 0f32,
+        };
+        for step in &path {
+            assert_eq!(tile_distance(previous.pos, step.pos), 1);
+            assert!((step.cost - previous.cost - GRASS).abs() < COST_EPSILON);
+            previous = *step;
+        }
+
+        assert_eq!(paths.path_to(Vec2::ZERO), Some(Vec::new()));
+        assert_eq!(paths.path_to(Vec2::new(2f32, 0f32)), None);
+        assert_eq!(paths.path_to(Vec2::new(9f32, 9f32)), None);
+    }
+
+    #[test]
+    fn units_only_get_paths_they_can_finish_this_turn() {
+        let gameboard = grass_board(8, 1);
+        let mut unit = unit(TeamColour::Blue, 0f32, 0f32);
+        unit.movement = Movement(2);
+
+        assert_eq!(
+            unit.path_to(&gameboard, Vec2::new(3f32, 0f32))
+                .unwrap()
+                .len(),
+            3
+        );
+        assert_eq!(unit.path_to(&gameboard, Vec2::new(4f32, 0f32)), None);
+    }
+}
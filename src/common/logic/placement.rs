@@ -0,0 +1,370 @@
+use bevy::prelude::*;
+use rand::seq::SliceRandom;
+
+use super::{
+    combat::tile_distance, neo_gameboard::Gameboard, pathfinding::ShortestPaths, rng::GameRng,
+    Archetype, Archetypes, PlayerTeam, TeamColour, Terrain, TileFeatures,
+};
+
+// Roughly how many tiles there are for every currency site
+const TILES_PER_CURRENCY_SITE: u32 = 150;
+// How far nests are kept from the edge of the board
+const NEST_INSET: f32 = 3f32;
+// Nobody should start with a site right on top of their nest, or inside the
+// spread of their starting army
+const MIN_SITE_DISTANCE: i32 = 3;
+// Each team's nth closest site is aimed at n times this far away (in movement
+// cost), and they can't differ by more than HOME_SITE_TOLERANCE
+const HOME_SITE_SPACING: f32 = 5f32;
+const HOME_SITE_TOLERANCE: f32 = 3f32;
+
+// Puts down a nest for each of `players` (in the order teams are handed out in
+// the lobby) and the currency sites. Every nest can reach every other one, and
+// each team gets the same number of sites about as far from its nest as
+// everyone else's. Anything else is left to the caller to reroll
+pub fn place_features(
+    gameboard: &mut Gameboard,
+    players: usize,
+    rng: &mut GameRng,
+) -> Result<(), String> {
+    let nests = nest_positions(gameboard, players);
+    if nests.len() < players {
+        return Err(format!(
+            "only room for {} of {} nests",
+            nests.len(),
+            players
+        ));
+    }
+
+    connect_nests(gameboard, &nests);
+    for (i, nest) in nests.iter().enumerate() {
+        gameboard
+            .tile_mut(nest.x as usize, nest.y as usize)
+            .unwrap()
+            .set_feature(TileFeatures::Nest(PlayerTeam(TeamColour::from_int(&i))));
+    }
+
+    return place_currency_sites(gameboard, &nests, rng);
+}
+
+// Nests start near the corners, opposite corners first. Each goes on the
+// closest bit of dry land to its corner without anything else on it
+fn nest_positions(gameboard: &Gameboard, count: usize) -> Vec<Vec2> {
+    let (max_x, max_y) = (gameboard.x() as f32 - 1f32, gameboard.y() as f32 - 1f32);
+    let corners = [
+        Vec2::new(NEST_INSET, NEST_INSET),
+        Vec2::new(max_x - NEST_INSET, max_y - NEST_INSET),
+        Vec2::new(max_x - NEST_INSET, NEST_INSET),
+        Vec2::new(NEST_INSET, max_y - NEST_INSET),
+    ];
+
+    let mut nests = Vec::<Vec2>::new();
+    for corner in corners.into_iter().take(count) {
+        let nest = gameboard
+            .tiles()
+            .filter(|t| t.feature().is_none() && is_dry_land(t.terrain()))
+            .filter(|t| !nests.contains(&t.pos()))
+            .map(|t| t.pos())
+            .min_by(|a, b| a.distance(corner).total_cmp(&b.distance(corner)));
+
+        match nest {
+            Some(nest) => nests.push(nest),
+            None => warn!("Nowhere to put a nest near {}", corner),
+        }
+    }
+    return nests;
+}
+
+fn is_dry_land(terrain: Terrain) -> bool {
+    return !matches!(
+        terrain,
+        Terrain::Water | Terrain::ShallowWater | Terrain::Mountains
+    );
+}
+
+// Units can wade through deep water, but a board is only fair if nobody has
+// to just to reach the other nests or their sites, so it's treated as
+// impassable here. Nothing has been built yet, so every team moves the same
+// way. Any of them will do
+fn paths_from(gameboard: &Gameboard, nest: Vec2) -> ShortestPaths {
+    let team = PlayerTeam(TeamColour::Blue);
+    return gameboard.shortest_paths_by(nest, None, |tile| match tile.terrain() {
+        Terrain::Water => None,
+        _ => tile.movement_cost_for(&team),
+    });
+}
+
+// Deep water is the only thing paths_from won't cross, so any nest cut off
+// from the first gets a straight line of shallow water back to it
+fn connect_nests(gameboard: &mut Gameboard, nests: &[Vec2]) {
+    let Some(first) = nests.first() else {
+        return;
+    };
+
+    for nest in nests.iter().skip(1) {
+        if paths_from(gameboard, *first).cost(*nest).is_some() {
+            continue;
+        }
+
+        info!("Carving a land bridge from {} to {}", first, nest);
+        for pos in line_between(*first, *nest) {
+            let tile = gameboard.tile_mut(pos.x as usize, pos.y as usize).unwrap();
+            if tile.terrain() == Terrain::Water {
+                tile.set_terrain(Terrain::ShallowWater);
+            }
+        }
+    }
+}
+
+// Every tile on a Bresenham line between the two, including both ends. Steps
+// can be diagonal, which units can move along too
+fn line_between(from: Vec2, to: Vec2) -> Vec<Vec2> {
+    let (mut x, mut y) = (from.x as i32, from.y as i32);
+    let (end_x, end_y) = (to.x as i32, to.y as i32);
+
+    let dx = (end_x - x).abs();
+    let dy = -(end_y - y).abs();
+    let step_x = if x < end_x { 1 } else { -1 };
+    let step_y = if y < end_y { 1 } else { -1 };
+    let mut error = dx + dy;
+
+    let mut line = vec![from];
+    while x != end_x || y != end_y {
+        let doubled = 2 * error;
+        if doubled >= dy {
+            error += dy;
+            x += step_x;
+        }
+        if doubled <= dx {
+            error += dx;
+            y += step_y;
+        }
+        line.push(Vec2::new(x as f32, y as f32));
+    }
+    return line;
+}
+
+struct SiteCandidate {
+    pos: Vec2,
+    // Movement cost from each nest, in the same order as the nests
+    costs: Vec<f32>,
+}
+
+impl SiteCandidate {
+    // The nest this is strictly closest to, if any
+    fn closest_nest(&self) -> Option<usize> {
+        let (closest, cost) = self
+            .costs
+            .iter()
+            .enumerate()
+            .min_by(|a, b| a.1.total_cmp(b.1))?;
+        let tied = self.costs.iter().filter(|c| *c == cost).count() > 1;
+        return if tied { None } else { Some(closest) };
+    }
+
+    // How much closer the nearest nest is than the furthest one
+    fn spread(&self) -> f32 {
+        let min = self.costs.iter().copied().fold(f32::MAX, f32::min);
+        let max = self.costs.iter().copied().fold(0f32, f32::max);
+        return max - min;
+    }
+}
+
+// Each team first gets its own sites, the same number and mix for everyone and
+// at about the same distance. The rest go wherever is closest to being the
+// same distance from every nest, so they're worth fighting over
+fn place_currency_sites(
+    gameboard: &mut Gameboard,
+    nests: &[Vec2],
+    rng: &mut GameRng,
+) -> Result<(), String> {
+    let paths = nests
+        .iter()
+        .map(|nest| paths_from(gameboard, *nest))
+        .collect::<Vec<ShortestPaths>>();
+
+    // Shuffled so that ties are broken differently on every board
+    let mut candidates = gameboard
+        .tiles()
+        .filter(|t| t.feature().is_none() && is_dry_land(t.terrain()))
+        .filter(|t| {
+            nests
+                .iter()
+                .all(|nest| tile_distance(*nest, t.pos()) >= MIN_SITE_DISTANCE)
+        })
+        .filter_map(|t| {
+            let costs = paths
+                .iter()
+                .map(|p| p.cost(t.pos()))
+                .collect::<Option<Vec<f32>>>()?;
+            Some(SiteCandidate {
+                pos: t.pos(),
+                costs,
+            })
+        })
+        .collect::<Vec<SiteCandidate>>();
+    candidates.shuffle(rng);
+
+    let count = u32::max(2, gameboard.x() * gameboard.y() / TILES_PER_CURRENCY_SITE) as usize;
+    let rounds = usize::max(1, count / (2 * nests.len()));
+
+    let mut sites = Vec::<(Vec2, Archetypes)>::new();
+    for round in 0..rounds {
+        let archetype = site_archetype(round);
+        let target = HOME_SITE_SPACING * (round + 1) as f32;
+
+        let mut costs = Vec::<f32>::new();
+        for nest in 0..nests.len() {
+            let Some(index) = candidates
+                .iter()
+                .enumerate()
+                .filter(|(_, c)| c.closest_nest() == Some(nest))
+                .min_by(|a, b| {
+                    (a.1.costs[nest] - target)
+                        .abs()
+                        .total_cmp(&(b.1.costs[nest] - target).abs())
+                })
+                .map(|(i, _)| i)
+            else {
+                return Err(format!("nowhere to put a site for nest {}", nest));
+            };
+
+            let candidate = candidates.remove(index);
+            costs.push(candidate.costs[nest]);
+            sites.push((candidate.pos, archetype.clone()));
+        }
+
+        let nearest = costs.iter().copied().fold(f32::MAX, f32::min);
+        let furthest = costs.iter().copied().fold(0f32, f32::max);
+        if furthest - nearest > HOME_SITE_TOLERANCE {
+            return Err(format!(
+                "sites {} away from one nest but {} from another",
+                nearest, furthest
+            ));
+        }
+    }
+
+    // Stable, so equally fair candidates stay shuffled
+    candidates.sort_by(|a, b| a.spread().total_cmp(&b.spread()));
+    let contested = count.saturating_sub(sites.len());
+    for (i, candidate) in candidates.into_iter().take(contested).enumerate() {
+        sites.push((candidate.pos, site_archetype(i)));
+    }
+
+    for (pos, archetype) in sites {
+        gameboard
+            .tile_mut(pos.x as usize, pos.y as usize)
+            .unwrap()
+            .set_feature(TileFeatures::CurrencySite(Archetype(archetype)));
+    }
+    return Ok(());
+}
+
+fn site_archetype(i: usize) -> Archetypes {
+    return if i % 2 == 0 {
+        Archetypes::Magic
+    } else {
+        Archetypes::Science
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::logic::testing::grass_board;
+
+    fn sites(gameboard: &Gameboard) -> Vec<Vec2> {
+        return gameboard
+            .tiles()
+            .filter(|t| {
+                t.feature().map_or(false, |f| {
+                    matches!(f.feature, TileFeatures::CurrencySite(_))
+                })
+            })
+            .map(|t| t.pos())
+            .collect();
+    }
+
+    #[test]
+    fn nests_cut_off_by_deep_water_get_a_land_bridge() {
+        let mut gameboard = grass_board(12, 12);
+        for y in 0..12 {
+            gameboard
+                .tile_mut(6, y)
+                .unwrap()
+                .set_terrain(Terrain::Water);
+        }
+        let nests = [Vec2::new(2f32, 2f32), Vec2::new(9f32, 9f32)];
+        assert_eq!(paths_from(&gameboard, nests[0]).cost(nests[1]), None);
+
+        connect_nests(&mut gameboard, &nests);
+
+        assert!(paths_from(&gameboard, nests[0]).cost(nests[1]).is_some());
+        let bridged = gameboard
+            .tiles()
+            .filter(|t| t.terrain() == Terrain::ShallowWater)
+            .count();
+        assert_eq!(bridged, 1);
+    }
+
+    #[test]
+    fn every_nest_gets_a_site_about_as_far_away() {
+        for seed in [1, 2, 3] {
+            let mut gameboard = grass_board(40, 40);
+            let mut rng = GameRng::from_seed(seed);
+            place_features(&mut gameboard, 4, &mut rng).unwrap();
+
+            let nests = (0..4)
+                .map(|i| {
+                    gameboard
+                        .nest_of(&PlayerTeam(TeamColour::from_int(&i)))
+                        .unwrap()
+                })
+                .collect::<Vec<Vec2>>();
+            let sites = sites(&gameboard);
+            assert!(!sites.is_empty());
+
+            let closest = nests
+                .iter()
+                .map(|nest| {
+                    let paths = paths_from(&gameboard, *nest);
+                    sites
+                        .iter()
+                        .filter_map(|site| paths.cost(*site))
+                        .fold(f32::MAX, f32::min)
+                })
+                .collect::<Vec<f32>>();
+            let nearest = closest.iter().copied().fold(f32::MAX, f32::min);
+            let furthest = closest.iter().copied().fold(0f32, f32::max);
+            assert!(
+                furthest - nearest <= HOME_SITE_TOLERANCE,
+                "closest sites range from {} to {}",
+                nearest,
+                furthest
+            );
+        }
+    }
+
+    #[test]
+    fn unfair_boards_are_turned_down() {
+        // Blue's nest is out in the shallows, so all of its land is much
+        // further away than Red's is
+        let mut gameboard = grass_board(30, 10);
+        for x in 0..=10 {
+            for y in 0..10 {
+                gameboard
+                    .tile_mut(x, y)
+                    .unwrap()
+                    .set_terrain(Terrain::ShallowWater);
+            }
+        }
+        let nests = [Vec2::new(2f32, 5f32), Vec2::new(27f32, 5f32)];
+        let mut rng = GameRng::from_seed(1234);
+
+        let result = place_currency_sites(&mut gameboard, &nests, &mut rng);
+
+        let err = result.unwrap_err();
+        assert!(err.starts_with("sites "), "{}", err);
+        assert!(sites(&gameboard).is_empty());
+    }
+}
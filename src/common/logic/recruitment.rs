@@ -0,0 +1,425 @@
+use std::collections::{HashMap, VecDeque};
+
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use super::{
+    combat::tile_distance,
+    economy::Economy,
+    neo_gameboard::Gameboard,
+    turn::{ActionFailure, TurnEvent},
+    units::{UnitDefinition, UnitID, UnitRegistry},
+    PlayerTeam, TileFeatures, Unit,
+};
+
+// How many queued units each nest can put out per turn
+pub const RECRUITS_PER_TURN: usize = 1;
+// What every team starts the match with, next to its nest
+pub const STARTING_ARMY: [&str; 3] = [
+    "none_none_none_none_t0",
+    "none_none_none_none_t0",
+    "science_support_healer_t1",
+];
+// How far from the nest the starting army can be spread out
+const STARTING_ARMY_RANGE: i32 = 2;
+
+// A player asking for a unit at their nest
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct RecruitOrder {
+    pub owner: PlayerTeam,
+    pub unit: UnitID,
+}
+
+// Units that have been paid for, but haven't had room to come out of the nest
+// yet. Oldest first
+#[derive(Clone, Debug, Default, Deserialize, Resource, Serialize)]
+pub struct RecruitQueue {
+    queues: HashMap<PlayerTeam, VecDeque<UnitID>>,
+}
+
+impl UnitRegistry {
+    // Units that can come out of a nest, sorted by id
+    pub fn recruitable(&self) -> Vec<(&UnitID, &UnitDefinition)> {
+        let mut recruitable = self
+            .iter()
+            .filter(|(_, definition)| definition.recruit_cost.is_some())
+            .collect::<Vec<(&UnitID, &UnitDefinition)>>();
+        recruitable.sort_by(|a, b| a.0 .0.cmp(&b.0 .0));
+        return recruitable;
+    }
+}
+
+impl Gameboard {
+    pub fn nest_of(&self, team: &PlayerTeam) -> Option<Vec2> {
+        return self
+            .tiles()
+            .find(|t| {
+                t.feature()
+                    .map_or(false, |f| f.feature == TileFeatures::Nest(team.clone()))
+            })
+            .map(|t| t.pos());
+    }
+
+    // The closest tile to `centre` (but not `centre` itself) that `team` can
+    // stand on and nobody is already on
+    fn free_tile_near(
+        &self,
+        units: &[Unit],
+        centre: Vec2,
+        team: &PlayerTeam,
+        range: i32,
+    ) -> Option<Vec2> {
+        for distance in 1..=range {
+            for x in -distance..=distance {
+                for y in -distance..=distance {
+                    let pos = centre + Vec2::new(x as f32, y as f32);
+                    if tile_distance(centre, pos) != distance || pos.x < 0f32 || pos.y < 0f32 {
+                        continue;
+                    }
+                    let Some(tile) = self.tile(pos.x as usize, pos.y as usize) else {
+                        continue;
+                    };
+                    if tile.movement_cost_for(team).is_none()
+                        || units.iter().any(|u| u.is_alive() && u.pos == pos)
+                    {
+                        continue;
+                    }
+                    return Some(pos);
+                }
+            }
+        }
+        return None;
+    }
+}
+
+// Pays for every order and adds it to the back of the owner's queue, then each
+// nest puts out whatever is at the front of its queue if there's room next to it
+pub fn resolve_recruits(
+    gameboard: &Gameboard,
+    registry: &UnitRegistry,
+    economy: &mut Economy,
+    queue: &mut RecruitQueue,
+    units: &mut Vec<Unit>,
+    orders: Vec<RecruitOrder>,
+    events: &mut Vec<TurnEvent>,
+) {
+    for order in orders {
+        let fail = |reason: ActionFailure| TurnEvent::RecruitFailed {
+            owner: order.owner.clone(),
+            unit: order.unit.clone(),
+            reason,
+        };
+
+        let Some(cost) = registry.get(&order.unit).and_then(|d| d.recruit_cost) else {
+            events.push(fail(ActionFailure::InvalidTarget));
+            continue;
+        };
+        if gameboard.nest_of(&order.owner).is_none() {
+            events.push(fail(ActionFailure::NoTarget));
+            continue;
+        }
+        if !economy.spend(&order.owner, cost) {
+            events.push(fail(ActionFailure::CannotAfford));
+            continue;
+        }
+
+        events.push(TurnEvent::RecruitQueued {
+            owner: order.owner.clone(),
+            unit: order.unit.clone(),
+        });
+        queue
+            .queues
+            .entry(order.owner)
+            .or_default()
+            .push_back(order.unit);
+    }
+
+    // Board order, so who gets a contested tile doesn't depend on HashMap order
+    let nests = gameboard
+        .tiles()
+        .filter_map(|t| match t.feature().map(|f| &f.feature) {
+            Some(TileFeatures::Nest(team)) => Some((t.pos(), team.clone())),
+            _ => None,
+        })
+        .collect::<Vec<(Vec2, PlayerTeam)>>();
+
+    for (nest, team) in nests {
+        let Some(team_queue) = queue.queues.get_mut(&team) else {
+            continue;
+        };
+
+        for _ in 0..RECRUITS_PER_TURN {
+            let Some(id) = team_queue.front() else {
+                break;
+            };
+            // Stays at the front of the queue until there's room
+            let Some(pos) = gameboard.free_tile_near(units, nest, &team, 1) else {
+                break;
+            };
+            let Some(unit) = registry.spawn(id, pos, team.clone()) else {
+                team_queue.pop_front();
+                continue;
+            };
+
+            events.push(TurnEvent::Recruited {
+                pos,
+                owner: team.clone(),
+                unit: id.clone(),
+            });
+            units.push(unit);
+            team_queue.pop_front();
+        }
+    }
+}
+
+// Gives every team a starting army around the nest the board was generated
+// with. Nests for teams that aren't playing are cleared away
+pub fn place_starting_armies(
+    gameboard: &mut Gameboard,
+    registry: &UnitRegistry,
+    teams: &[PlayerTeam],
+) -> Vec<Unit> {
+    let unused = gameboard
+        .tiles()
+        .filter(|t| match t.feature().map(|f| &f.feature) {
+            Some(TileFeatures::Nest(team)) => !teams.contains(team),
+            _ => false,
+        })
+        .map(|t| t.pos_usize())
+        .collect::<Vec<(usize, usize)>>();
+    for (x, y) in unused {
+        gameboard.tile_mut(x, y).unwrap().remove_feature();
+    }
+
+    let mut units = Vec::<Unit>::new();
+    for team in teams {
+        let Some(nest) = gameboard.nest_of(team) else {
+            warn!("{:?} has no nest on this board", team.0);
+            continue;
+        };
+
+        for id in STARTING_ARMY {
+            let id = UnitID::new(id);
+            let Some(pos) = gameboard.free_tile_near(&units, nest, team, STARTING_ARMY_RANGE)
+            else {
+                warn!("No room for {:?}'s starting {}", team.0, id);
+                break;
+            };
+            match registry.spawn(&id, pos, team.clone()) {
+                Some(unit) => units.push(unit),
+                None => warn!("Starting army unit {} isn't defined", id),
+            }
+        }
+    }
+
+    return units;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::logic::{
+        economy::STARTING_BALANCE,
+        testing::{grass_board, unit},
+        Cost, TeamColour,
+    };
+
+    const RECRUIT: &str = "none_none_none_none_t0";
+
+    fn registry() -> UnitRegistry {
+        return UnitRegistry::from_toml(include_str!("../../../assets/data/units.toml"))
+            .unwrap_or_else(|err| panic!("{}", err));
+    }
+
+    fn board(width: u32, height: u32, nests: &[(usize, usize, TeamColour)]) -> Gameboard {
+        let mut gameboard = grass_board(width, height);
+        for (x, y, team) in nests {
+            gameboard
+                .tile_mut(*x, *y)
+                .unwrap()
+                .set_feature(TileFeatures::Nest(PlayerTeam(team.clone())));
+        }
+        return gameboard;
+    }
+
+    fn recruit(team: TeamColour, id: &str) -> RecruitOrder {
+        return RecruitOrder {
+            owner: PlayerTeam(team),
+            unit: UnitID::new(id),
+        };
+    }
+
+    struct Match {
+        gameboard: Gameboard,
+        registry: UnitRegistry,
+        economy: Economy,
+        queue: RecruitQueue,
+        units: Vec<Unit>,
+    }
+
+    impl Match {
+        fn new(gameboard: Gameboard, units: Vec<Unit>) -> Self {
+            return Self {
+                gameboard,
+                registry: registry(),
+                economy: Economy::default(),
+                queue: RecruitQueue::default(),
+                units,
+            };
+        }
+
+        fn turn(&mut self, orders: Vec<RecruitOrder>) -> Vec<TurnEvent> {
+            let mut events = Vec::new();
+            resolve_recruits(
+                &self.gameboard,
+                &self.registry,
+                &mut self.economy,
+                &mut self.queue,
+                &mut self.units,
+                orders,
+                &mut events,
+            );
+            return events;
+        }
+
+        fn recruited(&self) -> Vec<(Vec2, TeamColour)> {
+            return self
+                .units
+                .iter()
+                .filter(|u| u.id == UnitID::new(RECRUIT))
+                .map(|u| (u.pos, u.owner.0.clone()))
+                .collect();
+        }
+    }
+
+    #[test]
+    fn recruits_are_paid_for_and_come_out_one_a_turn() {
+        let mut game = Match::new(board(8, 8, &[(3, 3, TeamColour::Blue)]), Vec::new());
+        let blue = PlayerTeam(TeamColour::Blue);
+
+        let events = game.turn(vec![
+            recruit(TeamColour::Blue, RECRUIT),
+            recruit(TeamColour::Blue, RECRUIT),
+        ]);
+        let queued = events
+            .iter()
+            .filter(|e| matches!(e, TurnEvent::RecruitQueued { .. }))
+            .count();
+        assert_eq!(queued, 2);
+        assert_eq!(game.economy.balance(&blue), Cost::default());
+        assert_eq!(game.units.len(), RECRUITS_PER_TURN);
+        // Never on the nest itself
+        assert!(game.units.iter().all(|u| u.pos != Vec2::new(3f32, 3f32)));
+
+        // The second was already paid for, so it comes out next turn for free
+        let events = game.turn(Vec::new());
+        assert!(matches!(events[..], [TurnEvent::Recruited { .. }]));
+        assert_eq!(game.units.len(), 2);
+        assert_eq!(game.economy.balance(&blue), Cost::default());
+    }
+
+    #[test]
+    fn bad_recruits_fail_without_costing_anything() {
+        let mut game = Match::new(board(8, 8, &[(3, 3, TeamColour::Blue)]), Vec::new());
+        let blue = PlayerTeam(TeamColour::Blue);
+        game.economy.set_balance(
+            &blue,
+            Cost {
+                magic: 1,
+                science: 1,
+            },
+        );
+
+        let events = game.turn(vec![
+            recruit(TeamColour::Blue, "nobody"),
+            // Defined, but can't come out of a nest
+            recruit(TeamColour::Blue, STARTING_ARMY[2]),
+            // Red has no nest on this board
+            recruit(TeamColour::Red, RECRUIT),
+            recruit(TeamColour::Blue, RECRUIT),
+            recruit(TeamColour::Blue, RECRUIT),
+        ]);
+
+        let failures = events
+            .iter()
+            .filter_map(|e| match e {
+                TurnEvent::RecruitFailed { unit, reason, .. } => Some((unit.0.as_str(), reason)),
+                _ => None,
+            })
+            .collect::<Vec<(&str, &ActionFailure)>>();
+        assert_eq!(
+            failures,
+            vec![
+                ("nobody", &ActionFailure::InvalidTarget),
+                (STARTING_ARMY[2], &ActionFailure::InvalidTarget),
+                (RECRUIT, &ActionFailure::NoTarget),
+                (RECRUIT, &ActionFailure::CannotAfford),
+            ]
+        );
+        assert_eq!(game.units.len(), 1);
+        assert_eq!(game.economy.balance(&blue), Cost::default());
+        assert_eq!(
+            game.economy.balance(&PlayerTeam(TeamColour::Red)),
+            STARTING_BALANCE
+        );
+    }
+
+    #[test]
+    fn recruits_go_next_to_the_nest_or_wait_for_room() {
+        // The nest is in the corner, so only three tiles are next to it
+        let mut units = vec![
+            unit(TeamColour::Red, 0f32, 0f32),
+            unit(TeamColour::Red, 0f32, 1f32),
+            unit(TeamColour::Red, 1f32, 0f32),
+        ];
+        units[0].health.0 = 0f32;
+        let mut game = Match::new(board(8, 8, &[(0, 0, TeamColour::Blue)]), units);
+
+        game.turn(vec![recruit(TeamColour::Blue, RECRUIT)]);
+        assert_eq!(
+            game.recruited(),
+            vec![(Vec2::new(1f32, 1f32), TeamColour::Blue)]
+        );
+
+        // Now there's nowhere to go, so it waits at the front of the queue
+        let events = game.turn(vec![recruit(TeamColour::Blue, RECRUIT)]);
+        assert!(!events
+            .iter()
+            .any(|e| matches!(e, TurnEvent::Recruited { .. })));
+        assert_eq!(game.recruited().len(), 1);
+
+        game.units[1].pos = Vec2::new(5f32, 5f32);
+        game.turn(Vec::new());
+        assert_eq!(
+            game.recruited()[1],
+            (Vec2::new(0f32, 1f32), TeamColour::Blue)
+        );
+    }
+
+    #[test]
+    fn nests_sharing_a_tile_take_turns_in_board_order() {
+        let nests = [(0, 0, TeamColour::Red), (2, 0, TeamColour::Blue)];
+        let orders = vec![
+            recruit(TeamColour::Blue, RECRUIT),
+            recruit(TeamColour::Red, RECRUIT),
+        ];
+
+        let mut outcomes = Vec::new();
+        for orders in [orders.clone(), orders.into_iter().rev().collect()] {
+            // Only (1, 0) is free for either nest
+            let mut game = Match::new(board(3, 1, &nests), Vec::new());
+            game.turn(orders);
+            let first = game.recruited();
+            game.turn(Vec::new());
+            outcomes.push((first, game.recruited()));
+        }
+
+        assert_eq!(outcomes[0], outcomes[1]);
+        assert_eq!(
+            outcomes[0].0,
+            vec![(Vec2::new(1f32, 0f32), TeamColour::Red)]
+        );
+        // Blue is still waiting, since Red's recruit hasn't moved
+        assert_eq!(outcomes[0].1.len(), 1);
+    }
+}
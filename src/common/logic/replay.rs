@@ -0,0 +1,319 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use super::{
+    economy::Economy,
+    neo_gameboard::Gameboard,
+    recruitment::RecruitQueue,
+    rng::GameRng,
+    save::{read_versioned, write_versioned, SaveError},
+    turn::{play_turn, TurnOrders, TurnResult},
+    units::UnitRegistry,
+    victory::MatchState,
+    TurnCounter, Unit,
+};
+
+pub const REPLAY_DIR: &str = "./replays";
+const REPLAY_EXTENSION: &str = "replay";
+
+const REPLAY_MAGIC: [u8; 4] = *b"PCRP";
+// Bump this whenever anything in a replay, or the way turns resolve, changes.
+// Old replays would play out differently after that
+pub const REPLAY_VERSION: u32 = 2;
+
+// The whole match at the start of a turn
+#[derive(Clone, Deserialize, Serialize)]
+pub struct ReplayFrame {
+    pub turn: u32,
+    pub gameboard: Gameboard,
+    pub units: Vec<Unit>,
+    pub economy: Economy,
+    pub recruit_queue: RecruitQueue,
+    pub match_state: MatchState,
+}
+
+impl ReplayFrame {
+    // Plays a turn out the same way resolve_turns does, giving the start of the
+    // next one
+    pub fn step(&self, registry: &UnitRegistry, orders: &TurnOrders) -> (ReplayFrame, TurnResult) {
+        let mut next = self.clone();
+        let mut units = next
+            .units
+            .drain(..)
+            .map(|unit| ((), unit))
+            .collect::<Vec<((), Unit)>>();
+
+        let result = play_turn(
+            next.turn,
+            &mut next.gameboard,
+            registry,
+            &mut next.economy,
+            &mut next.recruit_queue,
+            &mut next.match_state,
+            &mut units,
+            orders.clone(),
+        );
+        next.units = units.into_iter().map(|(_, unit)| unit).collect();
+        next.turn += 1;
+        return (next, result);
+    }
+}
+
+// What was submitted in a turn, and what came of it at the time
+#[derive(Clone, Deserialize, Serialize)]
+pub struct ReplayTurn {
+    pub orders: TurnOrders,
+    pub result: TurnResult,
+}
+
+// Where the match started and every turn played since. Everything in between
+// is simulated again from `start`, so replays stay small
+#[derive(Clone, Deserialize, Serialize)]
+pub struct Replay {
+    // Only for reference, the board itself is in `start`
+    pub seed: u64,
+    pub start: ReplayFrame,
+    pub turns: Vec<ReplayTurn>,
+}
+
+impl Replay {
+    pub fn write(&self, path: &Path) -> Result<(), SaveError> {
+        return write_versioned(path, REPLAY_MAGIC, REPLAY_VERSION, self);
+    }
+
+    pub fn read(path: &Path) -> Result<Self, SaveError> {
+        return read_versioned(path, REPLAY_MAGIC, REPLAY_VERSION);
+    }
+}
+
+// The most recently written replay, if there are any
+pub fn latest_replay() -> Option<PathBuf> {
+    return fs::read_dir(REPLAY_DIR)
+        .ok()?
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.path().extension().and_then(|e| e.to_str()) == Some(REPLAY_EXTENSION))
+        .filter_map(|entry| Some((entry.metadata().ok()?.modified().ok()?, entry.path())))
+        .max_by_key(|(modified, _)| *modified)
+        .map(|(_, path)| path);
+}
+
+// Adds every resolved turn to the replay. The whole file is written again each
+// time, so a crash still leaves everything up to the last turn
+#[derive(Resource)]
+pub struct ReplayRecorder {
+    replay: Replay,
+    path: PathBuf,
+}
+
+impl ReplayRecorder {
+    pub fn new(seed: u64, start: ReplayFrame) -> Self {
+        let started = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_secs());
+        let path = Path::new(REPLAY_DIR)
+            .join(started.to_string())
+            .with_extension(REPLAY_EXTENSION);
+        info!("Recording replay to {}", path.display());
+
+        let recorder = Self {
+            replay: Replay {
+                seed,
+                start,
+                turns: Vec::new(),
+            },
+            path,
+        };
+        recorder.write();
+        return recorder;
+    }
+
+    pub fn record(&mut self, orders: TurnOrders, result: &TurnResult) {
+        self.replay.turns.push(ReplayTurn {
+            orders,
+            result: result.clone(),
+        });
+        self.write();
+    }
+
+    fn write(&self) {
+        if let Err(err) = self.replay.write(&self.path) {
+            error!("Unable to write replay {}: {}", self.path.display(), err);
+        }
+    }
+}
+
+// Run once the armies are on the board
+pub fn start_recording(
+    mut commands: Commands,
+    turn: Res<TurnCounter>,
+    economy: Res<Economy>,
+    recruit_queue: Res<RecruitQueue>,
+    match_state: Res<MatchState>,
+    rng: Res<GameRng>,
+    gameboard_q: Query<&Gameboard>,
+    units: Query<&Unit>,
+) {
+    let Ok(gameboard) = gameboard_q.get_single() else {
+        warn!("Not recording a replay without a gameboard");
+        return;
+    };
+
+    let start = ReplayFrame {
+        turn: turn.0,
+        gameboard: gameboard.clone(),
+        units: units.iter().cloned().collect(),
+        economy: economy.clone(),
+        recruit_queue: recruit_queue.clone(),
+        match_state: match_state.clone(),
+    };
+    commands.insert_resource(ReplayRecorder::new(rng.seed(), start));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::{
+        config::VictoryConfig,
+        logic::{
+            testing::{grass_board, order, unit},
+            turn::TurnEvent,
+            PlayerTeam, TeamColour, TileFeatures, TurnExecuteStages, UnitAction, UnitActions,
+        },
+    };
+
+    fn start() -> ReplayFrame {
+        let mut gameboard = grass_board(8, 8);
+        for (x, y, team) in [(0, 0, TeamColour::Blue), (7, 7, TeamColour::Red)] {
+            gameboard
+                .tile_mut(x, y)
+                .unwrap()
+                .set_feature(TileFeatures::Nest(PlayerTeam(team)));
+        }
+        let mut units = vec![
+            unit(TeamColour::Blue, 1f32, 1f32),
+            unit(TeamColour::Blue, 2f32, 1f32),
+            unit(TeamColour::Red, 5f32, 5f32),
+            unit(TeamColour::Red, 6f32, 5f32),
+        ];
+        units[0].attack.base = 6f32;
+
+        return ReplayFrame {
+            turn: 0,
+            gameboard,
+            units,
+            economy: Economy::default(),
+            recruit_queue: RecruitQueue::default(),
+            match_state: MatchState::new(
+                VictoryConfig::default(),
+                &[PlayerTeam(TeamColour::Blue), PlayerTeam(TeamColour::Red)],
+            ),
+        };
+    }
+
+    fn orders(actions: Vec<UnitAction>) -> TurnOrders {
+        return TurnOrders {
+            actions,
+            recruits: Vec::new(),
+        };
+    }
+
+    fn mv(from: (f32, f32), to: (f32, f32)) -> UnitAction {
+        return order(UnitActions::Move, TurnExecuteStages::MidTurn, from, to);
+    }
+
+    fn attack(from: (f32, f32), to: (f32, f32)) -> UnitAction {
+        return order(UnitActions::Attack, TurnExecuteStages::AfterTurn, from, to);
+    }
+
+    // Plays the match the way resolve_turns does, with units keyed by where
+    // they were in the ECS and in whatever order it hands them over, and
+    // records it as it goes
+    fn record(start: &ReplayFrame, turns: Vec<TurnOrders>) -> (Replay, Vec<Unit>) {
+        let registry = UnitRegistry::default();
+        let mut live = start.clone();
+        let mut units = live
+            .units
+            .iter()
+            .cloned()
+            .enumerate()
+            .map(|(i, unit)| (Some(i), unit))
+            .rev()
+            .collect::<Vec<(Option<usize>, Unit)>>();
+
+        let mut replay = Replay {
+            seed: 0,
+            start: start.clone(),
+            turns: Vec::new(),
+        };
+        for (turn, mut orders) in turns.into_iter().enumerate() {
+            let recorded = orders.clone();
+            orders.actions.reverse();
+            let result = play_turn(
+                turn as u32,
+                &mut live.gameboard,
+                &registry,
+                &mut live.economy,
+                &mut live.recruit_queue,
+                &mut live.match_state,
+                &mut units,
+                orders,
+            );
+            replay.turns.push(ReplayTurn {
+                orders: recorded,
+                result,
+            });
+        }
+        return (replay, units.into_iter().map(|(_, unit)| unit).collect());
+    }
+
+    #[test]
+    fn stepping_through_a_replay_gives_the_recorded_results() {
+        let (replay, live_units) = record(
+            &start(),
+            vec![
+                orders(vec![
+                    mv((1f32, 1f32), (3f32, 3f32)),
+                    mv((5f32, 5f32), (4f32, 4f32)),
+                    mv((6f32, 5f32), (4f32, 4f32)),
+                    mv((2f32, 1f32), (1f32, 1f32)),
+                ]),
+                orders(vec![
+                    mv((5f32, 5f32), (4f32, 4f32)),
+                    mv((1f32, 1f32), (3f32, 2f32)),
+                    attack((3f32, 3f32), (4f32, 3f32)),
+                ]),
+                orders(vec![
+                    attack((3f32, 3f32), (4f32, 4f32)),
+                    attack((4f32, 4f32), (3f32, 3f32)),
+                    attack((3f32, 2f32), (4f32, 3f32)),
+                ]),
+                orders(vec![attack((3f32, 3f32), (4f32, 4f32))]),
+            ],
+        );
+        assert!(replay.turns.iter().any(|turn| turn
+            .result
+            .events
+            .iter()
+            .any(|e| matches!(e, TurnEvent::Destroyed { .. }))));
+
+        let registry = UnitRegistry::default();
+        let mut frame = replay.start.clone();
+        for (turn, recorded) in replay.turns.iter().enumerate() {
+            let (next, result) = frame.step(&registry, &recorded.orders);
+            assert_eq!(result, recorded.result, "turn {}", turn);
+            frame = next;
+        }
+
+        assert_eq!(frame.turn, replay.turns.len() as u32);
+        assert_eq!(frame.units.len(), live_units.len());
+        for (a, b) in frame.units.iter().zip(&live_units) {
+            assert_eq!((a.pos, &a.health), (b.pos, &b.health));
+        }
+    }
+}
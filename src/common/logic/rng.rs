@@ -0,0 +1,58 @@
+use bevy::prelude::*;
+use rand::{Rng, RngCore, SeedableRng};
+use rand_chacha::ChaCha8Rng;
+use serde::{Deserialize, Serialize};
+
+use crate::common::config::Config;
+
+// Every bit of randomness in a match, from generating the board onwards, has
+// to come from here. ChaCha gives the same numbers on every platform, so the
+// same seed always gives the same board and the same game. Saves carry the
+// whole state, not just the seed, so a loaded game carries on where it was
+#[derive(Clone, Deserialize, Resource, Serialize)]
+pub struct GameRng {
+    seed: u64,
+    rng: ChaCha8Rng,
+}
+
+impl GameRng {
+    pub fn from_seed(seed: u64) -> Self {
+        return Self {
+            seed,
+            rng: ChaCha8Rng::seed_from_u64(seed),
+        };
+    }
+
+    pub fn seed(&self) -> u64 {
+        return self.seed;
+    }
+}
+
+impl RngCore for GameRng {
+    fn next_u32(&mut self) -> u32 {
+        return self.rng.next_u32();
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        return self.rng.next_u64();
+    }
+
+    fn fill_bytes(&mut self, dest: &mut [u8]) {
+        self.rng.fill_bytes(dest);
+    }
+
+    fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), rand::Error> {
+        return self.rng.try_fill_bytes(dest);
+    }
+}
+
+// Without a seed in config.toml, pick one. It's logged so the game can be
+// played again
+pub fn seed_game_rng(mut commands: Commands, config: Res<Config>) {
+    let seed = config
+        .gameboard_config
+        .seed
+        .unwrap_or_else(|| rand::thread_rng().gen());
+    info!("Seeding game with {}", seed);
+    commands.insert_resource(GameRng::from_seed(seed));
+}
@@ -0,0 +1,252 @@
+use std::{
+    fmt, fs,
+    io::{self, Read, Write},
+    path::Path,
+};
+
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+use super::{
+    economy::Economy, neo_gameboard::Gameboard, recruitment::RecruitQueue, rng::GameRng,
+    turn::TurnOrders, victory::MatchState, Unit, UnitAction,
+};
+
+pub const QUICKSAVE_PATH: &str = "./saves/quicksave.bin";
+
+// Every save starts with these, so anything else is turned away before
+// bincode gets a chance to misread it
+const SAVE_MAGIC: [u8; 4] = *b"PCSV";
+// Bump this whenever anything in a save changes shape. Old saves can't be
+// loaded after that
+pub const SAVE_VERSION: u32 = 1;
+
+// Everything needed to carry on a match exactly where it was left
+#[derive(Deserialize, Serialize)]
+pub struct SaveGame {
+    pub turn: u32,
+    pub gameboard: Gameboard,
+    // With the order each unit has been given this turn, if any
+    pub units: Vec<(Unit, Option<UnitAction>)>,
+    pub economy: Economy,
+    pub recruit_queue: RecruitQueue,
+    pub match_state: MatchState,
+    pub rng: GameRng,
+    // Orders that aren't attached to a unit
+    pub pending_orders: TurnOrders,
+}
+
+#[derive(Debug)]
+pub enum SaveError {
+    Io(io::Error),
+    Encode(bincode::Error),
+    Decode(bincode::Error),
+    NotASave,
+    VersionMismatch { save: u32, game: u32 },
+}
+
+impl fmt::Display for SaveError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        return match self {
+            SaveError::Io(err) => write!(f, "io error: {}", err),
+            SaveError::Encode(err) => write!(f, "unable to encode: {}", err),
+            SaveError::Decode(err) => write!(f, "unable to decode: {}", err),
+            SaveError::NotASave => write!(f, "not a file this game wrote"),
+            SaveError::VersionMismatch { save, game } => write!(
+                f,
+                "file is from version {}, but this game reads version {}",
+                save, game
+            ),
+        };
+    }
+}
+
+impl From<io::Error> for SaveError {
+    fn from(err: io::Error) -> Self {
+        return SaveError::Io(err);
+    }
+}
+
+impl SaveGame {
+    pub fn write(&self, path: &str) -> Result<(), SaveError> {
+        return write_versioned(Path::new(path), SAVE_MAGIC, SAVE_VERSION, self);
+    }
+
+    pub fn read(path: &str) -> Result<Self, SaveError> {
+        return read_versioned(Path::new(path), SAVE_MAGIC, SAVE_VERSION);
+    }
+}
+
+// Saves and replays are both a magic number, a big endian version, then
+// bincode. Written to a temporary file first, so a crash halfway through can't
+// ruin the last good one
+pub fn write_versioned<T: Serialize>(
+    path: &Path,
+    magic: [u8; 4],
+    version: u32,
+    value: &T,
+) -> Result<(), SaveError> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+
+    let data = bincode::serialize(value).map_err(SaveError::Encode)?;
+    let temp_path = path.with_extension("tmp");
+    let mut file = fs::File::create(&temp_path)?;
+    file.write_all(&magic)?;
+    file.write_all(&version.to_be_bytes())?;
+    file.write_all(&data)?;
+    file.sync_all()?;
+    fs::rename(temp_path, path)?;
+    return Ok(());
+}
+
+pub fn read_versioned<T: DeserializeOwned>(
+    path: &Path,
+    magic: [u8; 4],
+    version: u32,
+) -> Result<T, SaveError> {
+    let mut file = fs::File::open(path)?;
+    let mut header = [0u8; 8];
+    file.read_exact(&mut header)
+        .map_err(|_| SaveError::NotASave)?;
+    if header[..4] != magic {
+        return Err(SaveError::NotASave);
+    }
+    let file_version = u32::from_be_bytes([header[4], header[5], header[6], header[7]]);
+    if file_version != version {
+        return Err(SaveError::VersionMismatch {
+            save: file_version,
+            game: version,
+        });
+    }
+
+    let mut data = Vec::<u8>::new();
+    file.read_to_end(&mut data)?;
+    return bincode::deserialize(&data).map_err(SaveError::Decode);
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, path::PathBuf};
+
+    use rand::RngCore;
+
+    use super::*;
+    use crate::common::{
+        config::VictoryConfig,
+        logic::{
+            recruitment::RecruitOrder,
+            testing::{grass_board, order, unit},
+            units::UnitID,
+            PlayerTeam, TeamColour, TurnExecuteStages, UnitActions,
+        },
+    };
+
+    // Somewhere of its own in the temp directory, so tests running at the
+    // same time don't trip over each other
+    fn temp_path(name: &str) -> PathBuf {
+        return env::temp_dir()
+            .join(format!("calamity-save-test-{}", std::process::id()))
+            .join(name);
+    }
+
+    fn save() -> SaveGame {
+        let teams = [PlayerTeam(TeamColour::Blue), PlayerTeam(TeamColour::Red)];
+        let mut rng = GameRng::from_seed(99);
+        rng.next_u64();
+
+        let attack = order(
+            UnitActions::Attack,
+            TurnExecuteStages::MidTurn,
+            (1f32, 1f32),
+            (2f32, 2f32),
+        );
+        return SaveGame {
+            turn: 7,
+            gameboard: grass_board(16, 16),
+            units: vec![
+                (unit(TeamColour::Blue, 1f32, 1f32), Some(attack)),
+                (unit(TeamColour::Red, 2f32, 2f32), None),
+            ],
+            economy: Economy::default(),
+            recruit_queue: RecruitQueue::default(),
+            match_state: MatchState::new(VictoryConfig::default(), &teams),
+            rng,
+            pending_orders: TurnOrders {
+                actions: Vec::new(),
+                recruits: vec![RecruitOrder {
+                    owner: teams[1].clone(),
+                    unit: UnitID::new("scout"),
+                }],
+            },
+        };
+    }
+
+    #[test]
+    fn saves_load_back_the_same() {
+        let path = temp_path("round_trip.bin");
+        let saved = save();
+        saved.write(path.to_str().unwrap()).unwrap();
+
+        let mut loaded = SaveGame::read(path.to_str().unwrap()).unwrap();
+        assert_eq!(
+            bincode::serialize(&loaded).unwrap(),
+            bincode::serialize(&saved).unwrap()
+        );
+        // The rng carries on from where it was, not from the seed
+        assert_eq!(loaded.rng.next_u64(), saved.rng.clone().next_u64());
+        // Nothing left behind from writing it
+        assert!(!path.with_extension("tmp").exists());
+
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn other_files_arent_read_as_saves() {
+        let path = temp_path("not_a_save.bin");
+        write_versioned(&path, *b"PCRP", SAVE_VERSION, &save()).unwrap();
+        assert!(matches!(
+            SaveGame::read(path.to_str().unwrap()),
+            Err(SaveError::NotASave)
+        ));
+
+        // Too short to even have a header
+        fs::write(&path, b"PC").unwrap();
+        assert!(matches!(
+            SaveGame::read(path.to_str().unwrap()),
+            Err(SaveError::NotASave)
+        ));
+
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn saves_from_other_versions_are_turned_away() {
+        let path = temp_path("old_version.bin");
+        write_versioned(&path, SAVE_MAGIC, SAVE_VERSION + 1, &save()).unwrap();
+
+        let result = SaveGame::read(path.to_str().unwrap());
+        assert!(matches!(
+            result,
+            Err(SaveError::VersionMismatch { save, game })
+                if save == SAVE_VERSION + 1 && game == SAVE_VERSION
+        ));
+
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn truncated_saves_fail_to_decode() {
+        let path = temp_path("truncated.bin");
+        save().write(path.to_str().unwrap()).unwrap();
+        let data = fs::read(&path).unwrap();
+        fs::write(&path, &data[..data.len() / 2]).unwrap();
+
+        assert!(matches!(
+            SaveGame::read(path.to_str().unwrap()),
+            Err(SaveError::Decode(_))
+        ));
+
+        fs::remove_file(path).unwrap();
+    }
+}
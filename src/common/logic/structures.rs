@@ -0,0 +1,333 @@
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use super::{
+    combat::tile_distance,
+    economy::Economy,
+    neo_gameboard::Gameboard,
+    turn::{ActionFailure, TurnEvent},
+    Cost, Health, PlayerTeam, Terrain, Unit, UnitAction,
+};
+
+// How far from a tile a unit can be and still build on it
+pub const BUILD_RANGE: i32 = 1;
+
+#[derive(Clone, Copy, Debug, Deserialize, Eq, FromReflect, Hash, PartialEq, Reflect, Serialize)]
+pub enum StructureKind {
+    Wall,
+    Watchtower,
+    Outpost,
+}
+
+impl StructureKind {
+    pub fn cost(&self) -> Cost {
+        return match self {
+            StructureKind::Wall => Cost {
+                magic: 0,
+                science: 2,
+            },
+            StructureKind::Watchtower => Cost {
+                magic: 2,
+                science: 2,
+            },
+            StructureKind::Outpost => Cost {
+                magic: 4,
+                science: 4,
+            },
+        };
+    }
+
+    // Turns of building needed before the structure does anything
+    pub fn build_turns(&self) -> u32 {
+        return match self {
+            StructureKind::Wall => 1,
+            StructureKind::Watchtower => 2,
+            StructureKind::Outpost => 3,
+        };
+    }
+
+    pub fn max_health(&self) -> f32 {
+        return match self {
+            StructureKind::Wall => 15f32,
+            StructureKind::Watchtower => 8f32,
+            StructureKind::Outpost => 12f32,
+        };
+    }
+
+    // How far the owner can see from the structure
+    pub fn vision_range(&self) -> i32 {
+        return match self {
+            StructureKind::Wall => 1,
+            StructureKind::Watchtower => 5,
+            StructureKind::Outpost => 2,
+        };
+    }
+
+    // Multiplier on the cost of moving onto the tile. None means the tile
+    // can't be entered at all
+    pub fn movement_modifier(&self, owned: bool) -> Option<f32> {
+        return match (self, owned) {
+            // Walls only let their owner through
+            (StructureKind::Wall, true) => Some(1f32),
+            (StructureKind::Wall, false) => None,
+            (StructureKind::Watchtower, _) => Some(1f32),
+            // Outposts double as roads for whoever owns them
+            (StructureKind::Outpost, true) => Some(0.5),
+            (StructureKind::Outpost, false) => Some(1f32),
+        };
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, FromReflect, PartialEq, Reflect, Serialize)]
+pub struct Structure {
+    pub kind: StructureKind,
+    pub owner: PlayerTeam,
+    pub health: Health,
+    pub build_progress: u32,
+}
+
+impl Structure {
+    pub fn new(kind: StructureKind, owner: PlayerTeam) -> Self {
+        return Self {
+            kind,
+            owner,
+            health: Health(kind.max_health()),
+            build_progress: 0,
+        };
+    }
+
+    pub fn is_complete(&self) -> bool {
+        return self.build_progress >= self.kind.build_turns();
+    }
+}
+
+// Every unit building on a tile this stage adds a turn of progress. Starting a
+// new structure counts as its first turn
+pub fn resolve_builds(
+    gameboard: &mut Gameboard,
+    economy: &mut Economy,
+    units: &[Unit],
+    builds: Vec<(usize, UnitAction, StructureKind)>,
+    events: &mut Vec<TurnEvent>,
+) {
+    for (i, action, kind) in builds {
+        let builder = &units[i];
+        if tile_distance(builder.pos, action.action_pos) > BUILD_RANGE {
+            events.push(TurnEvent::ActionFailed {
+                action,
+                reason: ActionFailure::OutOfRange,
+            });
+            continue;
+        }
+
+        let enemy_on_tile = units
+            .iter()
+            .any(|u| u.is_alive() && u.pos == action.action_pos && u.owner != builder.owner);
+        let (x, y) = (action.action_pos.x as usize, action.action_pos.y as usize);
+        let Some(tile) = gameboard.tile_mut(x, y) else {
+            events.push(TurnEvent::ActionFailed {
+                action,
+                reason: ActionFailure::InvalidTarget,
+            });
+            continue;
+        };
+
+        let buildable = !enemy_on_tile
+            && tile.feature().is_none()
+            && !matches!(tile.terrain(), Terrain::Water | Terrain::ShallowWater);
+        let structure = match tile.structure_mut() {
+            // Structures are paid for up front, when building starts
+            None if buildable => {
+                if !economy.spend(&builder.owner, kind.cost()) {
+                    events.push(TurnEvent::ActionFailed {
+                        action,
+                        reason: ActionFailure::CannotAfford,
+                    });
+                    continue;
+                }
+                tile.set_structure(Structure::new(kind, builder.owner.clone()))
+            }
+            Some(existing)
+                if buildable
+                    && existing.kind == kind
+                    && existing.owner == builder.owner
+                    && !existing.is_complete() =>
+            {
+                existing
+            }
+            _ => {
+                events.push(TurnEvent::ActionFailed {
+                    action,
+                    reason: ActionFailure::InvalidTarget,
+                });
+                continue;
+            }
+        };
+
+        structure.build_progress += 1;
+        if structure.is_complete() {
+            events.push(TurnEvent::Built {
+                pos: action.action_pos,
+                kind,
+                owner: structure.owner.clone(),
+            });
+        } else {
+            events.push(TurnEvent::BuildProgress {
+                pos: action.action_pos,
+                kind,
+                progress: structure.build_progress,
+                required: kind.build_turns(),
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::logic::{
+        economy::STARTING_BALANCE,
+        testing::{grass_board, order, unit},
+        TeamColour, TurnExecuteStages, UnitActions,
+    };
+
+    fn build(
+        gameboard: &mut Gameboard,
+        economy: &mut Economy,
+        units: &[Unit],
+        target: (f32, f32),
+        kind: StructureKind,
+    ) -> Vec<TurnEvent> {
+        let from = (units[0].pos.x, units[0].pos.y);
+        let builds = vec![(
+            0,
+            order(
+                UnitActions::Build(kind),
+                TurnExecuteStages::MidTurn,
+                from,
+                target,
+            ),
+            kind,
+        )];
+        let mut events = Vec::new();
+        resolve_builds(gameboard, economy, units, builds, &mut events);
+        return events;
+    }
+
+    fn failure(events: &[TurnEvent]) -> Option<ActionFailure> {
+        return events.iter().find_map(|event| match event {
+            TurnEvent::ActionFailed { reason, .. } => Some(reason.clone()),
+            _ => None,
+        });
+    }
+
+    #[test]
+    fn structures_are_paid_for_once_and_built_over_turns() {
+        let (mut gameboard, mut economy) = (grass_board(8, 8), Economy::default());
+        let units = vec![unit(TeamColour::Blue, 0f32, 0f32)];
+        let blue = units[0].owner.clone();
+
+        let events = build(
+            &mut gameboard,
+            &mut economy,
+            &units,
+            (1f32, 0f32),
+            StructureKind::Watchtower,
+        );
+        assert_eq!(
+            events,
+            vec![TurnEvent::BuildProgress {
+                pos: Vec2::new(1f32, 0f32),
+                kind: StructureKind::Watchtower,
+                progress: 1,
+                required: 2,
+            }]
+        );
+        assert_eq!(economy.balance(&blue), Cost::default());
+
+        let events = build(
+            &mut gameboard,
+            &mut economy,
+            &units,
+            (1f32, 0f32),
+            StructureKind::Watchtower,
+        );
+        assert_eq!(
+            events,
+            vec![TurnEvent::Built {
+                pos: Vec2::new(1f32, 0f32),
+                kind: StructureKind::Watchtower,
+                owner: blue.clone(),
+            }]
+        );
+        assert!(gameboard
+            .tile(1, 0)
+            .unwrap()
+            .structure()
+            .unwrap()
+            .is_complete());
+
+        // Finished structures can't be built any further
+        let events = build(
+            &mut gameboard,
+            &mut economy,
+            &units,
+            (1f32, 0f32),
+            StructureKind::Watchtower,
+        );
+        assert_eq!(failure(&events), Some(ActionFailure::InvalidTarget));
+    }
+
+    #[test]
+    fn structures_have_to_be_affordable() {
+        let (mut gameboard, mut economy) = (grass_board(8, 8), Economy::default());
+        let units = vec![unit(TeamColour::Blue, 0f32, 0f32)];
+
+        let events = build(
+            &mut gameboard,
+            &mut economy,
+            &units,
+            (1f32, 0f32),
+            StructureKind::Outpost,
+        );
+        assert_eq!(failure(&events), Some(ActionFailure::CannotAfford));
+        assert!(gameboard.tile(1, 0).unwrap().structure().is_none());
+        assert_eq!(economy.balance(&units[0].owner), STARTING_BALANCE);
+    }
+
+    #[test]
+    fn enemies_on_the_tile_stop_building() {
+        let (mut gameboard, mut economy) = (grass_board(8, 8), Economy::default());
+        let units = vec![
+            unit(TeamColour::Blue, 0f32, 0f32),
+            unit(TeamColour::Red, 1f32, 0f32),
+        ];
+
+        let events = build(
+            &mut gameboard,
+            &mut economy,
+            &units,
+            (1f32, 0f32),
+            StructureKind::Wall,
+        );
+        assert_eq!(failure(&events), Some(ActionFailure::InvalidTarget));
+        assert!(gameboard.tile(1, 0).unwrap().structure().is_none());
+        assert_eq!(economy.balance(&units[0].owner), STARTING_BALANCE);
+    }
+
+    #[test]
+    fn builders_have_to_be_next_to_the_tile() {
+        let (mut gameboard, mut economy) = (grass_board(8, 8), Economy::default());
+        let units = vec![unit(TeamColour::Blue, 0f32, 0f32)];
+
+        let events = build(
+            &mut gameboard,
+            &mut economy,
+            &units,
+            (2f32, 0f32),
+            StructureKind::Wall,
+        );
+        assert_eq!(failure(&events), Some(ActionFailure::OutOfRange));
+        assert_eq!(economy.balance(&units[0].owner), STARTING_BALANCE);
+    }
+}
@@ -0,0 +1,55 @@
+use bevy::prelude::*;
+
+use super::{
+    neo_gameboard::Gameboard, units::UnitID, Archetype, Archetypes, Attack, Defense, Health,
+    Movement, PlayerTeam, TeamColour, TurnExecuteStage, TurnExecuteStages, Unit, UnitAction,
+    UnitActions,
+};
+
+// Everything open grass, so moving anywhere costs the same
+pub fn grass_board(width: u32, height: u32) -> Gameboard {
+    return Gameboard::unexplored(width, height);
+}
+
+// A plain unit with nothing but a short ranged attack. Tests change whatever
+// they care about
+pub fn unit(team: TeamColour, x: f32, y: f32) -> Unit {
+    return Unit {
+        id: UnitID::new("test"),
+        pos: Vec2::new(x, y),
+        health: Health(10f32),
+        max_health: Health(10f32),
+        attack: Attack {
+            base: 3f32,
+            range: 1,
+            splash: false,
+            splash_multiplier: 1f32,
+            magic_multiplier: 1f32,
+            science_multiplier: 1f32,
+        },
+        heal: None,
+        defense: Defense {
+            base: 1f32,
+            magic_multiplier: 1f32,
+            science_multiplier: 1f32,
+        },
+        movement: Movement(3),
+        turn_execute_stage: TurnExecuteStage::default(),
+        archetype: Archetype(Archetypes::None),
+        owner: PlayerTeam(team),
+    };
+}
+
+pub fn order(
+    action_type: UnitActions,
+    stage: TurnExecuteStages,
+    from: (f32, f32),
+    to: (f32, f32),
+) -> UnitAction {
+    return UnitAction {
+        action_type,
+        turn_stage: TurnExecuteStage(stage),
+        curr_pos: Vec2::new(from.0, from.1),
+        action_pos: Vec2::new(to.0, to.1),
+    };
+}
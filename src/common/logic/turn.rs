@@ -0,0 +1,744 @@
+use std::cmp::Ordering;
+
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use super::{
+    combat::resolve_attacks,
+    economy::{resolve_economy, Economy},
+    healing::resolve_heals,
+    neo_gameboard::Gameboard,
+    recruitment::{resolve_recruits, RecruitOrder, RecruitQueue},
+    replay::ReplayRecorder,
+    structures::{resolve_builds, StructureKind},
+    units::{UnitID, UnitRegistry},
+    upgrades::resolve_upgrades,
+    victory::{check_victory, MatchState},
+    Cost, PlayerTeam, TurnCounter, TurnExecuteStages, Unit, UnitAction, UnitActions,
+};
+
+// Ask for the current turn to be resolved. Singleplayer sends this when the
+// player ends their turn, the server once every player has submitted orders
+pub struct ResolveTurnEvent;
+
+pub struct TurnResolvedEvent(pub TurnResult);
+
+// Everything the players asked for in a turn
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
+pub struct TurnOrders {
+    pub actions: Vec<UnitAction>,
+    pub recruits: Vec<RecruitOrder>,
+}
+
+impl TurnOrders {
+    // The same orders always resolve the same way, whatever order they came
+    // in. There's only one order per unit, so its position is enough
+    pub fn sort(&mut self) {
+        self.actions
+            .sort_by(|a, b| board_order(a.curr_pos, b.curr_pos));
+        self.recruits.sort_by_key(|r| r.owner.0.clone() as u8);
+    }
+}
+
+// Units are resolved in board order, so the outcome never depends on the order
+// the ECS hands them over in. Replays rely on this
+pub fn board_order(a: Vec2, b: Vec2) -> Ordering {
+    return a.x.total_cmp(&b.x).then(a.y.total_cmp(&b.y));
+}
+
+// Orders that aren't attached to a unit as a UnitAction component, i.e. ones
+// that came in over the network, and every recruit
+#[derive(Debug, Default, Resource)]
+pub struct PendingOrders(pub TurnOrders);
+
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
+pub struct TurnResult {
+    pub turn: u32,
+    pub events: Vec<TurnEvent>,
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub enum TurnEvent {
+    Moved {
+        from: Vec2,
+        to: Vec2,
+        owner: PlayerTeam,
+    },
+    ActionFailed {
+        action: UnitAction,
+        reason: ActionFailure,
+    },
+    Damaged {
+        pos: Vec2,
+        amount: f32,
+        // Where it came from. Players who can't see that tile get None
+        source: Option<Vec2>,
+    },
+    Healed {
+        pos: Vec2,
+        amount: f32,
+        source: Option<Vec2>,
+    },
+    BuildProgress {
+        pos: Vec2,
+        kind: StructureKind,
+        progress: u32,
+        required: u32,
+    },
+    Built {
+        pos: Vec2,
+        kind: StructureKind,
+        owner: PlayerTeam,
+    },
+    StructureDamaged {
+        pos: Vec2,
+        amount: f32,
+        source: Option<Vec2>,
+    },
+    StructureDestroyed {
+        pos: Vec2,
+        kind: StructureKind,
+        owner: PlayerTeam,
+    },
+    Destroyed {
+        pos: Vec2,
+        owner: PlayerTeam,
+    },
+    Upgraded {
+        pos: Vec2,
+        from: UnitID,
+        to: UnitID,
+        cost: Cost,
+    },
+    SiteCaptured {
+        pos: Vec2,
+        owner: PlayerTeam,
+    },
+    Income {
+        owner: PlayerTeam,
+        amount: Cost,
+    },
+    RecruitQueued {
+        owner: PlayerTeam,
+        unit: UnitID,
+    },
+    RecruitFailed {
+        owner: PlayerTeam,
+        unit: UnitID,
+        reason: ActionFailure,
+    },
+    Recruited {
+        pos: Vec2,
+        owner: PlayerTeam,
+        unit: UnitID,
+    },
+    NestRazed {
+        pos: Vec2,
+        owner: PlayerTeam,
+    },
+    // Out of the match. Everything they had left is gone
+    Eliminated {
+        owner: PlayerTeam,
+    },
+    // Empty if nobody was left standing
+    MatchOver {
+        winners: Vec<PlayerTeam>,
+    },
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub enum ActionFailure {
+    NoUnit,
+    DuplicateOrder,
+    UnitDestroyed,
+    OutOfRange,
+    // Another unit is in the way, or wanted the same tile
+    Blocked,
+    NoTarget,
+    InvalidTarget,
+    // e.g. trying to heal with a unit that has no HealAction
+    MissingAbility,
+    // The unit can't be promoted to that type
+    InvalidUpgrade,
+    CannotAfford,
+}
+
+const STAGES: [TurnExecuteStages; 3] = [
+    TurnExecuteStages::PreTurn,
+    TurnExecuteStages::MidTurn,
+    TurnExecuteStages::AfterTurn,
+];
+
+pub fn resolve_turns(
+    mut commands: Commands,
+    mut resolve_evr: EventReader<ResolveTurnEvent>,
+    mut resolved_evw: EventWriter<TurnResolvedEvent>,
+    mut turn: ResMut<TurnCounter>,
+    mut pending_orders: ResMut<PendingOrders>,
+    registry: Res<UnitRegistry>,
+    mut economy: ResMut<Economy>,
+    mut recruit_queue: ResMut<RecruitQueue>,
+    mut match_state: ResMut<MatchState>,
+    recorder: Option<ResMut<ReplayRecorder>>,
+    mut gameboard_q: Query<&mut Gameboard>,
+    mut units_q: Query<(Entity, &mut Unit, Option<&UnitAction>)>,
+) {
+    if resolve_evr.iter().count() == 0 {
+        return;
+    }
+    if match_state.is_over() {
+        info!("The match is over, not resolving any more turns");
+        return;
+    }
+    let Ok(mut gameboard) = gameboard_q.get_single_mut() else {
+        warn!("Unable to resolve turn without a gameboard");
+        return;
+    };
+
+    let mut orders = std::mem::take(&mut pending_orders.0);
+    let mut units = Vec::<(Option<Entity>, Unit)>::new();
+    for (entity, unit, action) in units_q.iter() {
+        units.push((Some(entity), unit.clone()));
+        if let Some(action) = action {
+            orders.actions.push(action.clone());
+            commands.entity(entity).remove::<UnitAction>();
+        }
+    }
+    let entities = units
+        .iter()
+        .filter_map(|(entity, _)| *entity)
+        .collect::<Vec<Entity>>();
+    let recorded_orders = recorder.as_ref().map(|_| orders.clone());
+
+    let result = play_turn(
+        turn.0,
+        &mut gameboard,
+        &registry,
+        &mut economy,
+        &mut recruit_queue,
+        &mut match_state,
+        &mut units,
+        orders,
+    );
+    if let (Some(mut recorder), Some(orders)) = (recorder, recorded_orders) {
+        recorder.record(orders, &result);
+    }
+
+    for entity in entities {
+        if !units.iter().any(|(e, _)| *e == Some(entity)) {
+            commands.entity(entity).despawn_recursive();
+        }
+    }
+    for (entity, unit) in units {
+        match entity {
+            Some(entity) => {
+                if let Ok((_, mut ecs_unit, _)) = units_q.get_mut(entity) {
+                    *ecs_unit = unit;
+                }
+            }
+            None => {
+                commands.spawn(unit).insert(Name::new("Unit"));
+            }
+        }
+    }
+
+    info!(
+        "Resolved turn {} ({} events)",
+        result.turn,
+        result.events.len()
+    );
+    turn.0 += 1;
+    resolved_evw.send(TurnResolvedEvent(result));
+}
+
+// Plays out a whole turn, the same way on every machine whatever order the
+// units and orders come in. This is the only way turns get played, so replays
+// always match what happened. Each unit carries a key (i.e. its entity) for
+// the caller to match it back up with, and recruits get the default one. Only
+// units still alive afterwards are kept
+pub fn play_turn<K: Default>(
+    turn: u32,
+    gameboard: &mut Gameboard,
+    registry: &UnitRegistry,
+    economy: &mut Economy,
+    recruit_queue: &mut RecruitQueue,
+    match_state: &mut MatchState,
+    units: &mut Vec<(K, Unit)>,
+    mut orders: TurnOrders,
+) -> TurnResult {
+    units.sort_by(|a, b| board_order(a.1.pos, b.1.pos));
+    orders.sort();
+
+    let (mut keys, mut resolved): (Vec<K>, Vec<Unit>) = std::mem::take(units).into_iter().unzip();
+    let mut result = resolve_turn(
+        turn,
+        gameboard,
+        registry,
+        economy,
+        recruit_queue,
+        &mut resolved,
+        orders,
+    );
+    check_victory(
+        match_state,
+        turn,
+        gameboard,
+        &mut resolved,
+        &mut result.events,
+    );
+
+    // Anything past the end of `keys` was recruited this turn
+    keys.resize_with(resolved.len(), K::default);
+    *units = keys
+        .into_iter()
+        .zip(resolved)
+        .filter(|(_, unit)| unit.is_alive())
+        .collect();
+    return result;
+}
+
+// Resolves every order for a turn against `units`. Everything is simultaneous
+// within a stage, and stages run PreTurn -> MidTurn -> AfterTurn. Units are
+// never removed here, anything that isn't alive afterwards should be despawned
+// by the caller. Recruits are added to the end of `units`
+pub fn resolve_turn(
+    turn: u32,
+    gameboard: &mut Gameboard,
+    registry: &UnitRegistry,
+    economy: &mut Economy,
+    recruit_queue: &mut RecruitQueue,
+    units: &mut Vec<Unit>,
+    orders: TurnOrders,
+) -> TurnResult {
+    let mut result = TurnResult {
+        turn,
+        events: Vec::new(),
+    };
+
+    // The order orders arrive in depends on the ECS and the network, so sort
+    // them into something every machine agrees on
+    let TurnOrders {
+        mut actions,
+        recruits,
+    } = orders;
+    actions.sort_by(|a, b| {
+        a.turn_stage
+            .0
+            .cmp(&b.turn_stage.0)
+            .then(board_order(a.curr_pos, b.curr_pos))
+    });
+
+    // Pair every order with its unit before anything moves
+    let mut orders = Vec::<(usize, UnitAction)>::with_capacity(actions.len());
+    for action in actions {
+        match units.iter().position(|u| u.pos == action.curr_pos) {
+            None => fail(&mut result, action, ActionFailure::NoUnit),
+            Some(i) if orders.iter().any(|(j, _)| *j == i) => {
+                fail(&mut result, action, ActionFailure::DuplicateOrder)
+            }
+            Some(i) => orders.push((i, action)),
+        }
+    }
+
+    for stage in STAGES {
+        let alive_before = units.iter().map(|u| u.is_alive()).collect::<Vec<bool>>();
+        let mut moves = Vec::<(usize, UnitAction)>::new();
+        let mut attacks = Vec::<(usize, UnitAction)>::new();
+        let mut heals = Vec::<(usize, UnitAction)>::new();
+        let mut builds = Vec::<(usize, UnitAction, StructureKind)>::new();
+        let mut upgrades = Vec::<(usize, UnitAction, UnitID)>::new();
+        for (i, action) in orders.iter().filter(|(_, a)| a.turn_stage.0 == stage) {
+            if !units[*i].is_alive() {
+                fail(&mut result, action.clone(), ActionFailure::UnitDestroyed);
+                continue;
+            }
+
+            match &action.action_type {
+                UnitActions::Move => moves.push((*i, action.clone())),
+                UnitActions::Attack => attacks.push((*i, action.clone())),
+                UnitActions::Heal => heals.push((*i, action.clone())),
+                UnitActions::Build(kind) => builds.push((*i, action.clone(), *kind)),
+                UnitActions::Upgrade(id) => upgrades.push((*i, action.clone(), id.clone())),
+            }
+        }
+
+        // Units move before anyone attacks, so attacks land wherever their
+        // target ended up
+        resolve_moves(gameboard, units, moves, &mut result);
+        resolve_attacks(gameboard, units, attacks, &mut result.events);
+        resolve_heals(units, heals, &mut result.events);
+        resolve_builds(gameboard, economy, units, builds, &mut result.events);
+        resolve_upgrades(registry, economy, units, upgrades, &mut result.events);
+
+        for (i, unit) in units.iter().enumerate() {
+            if alive_before[i] && !unit.is_alive() {
+                result.events.push(TurnEvent::Destroyed {
+                    pos: unit.pos,
+                    owner: unit.owner.clone(),
+                });
+            }
+        }
+    }
+
+    // New units come out after the fighting's done, and are paid for before
+    // this turn's income
+    resolve_recruits(
+        gameboard,
+        registry,
+        economy,
+        recruit_queue,
+        units,
+        recruits,
+        &mut result.events,
+    );
+    resolve_economy(gameboard, units, economy, &mut result.events);
+
+    return result;
+}
+
+fn resolve_moves(
+    gameboard: &Gameboard,
+    units: &mut [Unit],
+    moves: Vec<(usize, UnitAction)>,
+    result: &mut TurnResult,
+) {
+    let mut pending = Vec::<(usize, UnitAction)>::with_capacity(moves.len());
+    for (i, action) in moves {
+        // Tiles the unit can't enter (e.g. enemy walls) never get a cost
+        let unit = &units[i];
+        if gameboard
+            .shortest_paths(unit.pos, &unit.owner, Some(unit.movement.0 as f32))
+            .cost(action.action_pos)
+            .is_none()
+        {
+            fail(result, action, ActionFailure::OutOfRange);
+            continue;
+        }
+        pending.push((i, action));
+    }
+
+    // Keep throwing out moves that can't happen until everything left can.
+    // Bouncing one move can block another (it's now moving into a unit that
+    // stayed put), hence the loop
+    loop {
+        let mut rejected = Vec::<usize>::new();
+        for (k, (i, action)) in pending.iter().enumerate() {
+            let target = action.action_pos;
+
+            let contested = pending
+                .iter()
+                .filter(|(_, other)| other.action_pos == target)
+                .count()
+                > 1;
+
+            let blocked = match units
+                .iter()
+                .enumerate()
+                .find(|(j, u)| *j != *i && u.is_alive() && u.pos == target)
+            {
+                None => false,
+                Some((j, _)) => match pending.iter().find(|(m, _)| *m == j) {
+                    // The occupant is staying put
+                    None => true,
+                    // Units can't swap places by walking through each other
+                    Some((_, occupant_move)) => occupant_move.action_pos == units[*i].pos,
+                },
+            };
+
+            if contested || blocked {
+                rejected.push(k);
+            }
+        }
+
+        if rejected.is_empty() {
+            break;
+        }
+        for k in rejected.into_iter().rev() {
+            let (_, action) = pending.remove(k);
+            fail(result, action, ActionFailure::Blocked);
+        }
+    }
+
+    for (i, action) in pending {
+        result.events.push(TurnEvent::Moved {
+            from: units[i].pos,
+            to: action.action_pos,
+            owner: units[i].owner.clone(),
+        });
+        units[i].pos = action.action_pos;
+    }
+}
+
+fn fail(result: &mut TurnResult, action: UnitAction, reason: ActionFailure) {
+    result
+        .events
+        .push(TurnEvent::ActionFailed { action, reason });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::logic::{
+        testing::{grass_board, order, unit},
+        TeamColour, TurnExecuteStages,
+    };
+
+    fn resolve(units: &mut Vec<Unit>, actions: Vec<UnitAction>) -> TurnResult {
+        return resolve_turn(
+            0,
+            &mut grass_board(8, 8),
+            &UnitRegistry::default(),
+            &mut Economy::default(),
+            &mut RecruitQueue::default(),
+            units,
+            TurnOrders {
+                actions,
+                recruits: Vec::new(),
+            },
+        );
+    }
+
+    fn failures(result: &TurnResult) -> Vec<(Vec2, ActionFailure)> {
+        return result
+            .events
+            .iter()
+            .filter_map(|event| match event {
+                TurnEvent::ActionFailed { action, reason } => {
+                    Some((action.curr_pos, reason.clone()))
+                }
+                _ => None,
+            })
+            .collect();
+    }
+
+    fn mv(from: (f32, f32), to: (f32, f32)) -> UnitAction {
+        return order(UnitActions::Move, TurnExecuteStages::MidTurn, from, to);
+    }
+
+    #[test]
+    fn stages_run_in_order_whatever_order_they_were_given_in() {
+        let mut units = vec![
+            unit(TeamColour::Blue, 0f32, 0f32),
+            unit(TeamColour::Red, 1f32, 0f32),
+            unit(TeamColour::Blue, 5f32, 5f32),
+        ];
+        units[0].attack.base = 20f32;
+
+        let result = resolve(
+            &mut units,
+            vec![
+                order(
+                    UnitActions::Move,
+                    TurnExecuteStages::AfterTurn,
+                    (5f32, 5f32),
+                    (6f32, 6f32),
+                ),
+                mv((1f32, 0f32), (2f32, 0f32)),
+                order(
+                    UnitActions::Attack,
+                    TurnExecuteStages::PreTurn,
+                    (0f32, 0f32),
+                    (1f32, 0f32),
+                ),
+            ],
+        );
+
+        // The PreTurn attack kills the red unit before its MidTurn move
+        assert!(!units[1].is_alive());
+        assert_eq!(units[1].pos, Vec2::new(1f32, 0f32));
+        assert_eq!(
+            failures(&result),
+            vec![(Vec2::new(1f32, 0f32), ActionFailure::UnitDestroyed)]
+        );
+
+        let damaged = result
+            .events
+            .iter()
+            .position(|e| matches!(e, TurnEvent::Damaged { .. }))
+            .unwrap();
+        let destroyed = result
+            .events
+            .iter()
+            .position(|e| matches!(e, TurnEvent::Destroyed { .. }))
+            .unwrap();
+        let failed = result
+            .events
+            .iter()
+            .position(|e| matches!(e, TurnEvent::ActionFailed { .. }))
+            .unwrap();
+        let moved = result
+            .events
+            .iter()
+            .position(|e| matches!(e, TurnEvent::Moved { .. }))
+            .unwrap();
+        assert!(damaged < destroyed && destroyed < failed && failed < moved);
+        assert_eq!(units[2].pos, Vec2::new(6f32, 6f32));
+    }
+
+    #[test]
+    fn units_wanting_the_same_tile_both_stay_put() {
+        let mut units = vec![
+            unit(TeamColour::Blue, 0f32, 0f32),
+            unit(TeamColour::Red, 2f32, 0f32),
+        ];
+
+        let result = resolve(
+            &mut units,
+            vec![
+                mv((0f32, 0f32), (1f32, 0f32)),
+                mv((2f32, 0f32), (1f32, 0f32)),
+            ],
+        );
+
+        assert_eq!(units[0].pos, Vec2::new(0f32, 0f32));
+        assert_eq!(units[1].pos, Vec2::new(2f32, 0f32));
+        assert_eq!(
+            failures(&result),
+            vec![
+                (Vec2::new(2f32, 0f32), ActionFailure::Blocked),
+                (Vec2::new(0f32, 0f32), ActionFailure::Blocked),
+            ]
+        );
+    }
+
+    #[test]
+    fn units_can_follow_each_other_into_vacated_tiles() {
+        let mut units = vec![
+            unit(TeamColour::Blue, 0f32, 0f32),
+            unit(TeamColour::Blue, 1f32, 0f32),
+        ];
+
+        let result = resolve(
+            &mut units,
+            vec![
+                mv((0f32, 0f32), (1f32, 0f32)),
+                mv((1f32, 0f32), (2f32, 0f32)),
+            ],
+        );
+
+        assert!(failures(&result).is_empty());
+        assert_eq!(units[0].pos, Vec2::new(1f32, 0f32));
+        assert_eq!(units[1].pos, Vec2::new(2f32, 0f32));
+    }
+
+    #[test]
+    fn a_bounced_move_blocks_whoever_was_following() {
+        let mut units = vec![
+            unit(TeamColour::Blue, 0f32, 0f32),
+            unit(TeamColour::Blue, 1f32, 0f32),
+            unit(TeamColour::Red, 3f32, 0f32),
+        ];
+
+        let result = resolve(
+            &mut units,
+            vec![
+                mv((0f32, 0f32), (1f32, 0f32)),
+                mv((1f32, 0f32), (2f32, 0f32)),
+                mv((3f32, 0f32), (2f32, 0f32)),
+            ],
+        );
+
+        assert_eq!(failures(&result).len(), 3);
+        assert_eq!(units[0].pos, Vec2::new(0f32, 0f32));
+        assert_eq!(units[1].pos, Vec2::new(1f32, 0f32));
+        assert_eq!(units[2].pos, Vec2::new(3f32, 0f32));
+    }
+
+    #[test]
+    fn units_cant_swap_places() {
+        let mut units = vec![
+            unit(TeamColour::Blue, 0f32, 0f32),
+            unit(TeamColour::Red, 1f32, 0f32),
+        ];
+
+        let result = resolve(
+            &mut units,
+            vec![
+                mv((0f32, 0f32), (1f32, 0f32)),
+                mv((1f32, 0f32), (0f32, 0f32)),
+            ],
+        );
+
+        assert_eq!(failures(&result).len(), 2);
+        assert_eq!(units[0].pos, Vec2::new(0f32, 0f32));
+        assert_eq!(units[1].pos, Vec2::new(1f32, 0f32));
+    }
+
+    #[test]
+    fn orders_need_exactly_one_unit() {
+        let mut units = vec![unit(TeamColour::Blue, 0f32, 0f32)];
+
+        let result = resolve(
+            &mut units,
+            vec![
+                order(
+                    UnitActions::Move,
+                    TurnExecuteStages::AfterTurn,
+                    (0f32, 0f32),
+                    (0f32, 1f32),
+                ),
+                order(
+                    UnitActions::Move,
+                    TurnExecuteStages::PreTurn,
+                    (0f32, 0f32),
+                    (1f32, 0f32),
+                ),
+                mv((4f32, 4f32), (4f32, 5f32)),
+            ],
+        );
+
+        // The earliest stage's order is the one that counts
+        assert_eq!(units[0].pos, Vec2::new(1f32, 0f32));
+        assert_eq!(
+            failures(&result),
+            vec![
+                (Vec2::new(4f32, 4f32), ActionFailure::NoUnit),
+                (Vec2::new(0f32, 0f32), ActionFailure::DuplicateOrder),
+            ]
+        );
+    }
+
+    #[test]
+    fn order_of_orders_doesnt_change_the_result() {
+        let units = vec![
+            unit(TeamColour::Blue, 0f32, 0f32),
+            unit(TeamColour::Blue, 1f32, 0f32),
+            unit(TeamColour::Red, 3f32, 0f32),
+            unit(TeamColour::Red, 2f32, 2f32),
+            unit(TeamColour::Blue, 5f32, 5f32),
+        ];
+        let actions = vec![
+            mv((0f32, 0f32), (1f32, 0f32)),
+            mv((1f32, 0f32), (2f32, 0f32)),
+            mv((3f32, 0f32), (2f32, 0f32)),
+            order(
+                UnitActions::Attack,
+                TurnExecuteStages::PreTurn,
+                (2f32, 2f32),
+                (1f32, 1f32),
+            ),
+            order(
+                UnitActions::Attack,
+                TurnExecuteStages::AfterTurn,
+                (2f32, 2f32),
+                (3f32, 3f32),
+            ),
+            mv((5f32, 5f32), (4f32, 4f32)),
+            mv((6f32, 6f32), (6f32, 7f32)),
+        ];
+
+        let mut expected_units = units.clone();
+        let expected = resolve(&mut expected_units, actions.clone());
+        for shift in 1..actions.len() {
+            let mut shuffled = actions.clone();
+            shuffled.rotate_left(shift);
+            shuffled.reverse();
+
+            let mut shuffled_units = units.clone();
+            assert_eq!(resolve(&mut shuffled_units, shuffled), expected);
+            for (a, b) in shuffled_units.iter().zip(&expected_units) {
+                assert_eq!((a.pos, &a.health), (b.pos, &b.health));
+            }
+        }
+    }
+}
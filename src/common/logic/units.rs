@@ -1,90 +1,344 @@
+use std::{collections::HashMap, fmt, fs, io};
+
 use super::*;
 
 /*
-    A super messy list of units
-    Organised by archaetype -> class -> path -> tier
+    Unit types are defined in assets/data/units.toml, organised by
+    archetype -> class -> path -> tier
 */
 
-#[derive(Clone, Debug, Default, Deserialize, FromReflect, PartialEq, Reflect, Serialize)]
-pub enum UnitID {
-    #[default]
-    ScienceGenericTest,
-    MagicGenericTest,
+pub const UNITS_PATH: &str = "./assets/data/units.toml";
+
+// The key a unit type is defined under in units.toml
+#[derive(
+    Clone, Debug, Default, Deserialize, Eq, FromReflect, Hash, PartialEq, Reflect, Serialize,
+)]
+pub struct UnitID(pub String);
+
+impl UnitID {
+    pub fn new(id: &str) -> Self {
+        return Self(id.to_string());
+    }
 }
 
-/*
-;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;
-;;                                                                            ;;
-;;               ----==| T I E R   Z E R O   U N I T S |==----                ;;
-;;                                                                            ;;
-;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;
-*/
+impl fmt::Display for UnitID {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        return write!(f, "{}", self.0);
+    }
+}
+
+// Everything about a type of unit that doesn't change once it's spawned
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct UnitDefinition {
+    pub name: String,
+    pub archetype: Archetypes,
+    pub class: String,
+    pub path: String,
+    pub tier: u32,
+    pub health: f32,
+    pub attack: Attack,
+    #[serde(default)]
+    pub heal: Option<HealAction>,
+    pub defense: Defense,
+    pub movement: i32,
+    #[serde(default)]
+    pub execute_stage: TurnExecuteStages,
+    // Index into the character atlas
+    pub sprite: usize,
+    // What it costs to promote a unit into this one
+    #[serde(default)]
+    pub upgrade_cost: Cost,
+    // Only units with a recruit cost can come out of a nest
+    #[serde(default)]
+    pub recruit_cost: Option<Cost>,
+}
+
+impl UnitDefinition {
+    fn validate(&self) -> Vec<String> {
+        let mut problems = Vec::<String>::new();
+        let mut check = |ok: bool, problem: &str| {
+            if !ok {
+                problems.push(problem.to_string());
+            }
+        };
+        let non_negative = |value: f32| value.is_finite() && value >= 0f32;
+
+        check(!self.name.trim().is_empty(), "name is empty");
+        check(
+            self.health.is_finite() && self.health > 0f32,
+            "health must be above 0",
+        );
+        check(self.movement >= 0, "movement can't be negative");
+
+        check(
+            non_negative(self.attack.base),
+            "attack.base can't be negative",
+        );
+        check(self.attack.range >= 0, "attack.range can't be negative");
+        check(
+            self.attack.base == 0f32 || self.attack.range > 0,
+            "units that attack need an attack.range of at least 1",
+        );
+        check(
+            non_negative(self.attack.splash_multiplier)
+                && non_negative(self.attack.magic_multiplier)
+                && non_negative(self.attack.science_multiplier),
+            "attack multipliers can't be negative",
+        );
+
+        check(
+            non_negative(self.defense.base),
+            "defense.base can't be negative",
+        );
+        check(
+            non_negative(self.defense.magic_multiplier)
+                && non_negative(self.defense.science_multiplier),
+            "defense multipliers can't be negative",
+        );
+
+        if let Some(heal) = &self.heal {
+            check(
+                heal.base.is_finite() && heal.base > 0f32,
+                "heal.base must be above 0",
+            );
+            check(heal.range >= 0, "heal.range can't be negative");
+        }
+
+        return problems;
+    }
+
+    pub fn spawn(&self, id: &UnitID, pos: Vec2, owner: PlayerTeam) -> Unit {
+        return Unit {
+            id: id.clone(),
+            pos,
+            health: Health(self.health),
+            max_health: Health(self.health),
+            attack: self.attack.clone(),
+            heal: self.heal.clone(),
+            defense: self.defense.clone(),
+            movement: Movement(self.movement),
+            turn_execute_stage: TurnExecuteStage(self.execute_stage.clone()),
+            archetype: Archetype(self.archetype.clone()),
+            owner,
+        };
+    }
+}
+
+#[derive(Debug)]
+pub enum UnitDataError {
+    Io(io::Error),
+    Parse(toml::de::Error),
+    // Every entry that failed validation, with what's wrong with it
+    Invalid(Vec<(UnitID, String)>),
+}
+
+impl fmt::Display for UnitDataError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        return match self {
+            UnitDataError::Io(err) => write!(f, "unable to read unit data: {}", err),
+            UnitDataError::Parse(err) => write!(f, "unable to parse unit data: {}", err),
+            UnitDataError::Invalid(problems) => {
+                write!(f, "invalid unit data:")?;
+                for (id, problem) in problems {
+                    write!(f, "\n  {}: {}", id, problem)?;
+                }
+                Ok(())
+            }
+        };
+    }
+}
+
+#[derive(Deserialize)]
+struct UnitsFile {
+    units: HashMap<String, UnitDefinition>,
+}
+
+// Every type of unit, keyed by the id it's defined under
+#[derive(Debug, Default, Resource)]
+pub struct UnitRegistry {
+    definitions: HashMap<UnitID, UnitDefinition>,
+}
 
-//NOTE HASNT BEEN EDITED
-// pub const NONE_NONE_NONE_NONE_TZERO: Unit = Unit {
-//     health: Health(3f32),
-//     attack: Attack {
-//         base: 1f32,
-//         range: 1i32,
-//         splash: false,
-//         splash_multiplier: 1f32,
-//         magic_multiplier: 1f32,
-//         science_multiplier: 1f32,
-//     },
-//     defense: Defense {
-//         base: 0f32,
-//         magic_multiplier: 1f32,
-//         science_multiplier: 1f32,
-//     },
-//     movement: Movement(1),
-//     turn_execute_stage: TurnExecuteStage(TurnExecuteStages::MidTurn),
-//     archetype: Archetype(Archetypes::None),
-// };
-
-// const TIER_ZERO_BASE_UNIT: Unit = Unit {
-//     health: Health(3f32),
-//     attack: Attack {
-//         base: 1f32,
-//         range: 1i32,
-//         splash: false,
-//         splash_multiplier: 1f32,
-//         magic_multiplier: 1f32,
-//         science_multiplier: 1f32,
-//     },
-//     defense: Defense {
-//         base: 0f32,
-//         magic_multiplier: 1f32,
-//         science_multiplier: 1f32,
-//     },
-//     movement: Movement(1),
-//     turn_execute_stage: TurnExecuteStage(TurnExecuteStages::MidTurn),
-//     archetype: Archetype(Archetypes::None),
-// };
-
-// /*
-// ;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;
-// ;;                                                                            ;;
-// ;;                ----==| T I E R   O N E   U N I T S |==----                 ;;
-// ;;                                                                            ;;
-// ;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;
-// */
-
-// const SCIENCE_SUPPORT_HEALER_TONE: Unit = Unit {
-//     health: Health(3f32),
-//     attack: Attack {
-//         base: 1f32,
-//         range: 1i32,
-//         splash: false,
-//         splash_multiplier: 1f32,
-//         magic_multiplier: 1f32,
-//         science_multiplier: 1f32,
-//     },
-//     defense: Defense {
-//         base: 0f32,
-//         magic_multiplier: 1f32,
-//         science_multiplier: 1f32,
-//     },
-//     movement: Movement(1),
-//     turn_execute_stage: TurnExecuteStage(TurnExecuteStages::MidTurn),
-//     archetype: Archetype(Archetypes::None),
-// };
+impl UnitRegistry {
+    pub fn load(path: &str) -> Result<Self, UnitDataError> {
+        let data = fs::read_to_string(path).map_err(UnitDataError::Io)?;
+        return Self::from_toml(&data);
+    }
+
+    pub fn from_toml(data: &str) -> Result<Self, UnitDataError> {
+        let file = toml::from_str::<UnitsFile>(data).map_err(UnitDataError::Parse)?;
+
+        let mut problems = Vec::<(UnitID, String)>::new();
+        for (id, definition) in &file.units {
+            let id = UnitID::new(id);
+            if id.0.is_empty() || id.0.contains(char::is_whitespace) {
+                problems.push((
+                    id.clone(),
+                    "ids can't be empty or contain spaces".to_string(),
+                ));
+            }
+            for problem in definition.validate() {
+                problems.push((id.clone(), problem));
+            }
+        }
+
+        if !problems.is_empty() {
+            // HashMap order isn't stable, and the same file should always give
+            // the same error
+            problems.sort_by(|a, b| a.0 .0.cmp(&b.0 .0));
+            return Err(UnitDataError::Invalid(problems));
+        }
+
+        return Ok(Self {
+            definitions: file
+                .units
+                .into_iter()
+                .map(|(id, definition)| (UnitID(id), definition))
+                .collect(),
+        });
+    }
+
+    pub fn get(&self, id: &UnitID) -> Option<&UnitDefinition> {
+        return self.definitions.get(id);
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&UnitID, &UnitDefinition)> {
+        return self.definitions.iter();
+    }
+
+    pub fn spawn(&self, id: &UnitID, pos: Vec2, owner: PlayerTeam) -> Option<Unit> {
+        return Some(self.get(id)?.spawn(id, pos, owner));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const VALID: &str = r#"
+        [units.scout]
+        name = "Scout"
+        archetype = "None"
+        class = "None"
+        path = "None"
+        tier = 0
+        health = 3.0
+        movement = 2
+        sprite = 0
+
+        [units.scout.attack]
+        base = 1.0
+        range = 1
+        splash = false
+        splash_multiplier = 1.0
+        magic_multiplier = 1.0
+        science_multiplier = 1.0
+
+        [units.scout.defense]
+        base = 0.0
+        magic_multiplier = 1.0
+        science_multiplier = 1.0
+    "#;
+
+    fn problems(data: &str) -> Vec<(UnitID, String)> {
+        return match UnitRegistry::from_toml(data) {
+            Err(UnitDataError::Invalid(problems)) => problems,
+            other => panic!("expected invalid unit data, got {:?}", other.map(|_| ())),
+        };
+    }
+
+    #[test]
+    fn the_shipped_units_load() {
+        let registry = UnitRegistry::from_toml(include_str!("../../../assets/data/units.toml"))
+            .unwrap_or_else(|err| panic!("{}", err));
+        for id in recruitment::STARTING_ARMY {
+            assert!(
+                registry.get(&UnitID::new(id)).is_some(),
+                "{} is missing",
+                id
+            );
+        }
+    }
+
+    #[test]
+    fn missing_defaults_are_filled_in() {
+        let registry = UnitRegistry::from_toml(VALID).unwrap();
+        let scout = registry.get(&UnitID::new("scout")).unwrap();
+
+        assert_eq!(scout.execute_stage, TurnExecuteStages::MidTurn);
+        assert!(scout.heal.is_none());
+        assert!(scout.recruit_cost.is_none());
+
+        let unit = registry
+            .spawn(
+                &UnitID::new("scout"),
+                Vec2::ONE,
+                PlayerTeam(TeamColour::Red),
+            )
+            .unwrap();
+        assert_eq!(unit.health, Health(3f32));
+        assert_eq!(unit.max_health, Health(3f32));
+        assert!(registry
+            .spawn(&UnitID::new("nobody"), Vec2::ONE, PlayerTeam::default())
+            .is_none());
+    }
+
+    #[test]
+    fn unknown_keys_are_a_parse_error() {
+        let data = VALID.replace("movement = 2", "movement = 2\nspeed = 2");
+        let err = UnitRegistry::from_toml(&data).unwrap_err();
+
+        assert!(matches!(err, UnitDataError::Parse(_)));
+        assert!(err.to_string().contains("speed"), "{}", err);
+    }
+
+    #[test]
+    fn every_problem_is_reported_in_id_order() {
+        let data = format!(
+            "{}{}",
+            VALID.replace("health = 3.0", "health = 0.0"),
+            VALID
+                .replace("units.scout", "units.\"bad scout\"")
+                .replace("range = 1", "range = 0")
+                .replace("name = \"Scout\"", "name = \" \"")
+        );
+
+        assert_eq!(
+            problems(&data),
+            vec![
+                (
+                    UnitID::new("bad scout"),
+                    "ids can't be empty or contain spaces".to_string()
+                ),
+                (UnitID::new("bad scout"), "name is empty".to_string()),
+                (
+                    UnitID::new("bad scout"),
+                    "units that attack need an attack.range of at least 1".to_string()
+                ),
+                (UnitID::new("scout"), "health must be above 0".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn negative_stats_are_rejected() {
+        let data = VALID
+            .replace("movement = 2", "movement = -1")
+            .replace("splash_multiplier = 1.0", "splash_multiplier = -1.0")
+            .replace("base = 0.0", "base = -1.0");
+
+        let problems = problems(&data)
+            .into_iter()
+            .map(|(_, problem)| problem)
+            .collect::<Vec<String>>();
+        assert_eq!(
+            problems,
+            vec![
+                "movement can't be negative",
+                "attack multipliers can't be negative",
+                "defense.base can't be negative",
+            ]
+        );
+    }
+}
@@ -0,0 +1,325 @@
+use super::{
+    economy::Economy,
+    turn::{ActionFailure, TurnEvent},
+    units::{UnitDefinition, UnitID, UnitRegistry},
+    Archetypes, Health, Unit, UnitAction,
+};
+
+// "None" in a unit's class or path means it hasn't picked one yet
+const UNCHOSEN: &str = "None";
+
+impl UnitDefinition {
+    // Units promote one tier at a time, and stay on the archetype, class and
+    // path they've already picked. A tier zero unit hasn't picked anything
+    // yet, so it can go anywhere in tier one
+    pub fn can_upgrade_to(&self, other: &UnitDefinition) -> bool {
+        let archetype = self.archetype == Archetypes::None || self.archetype == other.archetype;
+        let class = self.class == UNCHOSEN || self.class == other.class;
+        let path = self.path == UNCHOSEN || self.path == other.path;
+
+        return other.tier == self.tier + 1 && archetype && class && path;
+    }
+}
+
+impl UnitRegistry {
+    // Everything a unit of type `id` can be promoted to, sorted by id
+    pub fn upgrades_for(&self, id: &UnitID) -> Vec<(&UnitID, &UnitDefinition)> {
+        let Some(definition) = self.get(id) else {
+            return Vec::new();
+        };
+
+        let mut upgrades = self
+            .iter()
+            .filter(|(_, other)| definition.can_upgrade_to(other))
+            .collect::<Vec<(&UnitID, &UnitDefinition)>>();
+        upgrades.sort_by(|a, b| a.0 .0.cmp(&b.0 .0));
+        return upgrades;
+    }
+
+    pub fn can_upgrade(&self, from: &UnitID, to: &UnitID) -> bool {
+        return match (self.get(from), self.get(to)) {
+            (Some(from), Some(to)) => from.can_upgrade_to(to),
+            _ => false,
+        };
+    }
+}
+
+impl Unit {
+    // The same unit as a different type. Where it is, who owns it and how
+    // hurt it is (as a fraction of max health) carry over
+    pub fn upgraded(&self, id: &UnitID, definition: &UnitDefinition) -> Unit {
+        let mut unit = definition.spawn(id, self.pos, self.owner.clone());
+        if self.max_health.0 > 0f32 {
+            unit.health = Health(definition.health * self.health.0 / self.max_health.0);
+        }
+        return unit;
+    }
+}
+
+// Units promote in place, after everything else in the stage
+pub fn resolve_upgrades(
+    registry: &UnitRegistry,
+    economy: &mut Economy,
+    units: &mut [Unit],
+    upgrades: Vec<(usize, UnitAction, UnitID)>,
+    events: &mut Vec<TurnEvent>,
+) {
+    for (i, action, to) in upgrades {
+        if !units[i].is_alive() {
+            events.push(TurnEvent::ActionFailed {
+                action,
+                reason: ActionFailure::UnitDestroyed,
+            });
+            continue;
+        }
+
+        let from = units[i].id.clone();
+        let Some(definition) = registry
+            .get(&to)
+            .filter(|_| registry.can_upgrade(&from, &to))
+        else {
+            events.push(TurnEvent::ActionFailed {
+                action,
+                reason: ActionFailure::InvalidUpgrade,
+            });
+            continue;
+        };
+
+        if !economy.spend(&units[i].owner, definition.upgrade_cost) {
+            events.push(TurnEvent::ActionFailed {
+                action,
+                reason: ActionFailure::CannotAfford,
+            });
+            continue;
+        }
+
+        units[i] = units[i].upgraded(&to, definition);
+        events.push(TurnEvent::Upgraded {
+            pos: units[i].pos,
+            from,
+            to,
+            cost: definition.upgrade_cost,
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::prelude::*;
+
+    use super::*;
+    use crate::common::logic::{
+        economy::STARTING_BALANCE, testing::order, Cost, PlayerTeam, TeamColour, TurnExecuteStages,
+        UnitActions,
+    };
+
+    fn entry(id: &str, kind: (&str, &str, &str), tier: u32, health: f32, cost: Cost) -> String {
+        let (archetype, class, path) = kind;
+        return format!(
+            r#"
+            [units.{id}]
+            name = "{id}"
+            archetype = "{archetype}"
+            class = "{class}"
+            path = "{path}"
+            tier = {tier}
+            health = {health:?}
+            movement = 2
+            sprite = 0
+            upgrade_cost = {{ magic = {magic}, science = {science} }}
+            attack = {{ base = 1.0, range = 1, splash = false, splash_multiplier = 1.0, magic_multiplier = 1.0, science_multiplier = 1.0 }}
+            defense = {{ base = 0.0, magic_multiplier = 1.0, science_multiplier = 1.0 }}
+            "#,
+            magic = cost.magic,
+            science = cost.science,
+        );
+    }
+
+    fn registry() -> UnitRegistry {
+        let magic = |magic| Cost { magic, science: 0 };
+        let data = [
+            entry(
+                "recruit",
+                ("None", "None", "None"),
+                0,
+                4f32,
+                Cost::default(),
+            ),
+            entry("mage", ("Magic", "Wizard", "None"), 1, 8f32, magic(2)),
+            entry("witch", ("Magic", "Witch", "None"), 1, 8f32, magic(2)),
+            entry("engineer", ("Science", "Tinker", "None"), 1, 6f32, magic(0)),
+            entry(
+                "pyromancer",
+                ("Magic", "Wizard", "Fire"),
+                2,
+                12f32,
+                magic(4),
+            ),
+            entry("hexer", ("Magic", "Witch", "Curse"), 2, 12f32, magic(4)),
+            entry("bomber", ("Science", "Tinker", "Fire"), 2, 12f32, magic(4)),
+        ]
+        .concat();
+        return UnitRegistry::from_toml(&data).unwrap_or_else(|err| panic!("{}", err));
+    }
+
+    fn upgrade(
+        registry: &UnitRegistry,
+        economy: &mut Economy,
+        units: &mut [Unit],
+        upgrades: Vec<(usize, &str)>,
+    ) -> Vec<TurnEvent> {
+        let upgrades = upgrades
+            .into_iter()
+            .map(|(i, to)| {
+                let pos = (units[i].pos.x, units[i].pos.y);
+                let to = UnitID::new(to);
+                (
+                    i,
+                    order(
+                        UnitActions::Upgrade(to.clone()),
+                        TurnExecuteStages::AfterTurn,
+                        pos,
+                        pos,
+                    ),
+                    to,
+                )
+            })
+            .collect();
+        let mut events = Vec::new();
+        resolve_upgrades(registry, economy, units, upgrades, &mut events);
+        return events;
+    }
+
+    fn spawn(registry: &UnitRegistry, id: &str, x: f32) -> Unit {
+        return registry
+            .spawn(
+                &UnitID::new(id),
+                Vec2::new(x, 0f32),
+                PlayerTeam(TeamColour::Blue),
+            )
+            .unwrap();
+    }
+
+    fn failures(events: &[TurnEvent]) -> Vec<ActionFailure> {
+        return events
+            .iter()
+            .filter_map(|event| match event {
+                TurnEvent::ActionFailed { reason, .. } => Some(reason.clone()),
+                _ => None,
+            })
+            .collect();
+    }
+
+    #[test]
+    fn units_promote_one_tier_along_their_path() {
+        let registry = registry();
+        let can = |from: &str, to: &str| registry.can_upgrade(&UnitID::new(from), &UnitID::new(to));
+
+        // Tier zero can go anywhere in tier one
+        assert!(can("recruit", "mage"));
+        assert!(can("recruit", "engineer"));
+        // After that, the archetype and class are fixed
+        assert!(can("mage", "pyromancer"));
+        assert!(can("witch", "hexer"));
+        assert!(!can("mage", "hexer"));
+        assert!(!can("engineer", "pyromancer"));
+        // One tier at a time, and never back down
+        assert!(!can("recruit", "pyromancer"));
+        assert!(!can("mage", "recruit"));
+        assert!(!can("mage", "mage"));
+        assert!(!can("mage", "nobody"));
+
+        let upgrades = registry
+            .upgrades_for(&UnitID::new("recruit"))
+            .into_iter()
+            .map(|(id, _)| id.0.as_str())
+            .collect::<Vec<&str>>();
+        assert_eq!(upgrades, vec!["engineer", "mage", "witch"]);
+    }
+
+    #[test]
+    fn upgrades_are_paid_for() {
+        let registry = registry();
+        let mut economy = Economy::default();
+        let mut units = vec![spawn(&registry, "recruit", 0f32)];
+        let blue = units[0].owner.clone();
+
+        let events = upgrade(&registry, &mut economy, &mut units, vec![(0, "mage")]);
+
+        assert_eq!(
+            events,
+            vec![TurnEvent::Upgraded {
+                pos: Vec2::new(0f32, 0f32),
+                from: UnitID::new("recruit"),
+                to: UnitID::new("mage"),
+                cost: Cost {
+                    magic: 2,
+                    science: 0
+                },
+            }]
+        );
+        assert_eq!(units[0].id, UnitID::new("mage"));
+        assert_eq!(
+            economy.balance(&blue),
+            Cost {
+                magic: STARTING_BALANCE.magic - 2,
+                science: STARTING_BALANCE.science,
+            }
+        );
+    }
+
+    #[test]
+    fn failed_upgrades_cost_nothing() {
+        let registry = registry();
+        let mut economy = Economy::default();
+        let mut units = vec![
+            spawn(&registry, "recruit", 0f32),
+            spawn(&registry, "recruit", 1f32),
+            spawn(&registry, "mage", 2f32),
+            spawn(&registry, "recruit", 3f32),
+        ];
+        units[3].health = Health(0f32);
+        let blue = units[0].owner.clone();
+
+        let events = upgrade(
+            &registry,
+            &mut economy,
+            &mut units,
+            vec![
+                (0, "pyromancer"),
+                (1, "nobody"),
+                (2, "pyromancer"),
+                (3, "mage"),
+            ],
+        );
+
+        assert_eq!(
+            failures(&events),
+            vec![
+                ActionFailure::InvalidUpgrade,
+                ActionFailure::InvalidUpgrade,
+                ActionFailure::CannotAfford,
+                ActionFailure::UnitDestroyed,
+            ]
+        );
+        assert_eq!(economy.balance(&blue), STARTING_BALANCE);
+        assert_eq!(units[2].id, UnitID::new("mage"));
+    }
+
+    #[test]
+    fn upgrading_keeps_how_hurt_a_unit_is() {
+        let registry = registry();
+        let mut recruit = spawn(&registry, "recruit", 5f32);
+        recruit.health = Health(1f32);
+
+        let mage = recruit.upgraded(
+            &UnitID::new("mage"),
+            registry.get(&UnitID::new("mage")).unwrap(),
+        );
+
+        assert_eq!(mage.health, Health(2f32));
+        assert_eq!(mage.max_health, Health(8f32));
+        assert_eq!(mage.pos, recruit.pos);
+        assert_eq!(mage.owner, recruit.owner);
+    }
+}
@@ -0,0 +1,412 @@
+use std::collections::HashMap;
+
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::common::config::VictoryConfig;
+
+use super::{neo_gameboard::Gameboard, turn::TurnEvent, Health, PlayerTeam, TileFeatures, Unit};
+
+// What each thing a team has left is worth in VictoryMode::Points
+const SITE_POINTS: u32 = 3;
+const STRUCTURE_POINTS: u32 = 2;
+const UNIT_POINTS: u32 = 1;
+
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum VictoryMode {
+    // Last team with a nest wins
+    #[default]
+    Conquest,
+    // Last team with units wins
+    Annihilation,
+    // First team to hold a share of the currency sites for long enough wins
+    Domination,
+    // Whoever has the most points when the turn limit runs out wins
+    Points,
+}
+
+// Who's playing, who's already out, and whether it's over yet. Set up when the
+// armies are placed
+#[derive(Clone, Debug, Default, Deserialize, Resource, Serialize)]
+pub struct MatchState {
+    pub config: VictoryConfig,
+    pub players: Vec<PlayerTeam>,
+    pub eliminated: Vec<PlayerTeam>,
+    // How many turns in a row each team has held enough sites for domination
+    held_for: HashMap<PlayerTeam, u32>,
+    // Empty if everyone was knocked out on the same turn
+    pub winners: Option<Vec<PlayerTeam>>,
+}
+
+impl MatchState {
+    pub fn new(config: VictoryConfig, players: &[PlayerTeam]) -> Self {
+        return Self {
+            config,
+            players: players.to_vec(),
+            ..default()
+        };
+    }
+
+    pub fn is_over(&self) -> bool {
+        return self.winners.is_some();
+    }
+
+    pub fn is_eliminated(&self, team: &PlayerTeam) -> bool {
+        return self.eliminated.contains(team);
+    }
+
+    // Everyone still playing, in the order they joined
+    pub fn remaining(&self) -> Vec<PlayerTeam> {
+        return self
+            .players
+            .iter()
+            .filter(|team| !self.is_eliminated(team))
+            .cloned()
+            .collect();
+    }
+}
+
+// Every completed structure, currency site and living unit a team has
+pub fn score(gameboard: &Gameboard, units: &[Unit], team: &PlayerTeam) -> u32 {
+    let mut score = 0;
+    for tile in gameboard.tiles() {
+        if tile
+            .structure()
+            .map_or(false, |s| s.owner == *team && s.is_complete())
+        {
+            score += STRUCTURE_POINTS;
+        }
+        if tile.feature().map_or(false, |f| {
+            matches!(f.feature, TileFeatures::CurrencySite(_))
+                && f.controller.as_ref() == Some(team)
+        }) {
+            score += SITE_POINTS;
+        }
+    }
+    score += units
+        .iter()
+        .filter(|u| u.is_alive() && u.owner == *team)
+        .count() as u32
+        * UNIT_POINTS;
+    return score;
+}
+
+// An enemy unit left standing on a nest at the end of the turn tears it down
+fn raze_nests(gameboard: &mut Gameboard, units: &[Unit], events: &mut Vec<TurnEvent>) {
+    for unit in units.iter().filter(|u| u.is_alive()) {
+        let Some(tile) = gameboard.tile_mut(unit.pos.x as usize, unit.pos.y as usize) else {
+            continue;
+        };
+        let Some(TileFeatures::Nest(owner)) = tile.feature().map(|f| f.feature.clone()) else {
+            continue;
+        };
+        if owner == unit.owner {
+            continue;
+        }
+
+        tile.remove_feature();
+        events.push(TurnEvent::NestRazed {
+            pos: unit.pos,
+            owner,
+        });
+    }
+}
+
+fn is_knocked_out(mode: VictoryMode, has_nest: bool, has_units: bool) -> bool {
+    return match mode {
+        VictoryMode::Conquest => !has_nest,
+        VictoryMode::Annihilation => !has_units,
+        // Nothing left to play with
+        VictoryMode::Domination | VictoryMode::Points => !has_nest && !has_units,
+    };
+}
+
+// Run after every turn. Knocked out teams lose everything they have left on
+// the board, so anything not alive afterwards should be despawned
+pub fn check_victory(
+    state: &mut MatchState,
+    turn: u32,
+    gameboard: &mut Gameboard,
+    units: &mut [Unit],
+    events: &mut Vec<TurnEvent>,
+) {
+    if state.is_over() || state.players.is_empty() {
+        return;
+    }
+
+    raze_nests(gameboard, units, events);
+
+    for team in state.remaining() {
+        let has_nest = gameboard.nest_of(&team).is_some();
+        let has_units = units.iter().any(|u| u.is_alive() && u.owner == team);
+        if !is_knocked_out(state.config.mode, has_nest, has_units) {
+            continue;
+        }
+
+        for unit in units.iter_mut().filter(|u| u.owner == team) {
+            unit.health = Health(0f32);
+        }
+        if let Some(nest) = gameboard.nest_of(&team) {
+            gameboard
+                .tile_mut(nest.x as usize, nest.y as usize)
+                .unwrap()
+                .remove_feature();
+        }
+        state.eliminated.push(team.clone());
+        events.push(TurnEvent::Eliminated { owner: team });
+    }
+
+    let remaining = state.remaining();
+    let winners = if remaining.len() <= 1 {
+        Some(remaining)
+    } else {
+        match state.config.mode {
+            VictoryMode::Conquest | VictoryMode::Annihilation => None,
+            VictoryMode::Domination => check_domination(state, gameboard, &remaining),
+            VictoryMode::Points if turn + 1 >= state.config.turn_limit => {
+                let scores = remaining
+                    .iter()
+                    .map(|team| (team.clone(), score(gameboard, units, team)))
+                    .collect::<Vec<(PlayerTeam, u32)>>();
+                let best = scores.iter().map(|(_, score)| *score).max().unwrap_or(0);
+                Some(
+                    scores
+                        .into_iter()
+                        .filter(|(_, score)| *score == best)
+                        .map(|(team, _)| team)
+                        .collect(),
+                )
+            }
+            VictoryMode::Points => None,
+        }
+    };
+
+    if let Some(winners) = winners {
+        info!("Match over after turn {}, won by {:?}", turn, winners);
+        events.push(TurnEvent::MatchOver {
+            winners: winners.clone(),
+        });
+        state.winners = Some(winners);
+    }
+}
+
+fn check_domination(
+    state: &mut MatchState,
+    gameboard: &Gameboard,
+    remaining: &[PlayerTeam],
+) -> Option<Vec<PlayerTeam>> {
+    let controllers = gameboard
+        .tiles()
+        .filter_map(|t| t.feature())
+        .filter(|f| matches!(f.feature, TileFeatures::CurrencySite(_)))
+        .map(|f| f.controller.clone())
+        .collect::<Vec<Option<PlayerTeam>>>();
+    if controllers.is_empty() {
+        return None;
+    }
+
+    let mut winners = Vec::<PlayerTeam>::new();
+    for team in remaining {
+        let held = controllers
+            .iter()
+            .filter(|c| c.as_ref() == Some(team))
+            .count();
+        let share = held as f32 / controllers.len() as f32;
+
+        let turns = state.held_for.entry(team.clone()).or_default();
+        *turns = if share >= state.config.site_share {
+            *turns + 1
+        } else {
+            0
+        };
+        if *turns >= state.config.hold_turns {
+            winners.push(team.clone());
+        }
+    }
+
+    return if winners.is_empty() {
+        None
+    } else {
+        Some(winners)
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::logic::{
+        testing::{grass_board, unit},
+        Archetype, Archetypes, TeamColour,
+    };
+
+    fn blue() -> PlayerTeam {
+        return PlayerTeam(TeamColour::Blue);
+    }
+
+    fn red() -> PlayerTeam {
+        return PlayerTeam(TeamColour::Red);
+    }
+
+    fn match_state(mode: VictoryMode) -> MatchState {
+        let config = VictoryConfig {
+            mode,
+            site_share: 0.5,
+            hold_turns: 2,
+            turn_limit: 3,
+        };
+        return MatchState::new(config, &[blue(), red()]);
+    }
+
+    // A nest for each team in opposite corners, and a currency site between
+    fn board() -> Gameboard {
+        let mut gameboard = grass_board(6, 6);
+        for (x, y, team) in [(0, 0, blue()), (5, 5, red())] {
+            gameboard
+                .tile_mut(x, y)
+                .unwrap()
+                .set_feature(TileFeatures::Nest(team));
+        }
+        for (x, y) in [(2, 2), (3, 3)] {
+            gameboard
+                .tile_mut(x, y)
+                .unwrap()
+                .set_feature(TileFeatures::CurrencySite(Archetype(Archetypes::Magic)));
+        }
+        return gameboard;
+    }
+
+    fn capture(gameboard: &mut Gameboard, x: usize, y: usize, team: Option<PlayerTeam>) {
+        gameboard
+            .tile_mut(x, y)
+            .unwrap()
+            .feature_mut()
+            .unwrap()
+            .controller = team;
+    }
+
+    fn check(
+        state: &mut MatchState,
+        turn: u32,
+        gameboard: &mut Gameboard,
+        units: &mut [Unit],
+    ) -> Vec<TurnEvent> {
+        let mut events = Vec::new();
+        check_victory(state, turn, gameboard, units, &mut events);
+        return events;
+    }
+
+    #[test]
+    fn conquest_is_won_by_razing_every_other_nest() {
+        let mut state = match_state(VictoryMode::Conquest);
+        let mut gameboard = board();
+        let mut units = vec![
+            unit(TeamColour::Blue, 5f32, 5f32),
+            unit(TeamColour::Red, 3f32, 0f32),
+        ];
+
+        let events = check(&mut state, 0, &mut gameboard, &mut units);
+
+        assert_eq!(
+            events,
+            vec![
+                TurnEvent::NestRazed {
+                    pos: Vec2::new(5f32, 5f32),
+                    owner: red(),
+                },
+                TurnEvent::Eliminated { owner: red() },
+                TurnEvent::MatchOver {
+                    winners: vec![blue()],
+                },
+            ]
+        );
+        // Everything red had left goes with it
+        assert!(!units[1].is_alive());
+        assert!(state.is_over());
+    }
+
+    #[test]
+    fn annihilation_ignores_nests() {
+        let mut state = match_state(VictoryMode::Annihilation);
+        let mut gameboard = board();
+        let mut units = vec![unit(TeamColour::Blue, 1f32, 1f32)];
+
+        let events = check(&mut state, 0, &mut gameboard, &mut units);
+
+        assert_eq!(state.winners, Some(vec![blue()]));
+        assert!(events.contains(&TurnEvent::Eliminated { owner: red() }));
+        assert_eq!(gameboard.nest_of(&red()), None);
+        assert_eq!(gameboard.nest_of(&blue()), Some(Vec2::ZERO));
+    }
+
+    #[test]
+    fn domination_needs_the_sites_held_for_long_enough() {
+        let mut state = match_state(VictoryMode::Domination);
+        let mut gameboard = board();
+        let mut units = vec![
+            unit(TeamColour::Blue, 1f32, 1f32),
+            unit(TeamColour::Red, 4f32, 4f32),
+        ];
+
+        capture(&mut gameboard, 2, 2, Some(blue()));
+        check(&mut state, 0, &mut gameboard, &mut units);
+        // Losing the share starts the count again
+        capture(&mut gameboard, 2, 2, Some(red()));
+        check(&mut state, 1, &mut gameboard, &mut units);
+        capture(&mut gameboard, 2, 2, Some(blue()));
+        check(&mut state, 2, &mut gameboard, &mut units);
+        assert!(!state.is_over());
+
+        check(&mut state, 3, &mut gameboard, &mut units);
+        assert_eq!(state.winners, Some(vec![blue()]));
+    }
+
+    #[test]
+    fn points_are_counted_at_the_turn_limit() {
+        let mut state = match_state(VictoryMode::Points);
+        let mut gameboard = board();
+        let mut units = vec![
+            unit(TeamColour::Blue, 1f32, 1f32),
+            unit(TeamColour::Red, 4f32, 4f32),
+            unit(TeamColour::Red, 4f32, 3f32),
+        ];
+        capture(&mut gameboard, 2, 2, Some(blue()));
+
+        assert_eq!(
+            score(&gameboard, &units, &blue()),
+            SITE_POINTS + UNIT_POINTS
+        );
+        assert_eq!(score(&gameboard, &units, &red()), 2 * UNIT_POINTS);
+
+        check(&mut state, 1, &mut gameboard, &mut units);
+        assert!(!state.is_over());
+        check(&mut state, 2, &mut gameboard, &mut units);
+        assert_eq!(state.winners, Some(vec![blue()]));
+    }
+
+    #[test]
+    fn tied_points_share_the_win() {
+        let mut state = match_state(VictoryMode::Points);
+        let mut gameboard = board();
+        let mut units = vec![
+            unit(TeamColour::Blue, 1f32, 1f32),
+            unit(TeamColour::Red, 4f32, 4f32),
+        ];
+
+        check(&mut state, 2, &mut gameboard, &mut units);
+        assert_eq!(state.winners, Some(vec![blue(), red()]));
+    }
+
+    #[test]
+    fn everyone_going_out_together_is_a_draw() {
+        let mut state = match_state(VictoryMode::Annihilation);
+        let mut gameboard = board();
+
+        let events = check(&mut state, 0, &mut gameboard, &mut []);
+
+        assert_eq!(state.winners, Some(Vec::new()));
+        assert_eq!(events.len(), 3);
+        // Nothing changes once it's over
+        assert!(check(&mut state, 1, &mut gameboard, &mut []).is_empty());
+    }
+}
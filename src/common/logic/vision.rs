@@ -0,0 +1,269 @@
+use std::cmp::Ordering;
+
+use bevy::prelude::*;
+
+use super::{
+    neo_gameboard::{Gameboard, Tile},
+    turn::board_order,
+    PlayerTeam, TeamColour, Terrain, TileFeatures, Unit,
+};
+
+// How far every unit can see on open ground
+pub const UNIT_VISION_RANGE: i32 = 3;
+// Extra range for anything standing on Mountains
+pub const MOUNTAIN_VISION_BONUS: i32 = 2;
+// A team can always see around its own nest
+pub const NEST_VISION_RANGE: i32 = 2;
+
+// Something a team sees from, i.e. a unit or a structure
+struct Viewpoint {
+    pos: Vec2,
+    range: i32,
+    elevated: bool,
+    team: TeamColour,
+}
+
+impl Tile {
+    pub fn is_visible_to(&self, team: &PlayerTeam) -> bool {
+        return self.visible_for().contains(&team.0);
+    }
+
+    // Whether this tile stops a viewer from seeing the tiles behind it. The
+    // tile itself can still be seen
+    fn blocks_sight(&self, elevated: bool) -> bool {
+        return match self.terrain() {
+            Terrain::Mountains => true,
+            // You can see over the trees from a mountain
+            Terrain::Forest => !elevated,
+            _ => false,
+        };
+    }
+}
+
+impl Gameboard {
+    // Recomputes which tiles every team can currently see. Features stay
+    // visible to a team once they've been seen, since they never move
+    pub fn update_vision(&mut self, units: &[Unit]) {
+        let (width, height) = (self.x() as i32, self.y() as i32);
+        let mut visible = vec![Vec::<TeamColour>::new(); (width * height) as usize];
+
+        // Only the square each viewpoint can reach needs checking
+        for viewpoint in self.viewpoints(units) {
+            let (x, y) = (viewpoint.pos.x as i32, viewpoint.pos.y as i32);
+            for target_x in
+                i32::max(0, x - viewpoint.range)..=i32::min(width - 1, x + viewpoint.range)
+            {
+                for target_y in
+                    i32::max(0, y - viewpoint.range)..=i32::min(height - 1, y + viewpoint.range)
+                {
+                    let teams = &mut visible[(target_x * height + target_y) as usize];
+                    if teams.contains(&viewpoint.team) {
+                        continue;
+                    }
+
+                    let target = Vec2::new(target_x as f32, target_y as f32);
+                    if self.line_of_sight(viewpoint.pos, target, viewpoint.elevated) {
+                        teams.push(viewpoint.team.clone());
+                    }
+                }
+            }
+        }
+
+        for (i, visible_for) in visible.into_iter().enumerate() {
+            let (x, y) = (i / height as usize, i % height as usize);
+            let tile = self.tile_mut(x, y).unwrap();
+            if let Some(feature) = tile.feature_mut() {
+                for team in &visible_for {
+                    let team = PlayerTeam(team.clone());
+                    if !feature.visible_to_players.contains(&team) {
+                        feature.visible_to_players.push(team);
+                    }
+                }
+            }
+            tile.set_visible_for(visible_for);
+        }
+    }
+
+    fn viewpoints(&self, units: &[Unit]) -> Vec<Viewpoint> {
+        let elevated = |pos: Vec2| {
+            self.tile(pos.x as usize, pos.y as usize)
+                .map_or(false, |t| t.terrain() == Terrain::Mountains)
+        };
+        let viewpoint = |pos: Vec2, range: i32, team: &PlayerTeam| {
+            let elevated = elevated(pos);
+            return Viewpoint {
+                pos,
+                range: if elevated {
+                    range + MOUNTAIN_VISION_BONUS
+                } else {
+                    range
+                },
+                elevated,
+                team: team.0.clone(),
+            };
+        };
+
+        let mut viewpoints = units
+            .iter()
+            .filter(|u| u.is_alive())
+            .map(|u| viewpoint(u.pos, UNIT_VISION_RANGE, &u.owner))
+            .collect::<Vec<Viewpoint>>();
+
+        for tile in self.tiles() {
+            if let Some(structure) = tile.structure() {
+                if structure.is_complete() {
+                    viewpoints.push(viewpoint(
+                        tile.pos(),
+                        structure.kind.vision_range(),
+                        &structure.owner,
+                    ));
+                }
+            }
+            if let Some(feature) = tile.feature() {
+                if let TileFeatures::Nest(team) = &feature.feature {
+                    viewpoints.push(viewpoint(tile.pos(), NEST_VISION_RANGE, team));
+                }
+            }
+        }
+
+        return viewpoints;
+    }
+
+    // Walks a Bresenham line between the two tiles, and checks nothing in
+    // between blocks the view. The ends themselves never block. Lines are
+    // always walked in board order, since the tiles a Bresenham line passes
+    // through can depend on which end it starts from, and whoever can be seen
+    // should always be able to see back
+    pub fn line_of_sight(&self, from: Vec2, to: Vec2, elevated: bool) -> bool {
+        let (from, to) = match board_order(from, to) {
+            Ordering::Greater => (to, from),
+            _ => (from, to),
+        };
+        let (mut x, mut y) = (from.x as i32, from.y as i32);
+        let (end_x, end_y) = (to.x as i32, to.y as i32);
+
+        let dx = (end_x - x).abs();
+        let dy = -(end_y - y).abs();
+        let step_x = if x < end_x { 1 } else { -1 };
+        let step_y = if y < end_y { 1 } else { -1 };
+        let mut error = dx + dy;
+
+        loop {
+            if x == end_x && y == end_y {
+                return true;
+            }
+
+            let doubled = 2 * error;
+            if doubled >= dy {
+                error += dy;
+                x += step_x;
+            }
+            if doubled <= dx {
+                error += dx;
+                y += step_y;
+            }
+
+            if x == end_x && y == end_y {
+                return true;
+            }
+            match self.tile(x as usize, y as usize) {
+                Some(tile) if tile.blocks_sight(elevated) => return false,
+                Some(_) => {}
+                None => return false,
+            }
+        }
+    }
+}
+
+// Keeps vision up to date whenever units or the board change
+pub fn update_vision(
+    mut gameboard_q: Query<&mut Gameboard>,
+    units_q: Query<&Unit>,
+    changed_units: Query<(), Changed<Unit>>,
+    mut removed_units: RemovedComponents<Unit>,
+) {
+    let Ok(mut gameboard) = gameboard_q.get_single_mut() else {
+        return;
+    };
+
+    let units_removed = removed_units.iter().count() > 0;
+    if !(gameboard.is_changed() || units_removed || !changed_units.is_empty()) {
+        return;
+    }
+
+    let units = units_q.iter().cloned().collect::<Vec<Unit>>();
+    gameboard.update_vision(&units);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::logic::testing::{grass_board, unit};
+
+    fn set_terrain(gameboard: &mut Gameboard, x: usize, y: usize, terrain: Terrain) {
+        gameboard.tile_mut(x, y).unwrap().set_terrain(terrain);
+    }
+
+    #[test]
+    fn mountains_block_sight_but_can_be_seen() {
+        let mut gameboard = grass_board(7, 7);
+        set_terrain(&mut gameboard, 2, 2, Terrain::Mountains);
+
+        let from = Vec2::new(0f32, 0f32);
+        assert!(gameboard.line_of_sight(from, Vec2::new(2f32, 2f32), false));
+        assert!(!gameboard.line_of_sight(from, Vec2::new(4f32, 4f32), false));
+        assert!(!gameboard.line_of_sight(from, Vec2::new(4f32, 4f32), true));
+        // Off to the side of the mountain
+        assert!(gameboard.line_of_sight(from, Vec2::new(4f32, 0f32), false));
+    }
+
+    #[test]
+    fn forests_only_block_from_the_ground() {
+        let mut gameboard = grass_board(7, 7);
+        set_terrain(&mut gameboard, 2, 0, Terrain::Forest);
+
+        let from = Vec2::new(0f32, 0f32);
+        assert!(!gameboard.line_of_sight(from, Vec2::new(4f32, 0f32), false));
+        assert!(gameboard.line_of_sight(from, Vec2::new(4f32, 0f32), true));
+    }
+
+    #[test]
+    fn sight_is_the_same_both_ways_round() {
+        let mut gameboard = grass_board(7, 7);
+        set_terrain(&mut gameboard, 3, 2, Terrain::Mountains);
+
+        for x in 0..7 {
+            for y in 0..7 {
+                let (a, b) = (Vec2::new(1f32, 1f32), Vec2::new(x as f32, y as f32));
+                assert_eq!(
+                    gameboard.line_of_sight(a, b, false),
+                    gameboard.line_of_sight(b, a, false),
+                    "{} to {}",
+                    a,
+                    b
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn units_see_further_from_mountains() {
+        let mut gameboard = grass_board(12, 1);
+        let blue = PlayerTeam(TeamColour::Blue);
+
+        gameboard.update_vision(&[unit(TeamColour::Blue, 0f32, 0f32)]);
+        let range = UNIT_VISION_RANGE as usize;
+        assert!(gameboard.tile(range, 0).unwrap().is_visible_to(&blue));
+        assert!(!gameboard.tile(range + 1, 0).unwrap().is_visible_to(&blue));
+        assert!(!gameboard
+            .tile(range, 0)
+            .unwrap()
+            .is_visible_to(&PlayerTeam(TeamColour::Red)));
+
+        set_terrain(&mut gameboard, 0, 0, Terrain::Mountains);
+        gameboard.update_vision(&[unit(TeamColour::Blue, 0f32, 0f32)]);
+        let range = (UNIT_VISION_RANGE + MOUNTAIN_VISION_BONUS) as usize;
+        assert!(gameboard.tile(range, 0).unwrap().is_visible_to(&blue));
+        assert!(!gameboard.tile(range + 1, 0).unwrap().is_visible_to(&blue));
+    }
+}
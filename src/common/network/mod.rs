@@ -0,0 +1,266 @@
+pub mod protocol;
+
+use std::{
+    fmt,
+    io::{self, Read, Write},
+    net::{SocketAddr, TcpStream},
+};
+
+use serde::{de::DeserializeOwned, Serialize};
+
+// Every message is framed as a big-endian u32 payload length, followed by the
+// bincode encoded payload
+const LENGTH_PREFIX_SIZE: usize = 4;
+
+// Comfortably larger than a full 256x256 board snapshot, but small enough
+// that a bad length prefix can't make us allocate the world
+pub const MAX_MESSAGE_SIZE: usize = 16 * 1024 * 1024;
+
+#[derive(Debug)]
+pub enum NetworkError {
+    Closed,
+    Io(io::Error),
+    Encode(bincode::Error),
+    Decode(bincode::Error),
+    MessageTooLarge(usize),
+}
+
+impl fmt::Display for NetworkError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        return match self {
+            NetworkError::Closed => write!(f, "connection closed"),
+            NetworkError::Io(err) => write!(f, "io error: {}", err),
+            NetworkError::Encode(err) => write!(f, "unable to encode message: {}", err),
+            NetworkError::Decode(err) => write!(f, "unable to decode message: {}", err),
+            NetworkError::MessageTooLarge(size) => write!(
+                f,
+                "message of {} bytes exceeds the {} byte limit",
+                size, MAX_MESSAGE_SIZE
+            ),
+        };
+    }
+}
+
+impl From<io::Error> for NetworkError {
+    fn from(err: io::Error) -> Self {
+        return NetworkError::Io(err);
+    }
+}
+
+// A non-blocking, length-prefixed message stream. Both the client and the
+// server poll these once per frame, so nothing here is allowed to block.
+pub struct Connection {
+    stream: TcpStream,
+    addr: SocketAddr,
+    read_buffer: Vec<u8>,
+    write_buffer: Vec<u8>,
+}
+
+impl Connection {
+    pub fn new(stream: TcpStream) -> Result<Self, NetworkError> {
+        stream.set_nonblocking(true)?;
+        stream.set_nodelay(true)?;
+        let addr = stream.peer_addr()?;
+
+        return Ok(Self {
+            stream,
+            addr,
+            read_buffer: Vec::new(),
+            write_buffer: Vec::new(),
+        });
+    }
+
+    pub fn addr(&self) -> SocketAddr {
+        return self.addr;
+    }
+
+    // Queues a message and writes as much of the queue as the socket will take
+    pub fn send<T: Serialize>(&mut self, message: &T) -> Result<(), NetworkError> {
+        let payload = bincode::serialize(message).map_err(NetworkError::Encode)?;
+        if payload.len() > MAX_MESSAGE_SIZE {
+            return Err(NetworkError::MessageTooLarge(payload.len()));
+        }
+
+        self.write_buffer
+            .extend_from_slice(&(payload.len() as u32).to_be_bytes());
+        self.write_buffer.extend_from_slice(&payload);
+        return self.flush();
+    }
+
+    // Writes any queued bytes that didn't fit in the socket last time
+    pub fn flush(&mut self) -> Result<(), NetworkError> {
+        while !self.write_buffer.is_empty() {
+            match self.stream.write(&self.write_buffer) {
+                Ok(0) => return Err(NetworkError::Closed),
+                Ok(written) => {
+                    self.write_buffer.drain(..written);
+                }
+                Err(err) if err.kind() == io::ErrorKind::WouldBlock => break,
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+                Err(err) => return Err(NetworkError::Io(err)),
+            }
+        }
+        return Ok(());
+    }
+
+    // Reads everything currently available and decodes every complete message
+    pub fn receive<T: DeserializeOwned>(&mut self) -> Result<Vec<T>, NetworkError> {
+        let mut chunk = [0u8; 4096];
+        loop {
+            match self.stream.read(&mut chunk) {
+                Ok(0) => return Err(NetworkError::Closed),
+                Ok(read) => self.read_buffer.extend_from_slice(&chunk[..read]),
+                Err(err) if err.kind() == io::ErrorKind::WouldBlock => break,
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+                Err(err) => return Err(NetworkError::Io(err)),
+            }
+        }
+
+        let mut messages = Vec::new();
+        while self.read_buffer.len() >= LENGTH_PREFIX_SIZE {
+            let mut length_bytes = [0u8; LENGTH_PREFIX_SIZE];
+            length_bytes.copy_from_slice(&self.read_buffer[..LENGTH_PREFIX_SIZE]);
+            let length = u32::from_be_bytes(length_bytes) as usize;
+
+            if length > MAX_MESSAGE_SIZE {
+                return Err(NetworkError::MessageTooLarge(length));
+            }
+            if self.read_buffer.len() < LENGTH_PREFIX_SIZE + length {
+                // Wait for the rest of the frame
+                break;
+            }
+
+            let frame: Vec<u8> = self
+                .read_buffer
+                .drain(..LENGTH_PREFIX_SIZE + length)
+                .skip(LENGTH_PREFIX_SIZE)
+                .collect();
+            messages.push(bincode::deserialize(&frame).map_err(NetworkError::Decode)?);
+        }
+
+        return Ok(messages);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        net::TcpListener,
+        thread,
+        time::{Duration, Instant},
+    };
+
+    use super::*;
+
+    // A connection to read from, and the raw socket feeding it
+    fn pair() -> (Connection, TcpStream) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let sender = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (stream, _) = listener.accept().unwrap();
+        return (Connection::new(stream).unwrap(), sender);
+    }
+
+    fn frame(message: &str) -> Vec<u8> {
+        let payload = bincode::serialize(message).unwrap();
+        let mut bytes = (payload.len() as u32).to_be_bytes().to_vec();
+        bytes.extend_from_slice(&payload);
+        return bytes;
+    }
+
+    // Loopback is quick but not instant, so keep reading until the buffer
+    // holds this many bytes or the test gives up
+    fn receive_buffered<T: DeserializeOwned>(
+        connection: &mut Connection,
+        buffered: usize,
+    ) -> Result<Vec<T>, NetworkError> {
+        let deadline = Instant::now() + Duration::from_secs(5);
+        let mut messages = Vec::new();
+        loop {
+            messages.extend(connection.receive::<T>()?);
+            if connection.read_buffer.len() >= buffered || Instant::now() > deadline {
+                return Ok(messages);
+            }
+            thread::sleep(Duration::from_millis(1));
+        }
+    }
+
+    fn receive_count<T: DeserializeOwned>(connection: &mut Connection, count: usize) -> Vec<T> {
+        let deadline = Instant::now() + Duration::from_secs(5);
+        let mut messages = Vec::new();
+        while messages.len() < count && Instant::now() < deadline {
+            messages.extend(connection.receive::<T>().unwrap());
+            thread::sleep(Duration::from_millis(1));
+        }
+        return messages;
+    }
+
+    #[test]
+    fn frames_split_across_reads_wait_for_the_rest() {
+        let (mut connection, mut sender) = pair();
+        let bytes = frame("hello");
+        let (first, rest) = bytes.split_at(6);
+
+        sender.write_all(first).unwrap();
+        let messages = receive_buffered::<String>(&mut connection, first.len()).unwrap();
+        assert!(messages.is_empty());
+        assert_eq!(connection.read_buffer.len(), first.len());
+
+        sender.write_all(rest).unwrap();
+        assert_eq!(receive_count::<String>(&mut connection, 1), vec!["hello"]);
+        assert!(connection.read_buffer.is_empty());
+    }
+
+    #[test]
+    fn several_frames_in_one_read_all_come_out() {
+        let (mut connection, mut sender) = pair();
+        let mut bytes = Vec::new();
+        for message in ["one", "two", "three"] {
+            bytes.extend(frame(message));
+        }
+        // Half of a fourth, which should be left in the buffer
+        bytes.extend_from_slice(&frame("four")[..3]);
+        sender.write_all(&bytes).unwrap();
+
+        assert_eq!(
+            receive_count::<String>(&mut connection, 3),
+            vec!["one", "two", "three"]
+        );
+        assert_eq!(connection.read_buffer.len(), 3);
+    }
+
+    #[test]
+    fn oversized_prefixes_are_rejected() {
+        let (mut connection, mut sender) = pair();
+        sender
+            .write_all(&(MAX_MESSAGE_SIZE as u32 + 1).to_be_bytes())
+            .unwrap();
+
+        let result = receive_buffered::<String>(&mut connection, usize::MAX);
+        assert!(matches!(
+            result,
+            Err(NetworkError::MessageTooLarge(size)) if size == MAX_MESSAGE_SIZE + 1
+        ));
+    }
+
+    #[test]
+    fn corrupt_payloads_fail_to_decode() {
+        let (mut connection, mut sender) = pair();
+        // Two bytes can't hold a u64
+        sender.write_all(&[0, 0, 0, 2, 0xff, 0xff]).unwrap();
+
+        let result = receive_buffered::<u64>(&mut connection, usize::MAX);
+        assert!(matches!(result, Err(NetworkError::Decode(_))));
+    }
+
+    #[test]
+    fn sent_messages_arrive_intact() {
+        let (mut receiver, sender) = pair();
+        let mut sender = Connection::new(sender).unwrap();
+        sender.send(&"over the wire".to_string()).unwrap();
+
+        assert_eq!(
+            receive_count::<String>(&mut receiver, 1),
+            vec!["over the wire"]
+        );
+    }
+}
@@ -0,0 +1,98 @@
+use serde::{Deserialize, Serialize};
+
+use bevy::prelude::Vec2;
+
+use crate::common::logic::{
+    neo_gameboard::Tile, turn::TurnResult, units::UnitID, Cost, PlayerTeam, Unit, UnitAction,
+};
+
+// Bump this whenever a message changes shape. Clients and servers on
+// different versions refuse each other during the handshake
+pub const PROTOCOL_VERSION: u32 = 8;
+
+#[derive(Debug, Deserialize, Serialize)]
+pub enum ClientMessage {
+    Handshake {
+        version: u32,
+        username: String,
+    },
+    SetReady(bool),
+    // Only honoured from the host
+    StartMatch,
+    SubmitOrders {
+        actions: Vec<UnitAction>,
+        // Units to recruit at the player's nest
+        recruits: Vec<UnitID>,
+    },
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub enum ServerMessage {
+    Handshake {
+        version: u32,
+        team: PlayerTeam,
+    },
+    LobbyUpdate(LobbyInfo),
+    MatchStarted,
+    BoardSnapshot(BoardSnapshot),
+    TurnResult {
+        result: TurnResult,
+        delta: BoardDelta,
+    },
+    Error(ProtocolError),
+}
+
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
+pub struct LobbyInfo {
+    pub max_players: u32,
+    pub players: Vec<LobbyPlayer>,
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct LobbyPlayer {
+    pub username: String,
+    pub team: PlayerTeam,
+    pub ready: bool,
+    pub host: bool,
+}
+
+// Everything a player can see when they join a match. Tiles they can't see
+// aren't sent at all
+#[derive(Debug, Deserialize, Serialize)]
+pub struct BoardSnapshot {
+    pub turn: u32,
+    pub width: u32,
+    pub height: u32,
+    // Tiles the player can currently see
+    pub visible: Vec<Vec2>,
+    // Every tile the player is allowed to know about
+    pub tiles: Vec<Tile>,
+    pub units: Vec<Unit>,
+    // The player's own currency, nobody else's
+    pub balance: Cost,
+}
+
+// What changed for a player over a turn. Tiles that drop out of `visible` keep
+// whatever the player last saw of them
+#[derive(Debug, Deserialize, Serialize)]
+pub struct BoardDelta {
+    pub visible: Vec<Vec2>,
+    // Only tiles that are different from the last time they were sent
+    pub tiles: Vec<Tile>,
+    pub units: Vec<Unit>,
+    pub balance: Cost,
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub enum ProtocolError {
+    VersionMismatch { server: u32, client: u32 },
+    HandshakeRequired,
+    ServerFull,
+    MatchInProgress,
+    NotInGame,
+    NotHost,
+    LobbyNotReady(String),
+    InvalidOrder(String),
+    // Knocked out players can watch, but not give orders
+    Spectating,
+}
@@ -1,10 +1,14 @@
 mod client;
 mod common;
+mod server;
 
 use bevy::prelude::*;
 use bevy_inspector_egui::quick::WorldInspectorPlugin;
-use client::{ClientPlugin, SingleplayerPlugin};
+use client::{
+    network::ClientNetworkPlugin, replay::ReplayPlugin, ClientPlugin, SingleplayerPlugin,
+};
 use common::config::{Config, RunEnvironment};
+use server::ServerPlugin;
 
 fn main() {
     let config = Config::load();
@@ -12,16 +16,22 @@ fn main() {
     let mut app = App::new();
     // Server startup
     match config.env {
-        RunEnvironment::Singleplayer | RunEnvironment::Client => {
+        RunEnvironment::Singleplayer | RunEnvironment::Client | RunEnvironment::Replay => {
             app.add_plugin(ClientPlugin);
             if config.debug {
                 app.add_plugin(WorldInspectorPlugin::default());
             }
-            if config.env == RunEnvironment::Singleplayer {
-                app.add_plugin(SingleplayerPlugin);
+            // The main menu decides between these
+            if config.env == RunEnvironment::Replay {
+                app.add_plugin(ReplayPlugin);
+            } else {
+                app.add_plugin(SingleplayerPlugin)
+                    .add_plugin(ClientNetworkPlugin);
             }
         }
-        RunEnvironment::Server => panic!("Server is currently not supported"),
+        RunEnvironment::Server => {
+            app.add_plugin(ServerPlugin);
+        }
     };
     app.insert_resource(config).run();
 }
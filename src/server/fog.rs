@@ -0,0 +1,461 @@
+use bevy::prelude::*;
+
+use crate::common::{
+    logic::{
+        economy::Economy,
+        neo_gameboard::{Gameboard, Tile},
+        turn::{TurnEvent, TurnResult},
+        PlayerTeam, Unit, UnitAction,
+    },
+    network::protocol::{BoardDelta, BoardSnapshot},
+};
+
+// What a client has already been sent, so turns only need to send what changed
+#[derive(Default)]
+pub struct KnownTiles {
+    height: usize,
+    tiles: Vec<Option<Tile>>,
+}
+
+impl KnownTiles {
+    fn index(&self, pos: Vec2) -> usize {
+        return pos.x as usize * self.height + pos.y as usize;
+    }
+}
+
+// Who a copy of the board is being made for. Teams that have been knocked out
+// spectate, and get to see the whole board
+pub struct Viewer<'a> {
+    pub team: &'a PlayerTeam,
+    pub spectating: bool,
+}
+
+impl Viewer<'_> {
+    fn sees(&self, tile: &Tile) -> bool {
+        return self.spectating || tile.is_visible_to(self.team);
+    }
+
+    fn sees_pos(&self, gameboard: &Gameboard, pos: &Vec2) -> bool {
+        return gameboard
+            .tile(pos.x as usize, pos.y as usize)
+            .map_or(false, |t| self.sees(t));
+    }
+
+    fn is(&self, team: &PlayerTeam) -> bool {
+        return self.spectating || *self.team == *team;
+    }
+}
+
+// The copy of a tile a team is allowed to have. Tiles a team can't see are
+// never sent at all, this just hides what everyone else can see
+fn tile_for(tile: &Tile, viewer: &Viewer) -> Tile {
+    let mut tile = tile.clone();
+    if viewer.spectating {
+        return tile;
+    }
+    let team = viewer.team;
+    let visible_for = if tile.is_visible_to(team) {
+        vec![team.0.clone()]
+    } else {
+        Vec::new()
+    };
+    tile.set_visible_for(visible_for);
+
+    if let Some(feature) = tile.feature_mut() {
+        feature.visible_to_players.retain(|t| t == team);
+    }
+    return tile;
+}
+
+// A team always knows about its own structures, even once nobody is watching
+// them
+fn is_known_to(tile: &Tile, viewer: &Viewer) -> bool {
+    return viewer.sees(tile) || tile.structure().map_or(false, |s| s.owner == *viewer.team);
+}
+
+fn visible_tiles(gameboard: &Gameboard, viewer: &Viewer) -> Vec<Vec2> {
+    return gameboard
+        .tiles()
+        .filter(|t| viewer.sees(t))
+        .map(|t| t.pos())
+        .collect();
+}
+
+// Every unit of the team's own, and anyone else's they can see
+pub fn visible_units(gameboard: &Gameboard, viewer: &Viewer, units: &[Unit]) -> Vec<Unit> {
+    return units
+        .iter()
+        .filter(|u| u.is_alive())
+        .filter(|u| viewer.is(&u.owner) || viewer.sees_pos(gameboard, &u.pos))
+        .cloned()
+        .collect();
+}
+
+pub fn snapshot_for(
+    viewer: &Viewer,
+    known: &mut KnownTiles,
+    turn: u32,
+    gameboard: &Gameboard,
+    economy: &Economy,
+    units: &[Unit],
+) -> BoardSnapshot {
+    known.height = gameboard.y() as usize;
+    known.tiles = vec![None; (gameboard.x() * gameboard.y()) as usize];
+
+    let mut tiles = Vec::<Tile>::new();
+    for tile in gameboard.tiles().filter(|t| is_known_to(t, viewer)) {
+        let tile = tile_for(tile, viewer);
+        let index = known.index(tile.pos());
+        known.tiles[index] = Some(tile.clone());
+        tiles.push(tile);
+    }
+
+    return BoardSnapshot {
+        turn,
+        width: gameboard.x(),
+        height: gameboard.y(),
+        visible: visible_tiles(gameboard, viewer),
+        tiles,
+        units: visible_units(gameboard, viewer, units),
+        balance: economy.balance(viewer.team),
+    };
+}
+
+pub fn delta_for(
+    viewer: &Viewer,
+    known: &mut KnownTiles,
+    gameboard: &Gameboard,
+    economy: &Economy,
+    units: &[Unit],
+) -> BoardDelta {
+    let mut tiles = Vec::<Tile>::new();
+    for tile in gameboard.tiles().filter(|t| is_known_to(t, viewer)) {
+        let tile = tile_for(tile, viewer);
+        let index = known.index(tile.pos());
+        if known
+            .tiles
+            .get(index)
+            .map_or(false, |t| t.as_ref() == Some(&tile))
+        {
+            continue;
+        }
+        if index < known.tiles.len() {
+            known.tiles[index] = Some(tile.clone());
+        }
+        tiles.push(tile);
+    }
+
+    return BoardDelta {
+        visible: visible_tiles(gameboard, viewer),
+        tiles,
+        units: visible_units(gameboard, viewer, units),
+        balance: economy.balance(viewer.team),
+    };
+}
+
+// Only the events that happened somewhere the team can see, plus failures of
+// its own orders. Nothing sent can point at a tile the team can't see, unless
+// it's about the team's own things
+pub fn result_for(
+    viewer: &Viewer,
+    gameboard: &Gameboard,
+    result: &TurnResult,
+    orders: &[UnitAction],
+) -> TurnResult {
+    let visible = |pos: &Vec2| viewer.sees_pos(gameboard, pos);
+    let owns = |owner: &PlayerTeam| viewer.is(owner);
+
+    let events = result
+        .events
+        .iter()
+        .filter_map(|event| {
+            let keep = match event {
+                TurnEvent::ActionFailed { action, .. } => orders.contains(action),
+                // Half a move would give away where the unit came from or
+                // went to. The delta still shows it coming into or out of view
+                TurnEvent::Moved { from, to, owner } => {
+                    owns(owner) || (visible(from) && visible(to))
+                }
+                TurnEvent::Damaged { pos, .. }
+                | TurnEvent::Healed { pos, .. }
+                | TurnEvent::StructureDamaged { pos, .. } => visible(pos),
+                TurnEvent::BuildProgress { pos, .. } | TurnEvent::Upgraded { pos, .. } => {
+                    visible(pos)
+                }
+                // Players always hear about their own things
+                TurnEvent::Built { pos, owner, .. }
+                | TurnEvent::StructureDestroyed { pos, owner, .. }
+                | TurnEvent::Destroyed { pos, owner }
+                | TurnEvent::SiteCaptured { pos, owner }
+                | TurnEvent::NestRazed { pos, owner } => owns(owner) || visible(pos),
+                TurnEvent::Recruited { pos, owner, .. } => owns(owner) || visible(pos),
+                // Nobody else gets to know how much anyone has, or what
+                // they're spending it on
+                TurnEvent::Income { owner, .. }
+                | TurnEvent::RecruitQueued { owner, .. }
+                | TurnEvent::RecruitFailed { owner, .. } => owns(owner),
+                // Everyone hears about the match ending, and who's out of it
+                TurnEvent::Eliminated { .. } | TurnEvent::MatchOver { .. } => true,
+            };
+            if !keep {
+                return None;
+            }
+
+            // Being hit from out of sight doesn't say where from
+            let mut event = event.clone();
+            if let TurnEvent::Damaged { source, .. }
+            | TurnEvent::Healed { source, .. }
+            | TurnEvent::StructureDamaged { source, .. } = &mut event
+            {
+                if !source.map_or(false, |pos| visible(&pos)) {
+                    *source = None;
+                }
+            }
+            return Some(event);
+        })
+        .collect();
+
+    return TurnResult {
+        turn: result.turn,
+        events,
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::logic::{
+        structures::{Structure, StructureKind},
+        testing::{grass_board, order, unit},
+        turn::ActionFailure,
+        Cost, TeamColour, TileFeatures, TurnExecuteStages, UnitActions,
+    };
+
+    // Blue at one end of the board, with a red unit next to it and another
+    // far out of sight at the other end, where red also has a wall
+    fn board() -> (Gameboard, Vec<Unit>) {
+        let mut gameboard = grass_board(12, 1);
+        gameboard
+            .tile_mut(11, 0)
+            .unwrap()
+            .set_structure(Structure::new(
+                StructureKind::Wall,
+                PlayerTeam(TeamColour::Red),
+            ));
+        gameboard
+            .tile_mut(1, 0)
+            .unwrap()
+            .set_feature(TileFeatures::Nest(PlayerTeam(TeamColour::Red)));
+        let units = vec![
+            unit(TeamColour::Blue, 0f32, 0f32),
+            unit(TeamColour::Red, 2f32, 0f32),
+            unit(TeamColour::Red, 10f32, 0f32),
+        ];
+        gameboard.update_vision(&units);
+        return (gameboard, units);
+    }
+
+    fn blue() -> PlayerTeam {
+        return PlayerTeam(TeamColour::Blue);
+    }
+
+    #[test]
+    fn snapshots_only_have_what_the_team_can_see() {
+        let (gameboard, units) = board();
+        let team = blue();
+        let viewer = Viewer {
+            team: &team,
+            spectating: false,
+        };
+
+        let snapshot = snapshot_for(
+            &viewer,
+            &mut KnownTiles::default(),
+            0,
+            &gameboard,
+            &Economy::default(),
+            &units,
+        );
+
+        let unit_positions = snapshot.units.iter().map(|u| u.pos).collect::<Vec<Vec2>>();
+        assert_eq!(
+            unit_positions,
+            vec![Vec2::new(0f32, 0f32), Vec2::new(2f32, 0f32)]
+        );
+        assert!(snapshot.tiles.iter().all(|t| t.pos().x <= 3f32));
+        assert_eq!(snapshot.visible.len(), snapshot.tiles.len());
+
+        // Nothing on a tile gives away who else can see it
+        for tile in &snapshot.tiles {
+            assert_eq!(tile.visible_for(), &[TeamColour::Blue]);
+            if let Some(feature) = tile.feature() {
+                assert_eq!(feature.visible_to_players, vec![team.clone()]);
+            }
+        }
+    }
+
+    #[test]
+    fn spectators_see_everything() {
+        let (gameboard, units) = board();
+        let team = blue();
+        let viewer = Viewer {
+            team: &team,
+            spectating: true,
+        };
+
+        let snapshot = snapshot_for(
+            &viewer,
+            &mut KnownTiles::default(),
+            0,
+            &gameboard,
+            &Economy::default(),
+            &units,
+        );
+
+        assert_eq!(snapshot.units.len(), 3);
+        assert_eq!(snapshot.tiles.len(), 12);
+        assert!(snapshot.tiles[11].structure().is_some());
+    }
+
+    #[test]
+    fn results_only_have_what_the_team_can_see() {
+        let (gameboard, _) = board();
+        let team = blue();
+        let viewer = Viewer {
+            team: &team,
+            spectating: false,
+        };
+
+        let own_order = order(
+            UnitActions::Attack,
+            TurnExecuteStages::MidTurn,
+            (0f32, 0f32),
+            (1f32, 0f32),
+        );
+        let enemy_order = order(
+            UnitActions::Attack,
+            TurnExecuteStages::MidTurn,
+            (2f32, 0f32),
+            (1f32, 0f32),
+        );
+        let visible = vec![
+            TurnEvent::Moved {
+                from: Vec2::new(3f32, 0f32),
+                to: Vec2::new(2f32, 0f32),
+                owner: PlayerTeam(TeamColour::Red),
+            },
+            // Blue always knows where its own units went
+            TurnEvent::Moved {
+                from: Vec2::new(0f32, 0f32),
+                to: Vec2::new(9f32, 0f32),
+                owner: blue(),
+            },
+            TurnEvent::Damaged {
+                pos: Vec2::new(2f32, 0f32),
+                amount: 1f32,
+                source: Some(Vec2::new(0f32, 0f32)),
+            },
+            TurnEvent::ActionFailed {
+                action: own_order.clone(),
+                reason: ActionFailure::NoTarget,
+            },
+            TurnEvent::Income {
+                owner: blue(),
+                amount: Cost::default(),
+            },
+            TurnEvent::Destroyed {
+                pos: Vec2::new(9f32, 0f32),
+                owner: blue(),
+            },
+            TurnEvent::MatchOver {
+                winners: vec![PlayerTeam(TeamColour::Red)],
+            },
+        ];
+        let hidden = vec![
+            TurnEvent::Moved {
+                from: Vec2::new(10f32, 0f32),
+                to: Vec2::new(9f32, 0f32),
+                owner: PlayerTeam(TeamColour::Red),
+            },
+            // Coming out of the fog
+            TurnEvent::Moved {
+                from: Vec2::new(5f32, 0f32),
+                to: Vec2::new(3f32, 0f32),
+                owner: PlayerTeam(TeamColour::Red),
+            },
+            // Walking into it
+            TurnEvent::Moved {
+                from: Vec2::new(3f32, 0f32),
+                to: Vec2::new(5f32, 0f32),
+                owner: PlayerTeam(TeamColour::Red),
+            },
+            // Blue shooting into the fog
+            TurnEvent::Damaged {
+                pos: Vec2::new(9f32, 0f32),
+                amount: 1f32,
+                source: Some(Vec2::new(2f32, 0f32)),
+            },
+            TurnEvent::ActionFailed {
+                action: enemy_order.clone(),
+                reason: ActionFailure::NoTarget,
+            },
+            TurnEvent::Income {
+                owner: PlayerTeam(TeamColour::Red),
+                amount: Cost::default(),
+            },
+            TurnEvent::StructureDamaged {
+                pos: Vec2::new(11f32, 0f32),
+                amount: 1f32,
+                source: Some(Vec2::new(10f32, 0f32)),
+            },
+        ];
+
+        let mut events = visible.clone();
+        events.extend(hidden);
+        let result = TurnResult { turn: 4, events };
+
+        let filtered = result_for(&viewer, &gameboard, &result, &[own_order]);
+        assert_eq!(
+            filtered,
+            TurnResult {
+                turn: 4,
+                events: visible,
+            }
+        );
+    }
+
+    #[test]
+    fn hidden_attackers_stay_hidden() {
+        let (gameboard, _) = board();
+        let team = blue();
+        let viewer = Viewer {
+            team: &team,
+            spectating: false,
+        };
+        let hit = |source: Option<Vec2>| TurnEvent::Damaged {
+            pos: Vec2::new(0f32, 0f32),
+            amount: 2f32,
+            source,
+        };
+        let healed = |source: Option<Vec2>| TurnEvent::Healed {
+            pos: Vec2::new(2f32, 0f32),
+            amount: 2f32,
+            source,
+        };
+
+        let result = TurnResult {
+            turn: 4,
+            events: vec![
+                hit(Some(Vec2::new(10f32, 0f32))),
+                hit(Some(Vec2::new(1f32, 0f32))),
+                healed(Some(Vec2::new(6f32, 0f32))),
+            ],
+        };
+
+        let filtered = result_for(&viewer, &gameboard, &result, &[]);
+        assert_eq!(
+            filtered.events,
+            vec![hit(None), hit(Some(Vec2::new(1f32, 0f32))), healed(None)]
+        );
+    }
+}
@@ -0,0 +1,406 @@
+use bevy::prelude::*;
+
+use crate::common::{
+    config::Config,
+    logic::{
+        economy::Economy, neo_gameboard::Gameboard, recruitment::place_starting_armies,
+        units::UnitRegistry, victory::MatchState, PlayerTeam, TeamColour, TurnCounter, Unit,
+    },
+    network::protocol::{LobbyInfo, LobbyPlayer, ProtocolError, ServerMessage, PROTOCOL_VERSION},
+};
+
+use super::{
+    fog::{snapshot_for, Viewer},
+    network::{ClientConnection, ServerConnections},
+    ServerState,
+};
+
+// The host is whoever has been in the lobby longest. If they leave, the next
+// player in line takes over
+fn host_index(clients: &[ClientConnection]) -> Option<usize> {
+    return clients.iter().position(|c| c.team.is_some());
+}
+
+pub fn lobby_info(clients: &[ClientConnection], max_players: u32) -> LobbyInfo {
+    let host = host_index(clients);
+    return LobbyInfo {
+        max_players,
+        players: clients
+            .iter()
+            .enumerate()
+            .filter_map(|(i, c)| {
+                Some(LobbyPlayer {
+                    username: c.username.clone()?,
+                    team: c.team.clone()?,
+                    ready: c.ready,
+                    host: host == Some(i),
+                })
+            })
+            .collect(),
+    };
+}
+
+pub fn broadcast_lobby(clients: &mut [ClientConnection], max_players: u32) {
+    let message = ServerMessage::LobbyUpdate(lobby_info(clients, max_players));
+    for client in clients.iter_mut().filter(|c| c.team.is_some()) {
+        client.send(&message);
+    }
+}
+
+pub fn handle_handshake(
+    clients: &mut [ClientConnection],
+    index: usize,
+    version: u32,
+    username: String,
+    max_players: u32,
+    state: ServerState,
+) -> bool {
+    if version != PROTOCOL_VERSION {
+        clients[index].send(&ServerMessage::Error(ProtocolError::VersionMismatch {
+            server: PROTOCOL_VERSION,
+            client: version,
+        }));
+        return false;
+    }
+
+    if clients[index].team.is_some() {
+        // Already shook hands, nothing to do
+        return true;
+    }
+
+    if state != ServerState::Lobby {
+        clients[index].send(&ServerMessage::Error(ProtocolError::MatchInProgress));
+        return false;
+    }
+
+    // Hand out the first colour nobody else has
+    let taken_teams = clients
+        .iter()
+        .filter_map(|c| c.team.clone())
+        .collect::<Vec<PlayerTeam>>();
+    let Some(team) = (0..max_players as usize)
+        .map(|i| PlayerTeam(TeamColour::from_int(&i)))
+        .find(|t| !taken_teams.contains(t))
+    else {
+        clients[index].send(&ServerMessage::Error(ProtocolError::ServerFull));
+        return false;
+    };
+
+    info!("{} joined the lobby as {:?}", username, team.0);
+    let client = &mut clients[index];
+    client.username = Some(username);
+    client.team = Some(team.clone());
+    client.ready = false;
+
+    return client.send(&ServerMessage::Handshake {
+        version: PROTOCOL_VERSION,
+        team,
+    });
+}
+
+pub fn handle_set_ready(
+    clients: &mut [ClientConnection],
+    index: usize,
+    ready: bool,
+    state: ServerState,
+) -> bool {
+    let client = &mut clients[index];
+    if client.team.is_none() {
+        return client.send(&ServerMessage::Error(ProtocolError::HandshakeRequired));
+    }
+    if state != ServerState::Lobby {
+        return client.send(&ServerMessage::Error(ProtocolError::MatchInProgress));
+    }
+
+    client.ready = ready;
+    return true;
+}
+
+pub fn handle_start_match(
+    clients: &mut [ClientConnection],
+    index: usize,
+    max_players: u32,
+    state: ServerState,
+    next_state: &mut NextState<ServerState>,
+) -> bool {
+    if state != ServerState::Lobby {
+        return clients[index].send(&ServerMessage::Error(ProtocolError::MatchInProgress));
+    }
+    if host_index(clients) != Some(index) {
+        return clients[index].send(&ServerMessage::Error(ProtocolError::NotHost));
+    }
+
+    let lobby = lobby_info(clients, max_players);
+    if (lobby.players.len() as u32) < max_players {
+        return clients[index].send(&ServerMessage::Error(ProtocolError::LobbyNotReady(
+            format!(
+                "waiting for players ({}/{})",
+                lobby.players.len(),
+                max_players
+            ),
+        )));
+    }
+    if let Some(player) = lobby.players.iter().find(|p| !p.ready) {
+        return clients[index].send(&ServerMessage::Error(ProtocolError::LobbyNotReady(
+            format!("{} is not ready", player.username),
+        )));
+    }
+
+    info!("Starting match with {} players", lobby.players.len());
+    next_state.set(ServerState::InGame);
+    return true;
+}
+
+// Every player gets a nest with a starting army around it. Runs before
+// start_match so they're on the board everyone gets sent
+pub fn spawn_starting_armies(
+    mut commands: Commands,
+    config: Res<Config>,
+    connections: Res<ServerConnections>,
+    registry: Res<UnitRegistry>,
+    mut match_state: ResMut<MatchState>,
+    mut gameboard_q: Query<&mut Gameboard>,
+) {
+    let Ok(mut gameboard) = gameboard_q.get_single_mut() else {
+        error!("Match started without a gameboard");
+        return;
+    };
+
+    let teams = connections
+        .clients
+        .iter()
+        .filter_map(|c| c.team.clone())
+        .collect::<Vec<PlayerTeam>>();
+    for unit in place_starting_armies(&mut gameboard, &registry, &teams) {
+        commands.spawn(unit).insert(Name::new("Unit"));
+    }
+    *match_state = MatchState::new(config.victory_config.clone(), &teams);
+}
+
+pub fn start_match(
+    mut connections: ResMut<ServerConnections>,
+    turn: Res<TurnCounter>,
+    economy: Res<Economy>,
+    gameboard_q: Query<&Gameboard>,
+    units: Query<&Unit>,
+) {
+    let Ok(gameboard) = gameboard_q.get_single() else {
+        error!("Match started without a gameboard");
+        return;
+    };
+
+    let units = units.iter().cloned().collect::<Vec<Unit>>();
+    for client in connections.clients.iter_mut() {
+        let Some(team) = client.team.clone() else {
+            continue;
+        };
+
+        let viewer = Viewer {
+            team: &team,
+            spectating: client.spectating,
+        };
+        let snapshot = snapshot_for(
+            &viewer,
+            &mut client.known_tiles,
+            turn.0,
+            gameboard,
+            &economy,
+            &units,
+        );
+        client.send(&ServerMessage::MatchStarted);
+        client.send(&ServerMessage::BoardSnapshot(snapshot));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        net::{TcpListener, TcpStream},
+        thread,
+        time::{Duration, Instant},
+    };
+
+    use crate::common::network::Connection;
+
+    use super::{super::fog::KnownTiles, *};
+
+    // A client the server hasn't heard from yet, and the other end of its
+    // socket to read replies from
+    fn connect() -> (ClientConnection, Connection) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let remote = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (stream, _) = listener.accept().unwrap();
+        let client = ClientConnection {
+            connection: Connection::new(stream).unwrap(),
+            username: None,
+            team: None,
+            ready: false,
+            spectating: false,
+            known_tiles: KnownTiles::default(),
+        };
+        return (client, Connection::new(remote).unwrap());
+    }
+
+    fn lobby(count: usize) -> (Vec<ClientConnection>, Vec<Connection>) {
+        let (mut clients, mut remotes) = (Vec::new(), Vec::new());
+        for _ in 0..count {
+            let (client, remote) = connect();
+            clients.push(client);
+            remotes.push(remote);
+        }
+        return (clients, remotes);
+    }
+
+    fn join(clients: &mut [ClientConnection], index: usize, max_players: u32) -> bool {
+        return handle_handshake(
+            clients,
+            index,
+            PROTOCOL_VERSION,
+            format!("player {}", index),
+            max_players,
+            ServerState::Lobby,
+        );
+    }
+
+    // The first error the server sent, waiting a little for it to arrive
+    fn error(remote: &mut Connection) -> Option<ProtocolError> {
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while Instant::now() < deadline {
+            for message in remote.receive::<ServerMessage>().unwrap() {
+                if let ServerMessage::Error(err) = message {
+                    return Some(err);
+                }
+            }
+            thread::sleep(Duration::from_millis(1));
+        }
+        return None;
+    }
+
+    #[test]
+    fn colours_are_handed_out_in_order() {
+        let (mut clients, _remotes) = lobby(4);
+        for index in 0..4 {
+            assert!(join(&mut clients, index, 4));
+        }
+
+        let teams = clients
+            .iter()
+            .map(|c| c.team.clone().unwrap().0)
+            .collect::<Vec<TeamColour>>();
+        assert_eq!(
+            teams,
+            vec![
+                TeamColour::Blue,
+                TeamColour::Red,
+                TeamColour::Purple,
+                TeamColour::Yellow
+            ]
+        );
+    }
+
+    #[test]
+    fn freed_colours_go_to_the_next_player() {
+        let (mut clients, _remotes) = lobby(3);
+        assert!(join(&mut clients, 0, 3));
+        assert!(join(&mut clients, 1, 3));
+        clients.remove(0);
+
+        assert!(join(&mut clients, 1, 3));
+        assert_eq!(clients[1].team, Some(PlayerTeam(TeamColour::Blue)));
+    }
+
+    #[test]
+    fn full_lobbies_turn_players_away() {
+        let (mut clients, mut remotes) = lobby(3);
+        assert!(join(&mut clients, 0, 2));
+        assert!(join(&mut clients, 1, 2));
+
+        assert!(!join(&mut clients, 2, 2));
+        assert!(clients[2].team.is_none());
+        assert_eq!(error(&mut remotes[2]), Some(ProtocolError::ServerFull));
+    }
+
+    #[test]
+    fn other_versions_are_turned_away() {
+        let (mut clients, mut remotes) = lobby(1);
+        let version = PROTOCOL_VERSION + 1;
+        assert!(!handle_handshake(
+            &mut clients,
+            0,
+            version,
+            "player".to_string(),
+            2,
+            ServerState::Lobby,
+        ));
+
+        assert!(clients[0].team.is_none());
+        assert_eq!(
+            error(&mut remotes[0]),
+            Some(ProtocolError::VersionMismatch {
+                server: PROTOCOL_VERSION,
+                client: version,
+            })
+        );
+    }
+
+    #[test]
+    fn only_the_host_can_start() {
+        let (mut clients, mut remotes) = lobby(2);
+        for index in 0..2 {
+            assert!(join(&mut clients, index, 2));
+            assert!(handle_set_ready(
+                &mut clients,
+                index,
+                true,
+                ServerState::Lobby
+            ));
+        }
+
+        let mut next_state = NextState::<ServerState>::default();
+        handle_start_match(&mut clients, 1, 2, ServerState::Lobby, &mut next_state);
+        assert_eq!(error(&mut remotes[1]), Some(ProtocolError::NotHost));
+        assert_eq!(next_state.0, None);
+
+        handle_start_match(&mut clients, 0, 2, ServerState::Lobby, &mut next_state);
+        assert_eq!(next_state.0, Some(ServerState::InGame));
+    }
+
+    #[test]
+    fn everyone_has_to_be_ready() {
+        let (mut clients, mut remotes) = lobby(2);
+        for index in 0..2 {
+            assert!(join(&mut clients, index, 2));
+        }
+        assert!(handle_set_ready(&mut clients, 0, true, ServerState::Lobby));
+
+        let mut next_state = NextState::<ServerState>::default();
+        handle_start_match(&mut clients, 0, 2, ServerState::Lobby, &mut next_state);
+        assert_eq!(
+            error(&mut remotes[0]),
+            Some(ProtocolError::LobbyNotReady(
+                "player 1 is not ready".to_string()
+            ))
+        );
+        assert_eq!(next_state.0, None);
+    }
+
+    #[test]
+    fn the_next_player_takes_over_as_host() {
+        let (mut clients, _remotes) = lobby(3);
+        for index in 0..3 {
+            assert!(join(&mut clients, index, 3));
+        }
+        assert!(lobby_info(&clients, 3).players[0].host);
+
+        // The host leaves, the same way receive_client_messages drops them
+        clients.remove(0);
+
+        let hosts = lobby_info(&clients, 3)
+            .players
+            .iter()
+            .map(|p| p.host)
+            .collect::<Vec<bool>>();
+        assert_eq!(hosts, vec![true, false]);
+        assert_eq!(host_index(&clients), Some(0));
+    }
+}
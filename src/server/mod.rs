@@ -0,0 +1,91 @@
+pub mod fog;
+pub mod lobby;
+pub mod network;
+
+use std::{net::TcpListener, time::Duration};
+
+use bevy::{app::ScheduleRunnerSettings, log::LogPlugin, prelude::*};
+
+use self::lobby::{spawn_starting_armies, start_match};
+use self::network::{
+    accept_connections, broadcast_turn_results, dispatch_orders, receive_client_messages,
+    ServerConnections, SubmittedOrders,
+};
+use crate::common::{
+    config::Config,
+    logic::{
+        neo_gameboard::spawn_gameboard, replay::start_recording, turn::resolve_turns,
+        vision::update_vision, GameLogicPlugin,
+    },
+};
+
+// Ticks per second. There's nothing to draw, so this only needs to be fast
+// enough to keep the network responsive
+const TICK_RATE: f64 = 30f64;
+
+pub struct ServerPlugin;
+
+impl Plugin for ServerPlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(ScheduleRunnerSettings::run_loop(Duration::from_secs_f64(
+            1f64 / TICK_RATE,
+        )))
+        .add_plugins(MinimalPlugins)
+        .add_plugin(LogPlugin::default())
+        .add_plugin(GameLogicPlugin)
+        .add_state::<ServerState>()
+        .init_resource::<ServerConnections>()
+        .init_resource::<SubmittedOrders>()
+        .add_startup_system(bind_listener)
+        .add_startup_system(spawn_gameboard)
+        .add_systems(
+            // Vision has to be worked out before anyone can be sent the board
+            (
+                spawn_starting_armies,
+                apply_system_buffers,
+                start_recording,
+                update_vision,
+                start_match,
+            )
+                .chain()
+                .in_schedule(OnEnter(ServerState::InGame)),
+        )
+        .add_system(accept_connections)
+        .add_system(receive_client_messages.after(accept_connections))
+        .add_system(
+            dispatch_orders
+                .after(receive_client_messages)
+                .before(resolve_turns),
+        )
+        .add_system(update_vision.after(resolve_turns))
+        .add_system(broadcast_turn_results.after(update_vision));
+    }
+}
+
+#[derive(States, Debug, Default, Clone, Copy, Eq, PartialEq, Hash)]
+pub enum ServerState {
+    #[default]
+    Lobby,
+    InGame,
+}
+
+#[derive(Resource)]
+pub struct ServerListener(TcpListener);
+
+fn bind_listener(mut commands: Commands, config: Res<Config>) {
+    let listener = match TcpListener::bind(&config.connection_address) {
+        Ok(listener) => listener,
+        Err(err) => panic!(
+            "Unable to bind server to {}: {:?}",
+            config.connection_address, err
+        ),
+    };
+
+    // The listener is polled once per tick, so it can't block the schedule
+    listener
+        .set_nonblocking(true)
+        .expect("Unable to set server listener to non-blocking");
+
+    info!("Server listening on {}", config.connection_address);
+    commands.insert_resource(ServerListener(listener));
+}
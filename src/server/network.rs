@@ -0,0 +1,324 @@
+use std::collections::HashMap;
+
+use bevy::prelude::*;
+
+use crate::common::{
+    config::Config,
+    logic::{
+        economy::Economy,
+        neo_gameboard::Gameboard,
+        recruitment::RecruitOrder,
+        turn::{PendingOrders, ResolveTurnEvent, TurnResolvedEvent},
+        units::UnitID,
+        victory::MatchState,
+        PlayerTeam, Unit, UnitAction,
+    },
+    network::{
+        protocol::{ClientMessage, ProtocolError, ServerMessage},
+        Connection,
+    },
+};
+
+use super::{
+    fog::{delta_for, result_for, KnownTiles, Viewer},
+    lobby::{broadcast_lobby, handle_handshake, handle_set_ready, handle_start_match},
+    ServerListener, ServerState,
+};
+
+#[derive(Resource, Default)]
+pub struct ServerConnections {
+    pub clients: Vec<ClientConnection>,
+}
+
+pub struct ClientConnection {
+    pub connection: Connection,
+    // Both of these are set once the client completes the handshake
+    pub username: Option<String>,
+    pub team: Option<PlayerTeam>,
+    pub ready: bool,
+    // Knocked out of the match, and watching the whole board
+    pub spectating: bool,
+    pub known_tiles: KnownTiles,
+}
+
+impl ClientConnection {
+    pub fn send(&mut self, message: &ServerMessage) -> bool {
+        if let Err(err) = self.connection.send(message) {
+            warn!("Unable to send to {}: {}", self.connection.addr(), err);
+            return false;
+        }
+        return true;
+    }
+}
+
+#[derive(Resource, Default)]
+pub struct SubmittedOrders {
+    pub orders: HashMap<PlayerTeam, (Vec<UnitAction>, Vec<UnitID>)>,
+    // The orders that went into the turn being resolved, so each player only
+    // hears about their own failing
+    pub dispatched: HashMap<PlayerTeam, Vec<UnitAction>>,
+}
+
+pub fn accept_connections(
+    listener: Option<Res<ServerListener>>,
+    mut connections: ResMut<ServerConnections>,
+) {
+    let Some(listener) = listener else {
+        return;
+    };
+
+    loop {
+        match listener.0.accept() {
+            Ok((stream, addr)) => match Connection::new(stream) {
+                Ok(connection) => {
+                    info!("Client connected from {}", addr);
+                    connections.clients.push(ClientConnection {
+                        connection,
+                        username: None,
+                        team: None,
+                        ready: false,
+                        spectating: false,
+                        known_tiles: KnownTiles::default(),
+                    });
+                }
+                Err(err) => warn!("Dropping connection from {}: {}", addr, err),
+            },
+            Err(err) if err.kind() == std::io::ErrorKind::WouldBlock => break,
+            Err(err) => {
+                warn!("Error accepting connection: {:?}", err);
+                break;
+            }
+        }
+    }
+}
+
+pub fn receive_client_messages(
+    config: Res<Config>,
+    state: Res<State<ServerState>>,
+    mut next_state: ResMut<NextState<ServerState>>,
+    mut connections: ResMut<ServerConnections>,
+    mut submitted_orders: ResMut<SubmittedOrders>,
+    units: Query<&Unit>,
+) {
+    let max_players = config.server_config.max_players;
+    let mut disconnected = Vec::<usize>::new();
+    let mut lobby_changed = false;
+
+    for index in 0..connections.clients.len() {
+        let messages = match connections.clients[index]
+            .connection
+            .receive::<ClientMessage>()
+        {
+            Ok(messages) => messages,
+            Err(err) => {
+                info!(
+                    "Client {} disconnected: {}",
+                    connections.clients[index].connection.addr(),
+                    err
+                );
+                disconnected.push(index);
+                continue;
+            }
+        };
+
+        for message in messages {
+            let keep_alive = match message {
+                ClientMessage::Handshake { version, username } => {
+                    lobby_changed = true;
+                    handle_handshake(
+                        &mut connections.clients,
+                        index,
+                        version,
+                        username,
+                        max_players,
+                        state.0,
+                    )
+                }
+                ClientMessage::SetReady(ready) => {
+                    lobby_changed = true;
+                    handle_set_ready(&mut connections.clients, index, ready, state.0)
+                }
+                ClientMessage::StartMatch => handle_start_match(
+                    &mut connections.clients,
+                    index,
+                    max_players,
+                    state.0,
+                    &mut next_state,
+                ),
+                ClientMessage::SubmitOrders { actions, recruits } => {
+                    let client = &mut connections.clients[index];
+                    let Some(team) = client.team.clone() else {
+                        client.send(&ServerMessage::Error(ProtocolError::HandshakeRequired));
+                        continue;
+                    };
+                    if state.0 != ServerState::InGame {
+                        client.send(&ServerMessage::Error(ProtocolError::NotInGame));
+                        continue;
+                    }
+                    if client.spectating {
+                        client.send(&ServerMessage::Error(ProtocolError::Spectating));
+                        continue;
+                    }
+
+                    match validate_orders(&team, &actions, &units) {
+                        Ok(()) => {
+                            submitted_orders.orders.insert(team, (actions, recruits));
+                            true
+                        }
+                        Err(err) => client.send(&ServerMessage::Error(err)),
+                    }
+                }
+            };
+
+            if !keep_alive {
+                disconnected.push(index);
+                break;
+            }
+        }
+    }
+
+    disconnected.sort();
+    disconnected.dedup();
+    for index in disconnected.into_iter().rev() {
+        let client = connections.clients.remove(index);
+        if let Some(team) = client.team {
+            submitted_orders.orders.remove(&team);
+            lobby_changed = true;
+        }
+    }
+
+    if lobby_changed && state.0 == ServerState::Lobby {
+        broadcast_lobby(&mut connections.clients, max_players);
+    }
+
+    for client in connections.clients.iter_mut() {
+        // Anything that didn't fit in the socket last frame
+        let _ = client.connection.flush();
+    }
+}
+
+// Once everyone still connected has submitted, hand their orders over to the
+// turn resolver
+pub fn dispatch_orders(
+    state: Res<State<ServerState>>,
+    match_state: Res<MatchState>,
+    connections: Res<ServerConnections>,
+    mut submitted_orders: ResMut<SubmittedOrders>,
+    mut pending_orders: ResMut<PendingOrders>,
+    mut resolve_evw: EventWriter<ResolveTurnEvent>,
+) {
+    if state.0 != ServerState::InGame || match_state.is_over() {
+        return;
+    }
+
+    let players = connections
+        .clients
+        .iter()
+        .filter(|c| !c.spectating)
+        .filter_map(|c| c.team.as_ref())
+        .collect::<Vec<&PlayerTeam>>();
+    if players.is_empty()
+        || !players
+            .iter()
+            .all(|team| submitted_orders.orders.contains_key(*team))
+    {
+        return;
+    }
+
+    let orders = submitted_orders.orders.drain().collect::<Vec<_>>();
+    submitted_orders.dispatched.clear();
+    for (team, (actions, recruits)) in orders {
+        pending_orders.0.actions.extend(actions.iter().cloned());
+        pending_orders
+            .0
+            .recruits
+            .extend(recruits.into_iter().map(|unit| RecruitOrder {
+                owner: team.clone(),
+                unit,
+            }));
+        submitted_orders.dispatched.insert(team, actions);
+    }
+    resolve_evw.send(ResolveTurnEvent);
+}
+
+// Each player only gets what they can see of the turn
+pub fn broadcast_turn_results(
+    mut resolved_evr: EventReader<TurnResolvedEvent>,
+    mut connections: ResMut<ServerConnections>,
+    submitted_orders: Res<SubmittedOrders>,
+    economy: Res<Economy>,
+    match_state: Res<MatchState>,
+    gameboard_q: Query<&Gameboard>,
+    units: Query<&Unit>,
+) {
+    let Ok(gameboard) = gameboard_q.get_single() else {
+        return;
+    };
+
+    // Dead units are despawned with commands, so they're still here until the
+    // end of the frame. visible_units skips them
+    let units = units.iter().cloned().collect::<Vec<Unit>>();
+    for ev in resolved_evr.iter() {
+        for client in connections.clients.iter_mut() {
+            let Some(team) = client.team.clone() else {
+                continue;
+            };
+            if match_state.is_eliminated(&team) && !client.spectating {
+                info!("{:?} has been knocked out and is now spectating", team.0);
+                client.spectating = true;
+            }
+            let viewer = Viewer {
+                team: &team,
+                spectating: client.spectating,
+            };
+
+            let orders = submitted_orders
+                .dispatched
+                .get(&team)
+                .map_or(&[][..], |o| &o[..]);
+            let message = ServerMessage::TurnResult {
+                result: result_for(&viewer, gameboard, &ev.0, orders),
+                delta: delta_for(
+                    &viewer,
+                    &mut client.known_tiles,
+                    gameboard,
+                    &economy,
+                    &units,
+                ),
+            };
+            client.send(&message);
+        }
+    }
+}
+
+fn validate_orders(
+    team: &PlayerTeam,
+    actions: &[UnitAction],
+    units: &Query<&Unit>,
+) -> Result<(), ProtocolError> {
+    for action in actions {
+        // Don't say whose unit it is, or whether there's one there at all,
+        // otherwise orders could be used to find units hidden by the fog
+        if !units
+            .iter()
+            .any(|u| u.pos == action.curr_pos && u.owner == *team)
+        {
+            return Err(ProtocolError::InvalidOrder(format!(
+                "you have no unit at {}",
+                action.curr_pos
+            )));
+        }
+    }
+
+    // One order per unit, otherwise the last one would silently win
+    for (i, action) in actions.iter().enumerate() {
+        if actions[..i].iter().any(|a| a.curr_pos == action.curr_pos) {
+            return Err(ProtocolError::InvalidOrder(format!(
+                "more than one order for the unit at {}",
+                action.curr_pos
+            )));
+        }
+    }
+
+    return Ok(());
+}